@@ -0,0 +1,76 @@
+// Print Markdown files in the terminal, with the same colours as the desktop app
+// Usage: dme-cat [--theme catppuccin_mocha] notes.md [other.md ...]
+// Or check the contrast of the colours of a theme: dme-cat [--theme <name>] --audit
+
+use std::{path::Path, process::ExitCode};
+
+use dme_core::{
+    markdown_file_to_ansi_with_theme,
+    theming::{
+        catalog::{ThemeCatalog, DEFAULT_THEME_NAME},
+        contrast::{ContrastLevel, ThemeAudit},
+        helix::ALL_HIGHLIGHT_NAMES_SUPPORTED_BY_HELIX,
+    },
+};
+
+fn main() -> ExitCode {
+    let usage =
+        "Usage: dme-cat [--theme <name>] <file.md>...\n       dme-cat [--theme <name>] --audit";
+    let mut args = std::env::args().skip(1);
+    let mut paths = Vec::new();
+    let mut theme = DEFAULT_THEME_NAME.to_string();
+    let mut is_audit = false;
+    // The options can be given anywhere, like `dme-cat notes.md --theme catppuccin_mocha`
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--theme" => match args.next() {
+                Some(name) => theme = name,
+                None => {
+                    eprintln!("{usage}");
+                    return ExitCode::FAILURE;
+                }
+            },
+            "--audit" => is_audit = true,
+            _ => paths.push(arg),
+        }
+    }
+    if is_audit {
+        return audit(&theme);
+    }
+    if paths.is_empty() {
+        eprintln!("{usage}");
+        return ExitCode::FAILURE;
+    }
+    for path in paths {
+        // Via the catalog, a user theme can replace the default one
+        match markdown_file_to_ansi_with_theme(Path::new(&path), &theme) {
+            Ok(rendered) => print!("{rendered}"),
+            Err(e) => {
+                eprintln!("{path}: {e}");
+                return ExitCode::FAILURE;
+            }
+        }
+    }
+    ExitCode::SUCCESS
+}
+
+/// Print the colours of the theme below the AA level, and fail if there are some
+fn audit(theme: &str) -> ExitCode {
+    let loaded = ThemeCatalog::new()
+        .and_then(|catalog| catalog.load(theme, ALL_HIGHLIGHT_NAMES_SUPPORTED_BY_HELIX));
+    let theme = match loaded {
+        Ok(theme) => theme,
+        Err(e) => {
+            eprintln!("{e}");
+            return ExitCode::FAILURE;
+        }
+    };
+    let summary = ThemeAudit::audit(&theme, Some(ContrastLevel::Aa)).summary(ContrastLevel::Aa);
+    for line in &summary {
+        println!("{line}");
+    }
+    match summary.is_empty() {
+        true => ExitCode::SUCCESS,
+        false => ExitCode::FAILURE,
+    }
+}
@@ -6,14 +6,19 @@ pub mod theming;
 pub mod util;
 
 use preview::{
+    budget::PreviewBudget,
     comrak::{highlight_code_from_cached_highlighter, ComrakParser},
+    options::PreviewOptions,
     preview::{Html, Previewable},
+    terminal::TerminalRenderer,
     tree_sitter_grammars::TreeSitterGrammarsManager,
     tree_sitter_highlight::TreeSitterHighlighter,
 };
 use theming::{
+    ansi::ColorMode,
+    catalog::ThemeCatalog,
     helix::ALL_HIGHLIGHT_NAMES_SUPPORTED_BY_HELIX,
-    renderer::Renderer,
+    renderer::{ColorSchemeSwitch, Renderer},
     theme::{Theme, DEFAULT_THEME},
 };
 
@@ -22,18 +27,120 @@ use theming::{
 pub fn markdown_content_to_highlighted_html(content: &str) -> Result<Html, String> {
     let theme = Theme::from_helix(DEFAULT_THEME, ALL_HIGHLIGHT_NAMES_SUPPORTED_BY_HELIX)
         .map_err(|e| e.to_string())?;
+    markdown_content_to_html_with(content, &theme, &PreviewOptions::default())
+}
+
+/// Same as `markdown_content_to_highlighted_html` with a theme of the `ThemeCatalog` given by
+/// its name, like "catppuccin_mocha" or the name of a user theme
+pub fn markdown_content_to_highlighted_html_with_theme(
+    content: &str,
+    theme_name: &str,
+) -> Result<Html, String> {
+    markdown_content_to_highlighted_html_with_options(
+        content,
+        theme_name,
+        &PreviewOptions::default(),
+    )
+}
+
+/// Same as `markdown_content_to_highlighted_html_with_theme` with the given Markdown extensions,
+/// the [preview] table of the front matter of the document can still override them
+pub fn markdown_content_to_highlighted_html_with_options(
+    content: &str,
+    theme_name: &str,
+    options: &PreviewOptions,
+) -> Result<Html, String> {
+    let theme = ThemeCatalog::new()?.load(theme_name, ALL_HIGHLIGHT_NAMES_SUPPORTED_BY_HELIX)?;
+    markdown_content_to_html_with(content, &theme, options)
+}
 
-    let parser = ComrakParser::new()?;
+/// Same as `markdown_content_to_highlighted_html` with a light and a dark themes of the
+/// `ThemeCatalog`, the dark one is used when the system prefers dark colours or when the
+/// `<html>` element has `data-theme="dark"`
+pub fn markdown_content_to_highlighted_html_with_theme_pair(
+    content: &str,
+    light_theme_name: &str,
+    dark_theme_name: &str,
+) -> Result<Html, String> {
+    let catalog = ThemeCatalog::new()?;
+    let light = catalog.load(light_theme_name, ALL_HIGHLIGHT_NAMES_SUPPORTED_BY_HELIX)?;
+    let dark = catalog.load(dark_theme_name, ALL_HIGHLIGHT_NAMES_SUPPORTED_BY_HELIX)?;
+    let (options, options_error) = PreviewOptions::default().with_front_matter(content);
+    let parser = ComrakParser::new()?.with_options(options);
     let mut html = parser.to_html(content);
-    html.push_style_from_theme(&theme);
+    if let Some(error) = options_error {
+        html.push_config_warning(error);
+    }
+    html.push_style_from_theme_pair(&light, &dark, ColorSchemeSwitch::Both);
+    Ok(html)
+}
+
+fn markdown_content_to_html_with(
+    content: &str,
+    theme: &Theme,
+    options: &PreviewOptions,
+) -> Result<Html, String> {
+    let (options, options_error) = options.clone().with_front_matter(content);
+    let parser = ComrakParser::new()?.with_options(options);
+    let mut html = parser.to_html(content);
+    if let Some(error) = options_error {
+        html.push_config_warning(error);
+    }
+    html.push_style_from_theme(theme);
     Ok(html)
 }
 
 /// Same as `markdown_content_to_highlighted_html` with a given path to a Markdown file
 pub fn markdown_file_to_highlighted_html(path: &Path) -> Result<Html, String> {
-    let content = read_to_string(path)
-        .map_err(|e| "Couldn't find given file: ".to_string() + &e.to_string())?;
-    markdown_content_to_highlighted_html(&content)
+    markdown_content_to_highlighted_html(&read_markdown_file(path)?)
+}
+
+/// Same as `markdown_content_to_highlighted_html_with_theme` with a given path to a Markdown file
+pub fn markdown_file_to_highlighted_html_with_theme(
+    path: &Path,
+    theme_name: &str,
+) -> Result<Html, String> {
+    markdown_content_to_highlighted_html_with_theme(&read_markdown_file(path)?, theme_name)
+}
+
+/// Same as `markdown_content_to_highlighted_html_with_options` with a given path to a Markdown file
+pub fn markdown_file_to_highlighted_html_with_options(
+    path: &Path,
+    theme_name: &str,
+    options: &PreviewOptions,
+) -> Result<Html, String> {
+    markdown_content_to_highlighted_html_with_options(
+        &read_markdown_file(path)?,
+        theme_name,
+        options,
+    )
+}
+
+/// Given a Markdown content, render it for a terminal with ANSI escape sequences, with code blocks
+/// highlighted with the default code theme. The colours are approximated to the 256 colours palette
+/// if the terminal doesn't announce 24-bit colours support via COLORTERM.
+pub fn markdown_content_to_ansi(content: &str) -> Result<String, String> {
+    let theme = Theme::from_helix(DEFAULT_THEME, ALL_HIGHLIGHT_NAMES_SUPPORTED_BY_HELIX)
+        .map_err(|e| e.to_string())?;
+    let manager = TreeSitterGrammarsManager::new()?;
+    Ok(TerminalRenderer::new(&manager, &theme, ColorMode::detect()).render(content))
+}
+
+/// Same as `markdown_content_to_ansi` with a given path to a Markdown file
+pub fn markdown_file_to_ansi(path: &Path) -> Result<String, String> {
+    markdown_content_to_ansi(&read_markdown_file(path)?)
+}
+
+/// Same as `markdown_file_to_ansi` with a theme of the `ThemeCatalog` given by its name
+pub fn markdown_file_to_ansi_with_theme(path: &Path, theme_name: &str) -> Result<String, String> {
+    let content = read_markdown_file(path)?;
+    let theme = ThemeCatalog::new()?.load(theme_name, ALL_HIGHLIGHT_NAMES_SUPPORTED_BY_HELIX)?;
+    let manager = TreeSitterGrammarsManager::new()?;
+    Ok(TerminalRenderer::new(&manager, &theme, ColorMode::detect()).render(&content))
+}
+
+fn read_markdown_file(path: &Path) -> Result<String, String> {
+    read_to_string(path).map_err(|e| "Couldn't find given file: ".to_string() + &e.to_string())
 }
 
 /// Try to detect the language via the file extension, this might returns some invalid languages
@@ -52,7 +159,12 @@ pub fn detect_lang_from_file_extension(path: &Path) -> String {
 /// `<span class='property'>color</span><span class='punctuation delimiter'>:</span>`
 pub fn highlight_code(lang: &str, code: &str) -> Result<Html, String> {
     let manager = TreeSitterGrammarsManager::new()?;
-    let final_html = highlight_code_from_cached_highlighter(&manager, Some(lang), code);
+    let final_html = highlight_code_from_cached_highlighter(
+        &manager,
+        Some(lang),
+        code,
+        &PreviewBudget::default(),
+    );
     Ok(final_html)
 }
 
@@ -105,8 +217,13 @@ $2+42$
             .map_err(|e| e.to_string())
             .unwrap();
 
+        // Only the CSS of the highlight names found in the CSS code block
         let renderer = Renderer::new(&theme);
-        let theme_css = renderer.css();
+        let theme_css = renderer.css_for_highlight_names(
+            &["property", "punctuation.bracket", "punctuation.delimiter"]
+                .map(String::from)
+                .into(),
+        );
         let html = markdown_content_to_highlighted_html(file)
             .unwrap()
             .to_safe_html_string();
@@ -117,8 +234,18 @@ $2+42$
 </code></pre>
 <p></p><figure><img src="test.svg" alt="a"></figure><p></p>
 <p></p><figure><img src="yep-here.svg" alt="something" title="with figure caption !"><figcaption>with figure caption !</figcaption></figure><p></p>
-<p><span class="math-inline"><svg xmlns="http://www.w3.org/2000/svg" xmlns:xlink="http://www.w3.org/1999/xlink" id="prefix0a" width="39.929" height="10.017" class="typst-frame" style="width:.99823em;height:.250433em;overflow:visible" viewBox="0 0 29.947 7.513"><g class="typst-group"><use xlink:href="#prefix0b" class="typst-text" transform="matrix(1 0 0 -1 0 7.513)"></use><use xlink:href="#prefix0c" class="typst-text" transform="matrix(1 0 0 -1 7.944 7.513)"></use><use xlink:href="#prefix0d" class="typst-text" transform="matrix(1 0 0 -1 18.947 7.513)"></use><use xlink:href="#prefix0b" class="typst-text" transform="matrix(1 0 0 -1 24.447 7.513)"></use></g><defs><symbol id="prefix0b" overflow="visible"><path d="M2.607 7.326a1.99 1.99 0 0 1-1.441-.594C.759 6.336.55 5.874.55 5.313c0-.374.275-.649.616-.649a.62.62 0 0 1 .605.616.595.595 0 0 1-.616.616c-.033 0-.055 0-.077-.011.209.539.693 1.012 1.386 1.012.902 0 1.408-.781 1.408-1.727 0-.737-.374-1.529-1.122-2.365L.682.473C.539.308.55.319.55 0h4.081l.319 1.98h-.363c-.088-.561-.165-.88-.231-.979-.055-.055-.385-.077-.99-.077H1.529l1.067 1.045c.748.704 1.694 1.463 2.013 2.046q.33.577.33 1.155c0 1.298-1.012 2.156-2.332 2.156"></path></symbol><symbol id="prefix0c" overflow="visible"><path d="M7.678 3.014H4.543v3.135q0 .264-.264.264t-.264-.264V3.014H.88q-.264 0-.264-.264t.264-.264h3.135V-.649q0-.264.264-.264t.264.264v3.135h3.135q.264 0 .264.264a.27.27 0 0 1-.264.264"></path></symbol><symbol id="prefix0d" overflow="visible"><path d="M3.883 7.447c-.099 0-.187-.055-.253-.154L.308 2.189v-.396h2.871V.891c0-.198-.044-.33-.121-.385S2.772.429 2.409.429h-.275V0c.319.022.825.033 1.507.033S4.829.022 5.148 0v.429h-.275c-.363 0-.572.022-.649.077s-.121.187-.121.385v.902h1.078v.429H4.103V7.26c0 .11-.077.187-.22.187m-.638-1.364V2.222H.737Z"></path></symbol></defs></svg></span></p>"##;
+<p><span class="math-inline"><svg xmlns="http://www.w3.org/2000/svg" xmlns:xlink="http://www.w3.org/1999/xlink" id="prefix0a" width="39.929" height="10.017" class="typst-frame" style="width:.99823em;height:.250433em;overflow:visible" viewBox="0 0 29.947 7.513"><g class="typst-group"><use xlink:href="#prefix0b" transform="matrix(1 0 0 -1 0 7.513)" class="typst-text"></use><use xlink:href="#prefix0c" transform="matrix(1 0 0 -1 7.944 7.513)" class="typst-text"></use><use xlink:href="#prefix0d" transform="matrix(1 0 0 -1 18.947 7.513)" class="typst-text"></use><use xlink:href="#prefix0b" transform="matrix(1 0 0 -1 24.447 7.513)" class="typst-text"></use></g><defs><symbol id="prefix0b" overflow="visible"><path d="M2.607 7.326a1.99 1.99 0 0 1-1.441-.594C.759 6.336.55 5.874.55 5.313c0-.374.275-.649.616-.649a.62.62 0 0 1 .605.616.595.595 0 0 1-.616.616c-.033 0-.055 0-.077-.011.209.539.693 1.012 1.386 1.012.902 0 1.408-.781 1.408-1.727 0-.737-.374-1.529-1.122-2.365L.682.473C.539.308.55.319.55 0h4.081l.319 1.98h-.363c-.088-.561-.165-.88-.231-.979-.055-.055-.385-.077-.99-.077H1.529l1.067 1.045c.748.704 1.694 1.463 2.013 2.046q.33.577.33 1.155c0 1.298-1.012 2.156-2.332 2.156"></path></symbol><symbol id="prefix0c" overflow="visible"><path d="M7.678 3.014H4.543v3.135q0 .264-.264.264t-.264-.264V3.014H.88q-.264 0-.264-.264t.264-.264h3.135V-.649q0-.264.264-.264t.264.264v3.135h3.135q.264 0 .264.264a.27.27 0 0 1-.264.264"></path></symbol><symbol id="prefix0d" overflow="visible"><path d="M3.883 7.447c-.099 0-.187-.055-.253-.154L.308 2.189v-.396h2.871V.891c0-.198-.044-.33-.121-.385S2.772.429 2.409.429h-.275V0c.319.022.825.033 1.507.033S4.829.022 5.148 0v.429h-.275c-.363 0-.572.022-.649.077s-.121.187-.121.385v.902h1.078v.429H4.103V7.26c0 .11-.077.187-.22.187m-.638-1.364V2.222H.737Z"></path></symbol></defs></svg></span></p>"##;
         let expected_with_theme = format!("<style>{theme_css}</style>{expected}\n");
         assert_eq!(html, expected_with_theme);
     }
+
+    #[test]
+    fn test_invalid_front_matter_options_are_reported_without_failing() {
+        let html = markdown_content_to_highlighted_html("---\npreview = 1\n---\n~~a~~\n").unwrap();
+        assert_eq!(
+            html.get_config_warnings(),
+            ["Invalid preview options in front matter, they are ignored: [preview] must be a table"]
+        );
+        assert!(html.to_safe_html_string().ends_with("<p>~~a~~</p>\n"));
+    }
 }
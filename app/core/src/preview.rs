@@ -1,7 +1,25 @@
+pub mod alerts;
+pub mod budget;
+#[cfg(feature = "builtin-grammars")]
+pub mod builtin_grammars;
 pub mod comrak;
+pub mod diff;
+pub mod equations;
+pub mod grammar_bundle;
+pub mod grammar_doctor;
+pub mod grammar_progress;
+pub mod grammar_sources;
+pub mod grammar_trust;
+pub mod grammar_update;
+pub mod highlight_diagnostics;
+pub mod highlighter_cache;
+pub mod latex;
 pub mod math;
+pub mod options;
 pub mod preview;
 pub mod proposed_grammars;
+pub mod query_overrides;
+pub mod terminal;
 pub mod toc;
 pub mod tree_sitter_grammars;
 pub mod tree_sitter_highlight;
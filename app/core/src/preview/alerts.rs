@@ -0,0 +1,180 @@
+// GitHub alerts like `> [!NOTE]`, parsed by Comrak and rendered as a container with a title
+// and an icon. The title can be replaced, and a `-` or `+` after the type makes the alert
+// collapsible, closed or open by default:
+//
+// > [!TIP]- Keyboard shortcuts
+// > Hidden until the title is clicked
+
+use std::collections::BTreeSet;
+
+use comrak::{
+    html::escape,
+    nodes::{AstNode, NodeHtmlBlock, NodeValue},
+    Arena,
+};
+
+// The style of the alerts belongs to the theming, it is still available from here
+pub use crate::theming::alerts::{ALERTS_CSS, ALERT_COLORS};
+
+/// The classes of the containers, the only ones allowed on `<div>` and `<details>`
+pub const ALERT_CLASSES: [&str; 6] = [
+    "markdown-alert",
+    "markdown-alert-note",
+    "markdown-alert-tip",
+    "markdown-alert-important",
+    "markdown-alert-warning",
+    "markdown-alert-caution",
+];
+
+/// Replace the alerts nodes by their HTML container around their content, and return the
+/// types of the alerts found, like "note"
+pub(crate) fn render_alerts<'a>(arena: &'a Arena<'a>, root: &'a AstNode<'a>) -> BTreeSet<String> {
+    let alerts: Vec<&AstNode> = root
+        .descendants()
+        .filter(|node| matches!(node.data.borrow().value, NodeValue::Alert(_)))
+        .collect();
+    let mut alert_types = BTreeSet::new();
+    for node in alerts {
+        let (opening, closing) = match &node.data.borrow().value {
+            NodeValue::Alert(alert) => {
+                let title = alert.alert_type.default_title();
+                let alert_type = title.to_lowercase();
+                let html = alert_html(&alert_type, title, alert.title.as_deref());
+                alert_types.insert(alert_type);
+                html
+            }
+            _ => continue,
+        };
+        let html_block = |literal: String| {
+            arena.alloc(
+                NodeValue::HtmlBlock(NodeHtmlBlock {
+                    block_type: 0,
+                    literal,
+                })
+                .into(),
+            )
+        };
+        node.insert_before(html_block(opening));
+        let children: Vec<&AstNode> = node.children().collect();
+        for child in children {
+            node.insert_before(child);
+        }
+        node.insert_before(html_block(closing));
+        node.detach();
+    }
+    alert_types
+}
+
+/// The opening and closing HTML of an alert. The custom title given by Comrak is everything
+/// after the type, starting with the `-` or `+` of the collapsible alerts.
+fn alert_html(
+    alert_type: &str,
+    default_title: &str,
+    custom_title: Option<&str>,
+) -> (String, String) {
+    let custom_title = custom_title.unwrap_or_default();
+    let (collapsible, title) = match custom_title.chars().next() {
+        Some('-') => (Some(false), &custom_title[1..]),
+        Some('+') => (Some(true), &custom_title[1..]),
+        _ => (None, custom_title),
+    };
+    let title = match title.trim() {
+        "" => default_title,
+        title => title,
+    };
+    let mut escaped_title = String::default();
+    let _ = escape(&mut escaped_title, title);
+
+    let classes = format!("markdown-alert markdown-alert-{alert_type}");
+    let icon = "<span class=\"markdown-alert-icon\"></span>";
+    match collapsible {
+        Some(open) => {
+            let open = if open { " open" } else { "" };
+            (
+                format!("<details class=\"{classes}\"{open}>\n<summary class=\"markdown-alert-title\">{icon}{escaped_title}</summary>\n"),
+                "</details>\n".to_string(),
+            )
+        }
+        None => (
+            format!("<div class=\"{classes}\">\n<p class=\"markdown-alert-title\">{icon}{escaped_title}</p>\n"),
+            "</div>\n".to_string(),
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::alert_html;
+    use crate::preview::{comrak::ComrakParser, preview::Previewable};
+
+    #[test]
+    fn test_alerts_are_rendered_with_title_and_icon() {
+        let given = "> [!NOTE]\n> Read *this*\n\n> [!WARNING] Careful <now>\n> Hot\n\n> [!TIP]- More\n> Hidden\n\n> [!CAUTION]+\n> Shown\n\n> Just a quote\n";
+        let html = ComrakParser::new().unwrap().to_html(given);
+        assert_eq!(
+            html.to_safe_html_string(),
+            r#"<div class="markdown-alert markdown-alert-note">
+<p class="markdown-alert-title"><span class="markdown-alert-icon"></span>Note</p>
+<p>Read <em>this</em></p>
+</div>
+<div class="markdown-alert markdown-alert-warning">
+<p class="markdown-alert-title"><span class="markdown-alert-icon"></span>Careful &lt;now&gt;</p>
+<p>Hot</p>
+</div>
+<details class="markdown-alert markdown-alert-tip">
+<summary class="markdown-alert-title"><span class="markdown-alert-icon"></span>More</summary>
+<p>Hidden</p>
+</details>
+<details class="markdown-alert markdown-alert-caution" open="">
+<summary class="markdown-alert-title"><span class="markdown-alert-icon"></span>Caution</summary>
+<p>Shown</p>
+</details>
+<blockquote>
+<p>Just a quote</p>
+</blockquote>
+"#
+        );
+        let types: Vec<&str> = html.get_alert_types().iter().map(String::as_str).collect();
+        assert_eq!(types, vec!["caution", "note", "tip", "warning"]);
+
+        // Only the classes of the alerts are kept
+        let html = ComrakParser::new()
+            .unwrap()
+            .to_html("<div class=\"markdown-alert evil\"><p class=\"x\">a</p></div>");
+        assert_eq!(
+            html.to_safe_html_string(),
+            "<div class=\"markdown-alert\"><p class=\"x\">a</p></div>\n"
+        );
+    }
+
+    #[test]
+    fn test_collapsible_alerts_and_custom_titles() {
+        let opening = |custom_title| alert_html("tip", "Tip", custom_title).0;
+        let tip = "class=\"markdown-alert markdown-alert-tip\"";
+        let icon = "<span class=\"markdown-alert-icon\"></span>";
+        // `-` is closed and `+` is open by default, both keep their custom title
+        assert_eq!(
+            opening(Some("- Keyboard shortcuts")),
+            format!("<details {tip}>\n<summary class=\"markdown-alert-title\">{icon}Keyboard shortcuts</summary>\n")
+        );
+        assert_eq!(
+            opening(Some("+ <b>Open</b>")),
+            format!("<details {tip} open>\n<summary class=\"markdown-alert-title\">{icon}&lt;b&gt;Open&lt;/b&gt;</summary>\n")
+        );
+        assert_eq!(
+            opening(Some("-")),
+            format!(
+                "<details {tip}>\n<summary class=\"markdown-alert-title\">{icon}Tip</summary>\n"
+            )
+        );
+        // A custom title without `-` or `+` is not collapsible, a blank one is the default one
+        assert_eq!(
+            opening(Some(" A -dash")),
+            format!("<div {tip}>\n<p class=\"markdown-alert-title\">{icon}A -dash</p>\n")
+        );
+        assert_eq!(opening(Some("  ")), opening(None));
+        assert_eq!(alert_html("tip", "Tip", Some("+")).1, "</details>\n");
+    }
+}
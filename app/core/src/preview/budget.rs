@@ -0,0 +1,303 @@
+// Limits applied while rendering a document, so a crafted Markdown file with a huge or
+// pathological code block or math expression cannot freeze the preview.
+// When a limit is reached, the block is rendered as escaped text and a warning is reported.
+
+use std::{
+    fmt,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        mpsc, Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use serde::Serialize;
+
+use super::highlight_diagnostics::HighlightError;
+
+/// The limits of a single rendering, the defaults are large enough to never be reached by
+/// a normal document
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PreviewBudget {
+    /// Maximum time to parse and highlight a single code block
+    pub highlight_timeout: Duration,
+    /// Maximum time to highlight all the code blocks of a document, the next ones are not
+    /// highlighted
+    pub max_document_highlight_time: Duration,
+    /// Code blocks bigger than this are not highlighted
+    pub max_code_block_bytes: usize,
+    /// Maximum time to render a single math expression
+    pub math_timeout: Duration,
+    /// Math expressions after this count are not rendered
+    pub max_math_expressions: usize,
+    /// The final HTML is truncated after this size
+    pub max_output_bytes: usize,
+}
+
+impl Default for PreviewBudget {
+    fn default() -> Self {
+        PreviewBudget {
+            highlight_timeout: Duration::from_secs(2),
+            max_document_highlight_time: Duration::from_secs(10),
+            max_code_block_bytes: 1024 * 1024,
+            math_timeout: Duration::from_secs(5),
+            max_math_expressions: 2000,
+            max_output_bytes: 50 * 1024 * 1024,
+        }
+    }
+}
+
+/// A limit of the budget reached during a rendering, code blocks have their own diagnostics
+#[derive(Debug, Serialize, Clone, PartialEq, Eq)]
+#[serde(tag = "kind", rename_all = "kebab-case")]
+pub enum BudgetWarning {
+    /// This math expression took too long, the next ones have been skipped
+    MathTimeout {
+        expression: String,
+        skipped: usize,
+    },
+    /// An expression of a previous rendering that took too long is still running, all the
+    /// expressions have been skipped
+    MathStillBusy {
+        skipped: usize,
+    },
+    TooManyMathExpressions {
+        limit: usize,
+        skipped: usize,
+    },
+    OutputTooLarge {
+        size: usize,
+        limit: usize,
+    },
+}
+
+impl fmt::Display for BudgetWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BudgetWarning::MathTimeout {
+                expression,
+                skipped,
+            } => write!(
+                f,
+                "The math expression `{expression}` took too long to render, {skipped} other expressions have been skipped"
+            ),
+            BudgetWarning::MathStillBusy { skipped } => write!(
+                f,
+                "A previous math expression is still rendering, {skipped} expressions have been skipped"
+            ),
+            BudgetWarning::TooManyMathExpressions { limit, skipped } => write!(
+                f,
+                "Only the first {limit} math expressions have been rendered, {skipped} have been skipped"
+            ),
+            BudgetWarning::OutputTooLarge { size, limit } => write!(
+                f,
+                "The document has been truncated as its HTML of {size} bytes is over the limit of {limit} bytes"
+            ),
+        }
+    }
+}
+
+/// Run `task` with a cancellation flag set to a non zero value once `timeout` is reached.
+/// The task must check the flag regularly, like the Tree-Sitter highlighter does.
+pub(crate) fn run_with_cancellation_deadline<T>(
+    timeout: Duration,
+    task: impl FnOnce(&AtomicUsize) -> T,
+) -> T {
+    let flag = AtomicUsize::new(0);
+    let (done_sender, done_receiver) = mpsc::channel::<()>();
+    let flag_ref = &flag;
+    thread::scope(|scope| {
+        scope.spawn(move || {
+            // Nothing received means the task is still running
+            if let Err(mpsc::RecvTimeoutError::Timeout) = done_receiver.recv_timeout(timeout) {
+                flag_ref.store(1, Ordering::SeqCst);
+            }
+        });
+        let result = task(flag_ref);
+        let _ = done_sender.send(());
+        result
+    })
+}
+
+/// The highlight time left for all the code blocks of a document. Each block is also limited
+/// by the `highlight_timeout` of the budget.
+pub struct HighlightDeadline {
+    block_timeout: Duration,
+    left: Mutex<Duration>,
+    /// The error once no time is left
+    exhausted: HighlightError,
+}
+
+impl HighlightDeadline {
+    pub fn new(budget: &PreviewBudget) -> Self {
+        HighlightDeadline {
+            block_timeout: budget.highlight_timeout,
+            left: Mutex::new(budget.max_document_highlight_time),
+            exhausted: HighlightError::DocumentHighlightTimeout,
+        }
+    }
+
+    /// Run `task` with the deadline of a single code block highlighted in several parts, like
+    /// the versions of a diff. The parts share the block timeout and the time left for the document.
+    pub(crate) fn run_block<T>(&self, task: impl FnOnce(&HighlightDeadline) -> T) -> T {
+        let left = *self.left.lock().unwrap_or_else(|e| e.into_inner());
+        let block = HighlightDeadline {
+            block_timeout: self.block_timeout,
+            left: Mutex::new(self.block_timeout.min(left)),
+            exhausted: match self.block_timeout < left {
+                true => HighlightError::ParseTimeout,
+                false => HighlightError::DocumentHighlightTimeout,
+            },
+        };
+        let start = Instant::now();
+        let result = task(&block);
+        let mut left = self.left.lock().unwrap_or_else(|e| e.into_inner());
+        *left = left.saturating_sub(start.elapsed());
+        result
+    }
+
+    /// Highlight a code block with `task`, like `run_with_cancellation_deadline()`, and remove
+    /// the time taken from the time left for the document
+    pub(crate) fn run<T>(
+        &self,
+        task: impl FnOnce(&AtomicUsize) -> Result<T, HighlightError>,
+    ) -> Result<T, HighlightError> {
+        let left = *self.left.lock().unwrap_or_else(|e| e.into_inner());
+        if left.is_zero() {
+            return Err(self.exhausted.clone());
+        }
+        let start = Instant::now();
+        let result = run_with_cancellation_deadline(self.block_timeout.min(left), task);
+        let mut left = self.left.lock().unwrap_or_else(|e| e.into_inner());
+        *left = left.saturating_sub(start.elapsed());
+        result
+    }
+}
+
+/// Why a task of a `DeadlineWorker` has no result
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum DeadlineError {
+    /// The task took too long, it keeps running in the background
+    TimedOut,
+    /// A previous task that timed out is still running, this one has not been started
+    StillBusy,
+}
+
+type Job = Box<dyn FnOnce() + Send>;
+
+/// A single background thread running tasks that cannot be stopped, like Typst renderings,
+/// and giving up waiting for them after a timeout. While a timed out task is still running, the
+/// next tasks are refused instead of piling up behind it.
+pub(crate) struct DeadlineWorker {
+    jobs: Mutex<mpsc::Sender<Job>>,
+    busy: Arc<AtomicBool>,
+}
+
+impl DeadlineWorker {
+    pub(crate) fn new() -> Self {
+        let (sender, receiver) = mpsc::channel::<Job>();
+        thread::spawn(move || {
+            for job in receiver {
+                job();
+            }
+        });
+        DeadlineWorker {
+            jobs: Mutex::new(sender),
+            busy: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// Run `task` on the worker thread and wait for its result at most `timeout`
+    pub(crate) fn run<T: Send + 'static>(
+        &self,
+        timeout: Duration,
+        task: impl FnOnce() -> T + Send + 'static,
+    ) -> Result<T, DeadlineError> {
+        // Only one task is waited at a time, the lock is held at most `timeout`
+        let jobs = self.jobs.lock().unwrap_or_else(|e| e.into_inner());
+        if self.busy.swap(true, Ordering::SeqCst) {
+            return Err(DeadlineError::StillBusy);
+        }
+        let (result_sender, result_receiver) = mpsc::channel();
+        let busy = self.busy.clone();
+        let job = Box::new(move || {
+            let result = task();
+            busy.store(false, Ordering::SeqCst);
+            let _ = result_sender.send(result);
+        });
+        if jobs.send(job).is_err() {
+            self.busy.store(false, Ordering::SeqCst);
+            return Err(DeadlineError::StillBusy);
+        }
+        result_receiver
+            .recv_timeout(timeout)
+            .map_err(|_| DeadlineError::TimedOut)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::atomic::Ordering,
+        time::{Duration, Instant},
+    };
+
+    use pretty_assertions::assert_eq;
+
+    use super::{
+        run_with_cancellation_deadline, DeadlineError, DeadlineWorker, HighlightDeadline,
+        PreviewBudget,
+    };
+    use crate::preview::highlight_diagnostics::HighlightError;
+
+    #[test]
+    fn test_deadlines_stop_waiting_for_slow_tasks() {
+        let start = Instant::now();
+        let cancelled = run_with_cancellation_deadline(Duration::from_millis(20), |flag| {
+            while flag.load(Ordering::SeqCst) == 0 {
+                std::thread::sleep(Duration::from_millis(1));
+            }
+            true
+        });
+        assert!(cancelled);
+        assert_eq!(
+            run_with_cancellation_deadline(Duration::from_secs(10), |_| 42),
+            42
+        );
+
+        let worker = DeadlineWorker::new();
+        let never = worker.run(Duration::from_millis(20), || {
+            std::thread::sleep(Duration::from_millis(300));
+        });
+        assert_eq!(never, Err(DeadlineError::TimedOut));
+        // The slow task is still running, the next ones are refused without waiting
+        assert_eq!(
+            worker.run(Duration::from_secs(10), || 42),
+            Err(DeadlineError::StillBusy)
+        );
+        std::thread::sleep(Duration::from_millis(400));
+        assert_eq!(worker.run(Duration::from_secs(10), || 42), Ok(42));
+        assert!(start.elapsed() < Duration::from_secs(2));
+    }
+
+    #[test]
+    fn test_document_highlight_time_is_shared_by_its_blocks() {
+        let deadline = HighlightDeadline::new(&PreviewBudget {
+            highlight_timeout: Duration::from_secs(10),
+            max_document_highlight_time: Duration::from_millis(30),
+            ..PreviewBudget::default()
+        });
+        let slow = deadline.run(|flag| {
+            while flag.load(Ordering::SeqCst) == 0 {
+                std::thread::sleep(Duration::from_millis(1));
+            }
+            Err::<(), _>(HighlightError::ParseTimeout)
+        });
+        assert_eq!(slow, Err(HighlightError::ParseTimeout));
+        assert_eq!(
+            deadline.run(|_| Ok(42)),
+            Err(HighlightError::DocumentHighlightTimeout)
+        );
+    }
+}
@@ -0,0 +1,153 @@
+// A core set of grammars statically linked in the crate with the `builtin-grammars` feature
+// They are published on crates.io by the grammars authors, with their parser already compiled
+// by the build script of each crate and their queries files included as strings
+
+use tree_sitter::Language;
+
+/// The languages available without installing any grammar
+pub const BUILTIN_GRAMMARS: &[&str] = &[
+    "rust",
+    "c",
+    "cpp",
+    "python",
+    "javascript",
+    "bash",
+    "json",
+    "toml",
+    "css",
+    "html",
+];
+
+/// A grammar compiled inside the binary, with its highlighting queries
+pub struct BuiltinGrammar {
+    pub language: Language,
+    pub highlights_query: String,
+    pub injections_query: &'static str,
+    pub locals_query: &'static str,
+}
+
+/// Get the built-in grammar of the given normalized lang, if it is part of BUILTIN_GRAMMARS
+pub fn builtin_grammar(lang: &str) -> Option<BuiltinGrammar> {
+    let (language, highlights_query, injections_query, locals_query) = match lang {
+        "rust" => (
+            tree_sitter_rust::LANGUAGE,
+            tree_sitter_rust::HIGHLIGHTS_QUERY.to_string(),
+            tree_sitter_rust::INJECTIONS_QUERY,
+            "",
+        ),
+        "c" => (
+            tree_sitter_c::LANGUAGE,
+            tree_sitter_c::HIGHLIGHT_QUERY.to_string(),
+            "",
+            "",
+        ),
+        // The C++ queries only contain the additions to C, like in its tree-sitter.json
+        "cpp" => (
+            tree_sitter_cpp::LANGUAGE,
+            format!(
+                "{}\n{}",
+                tree_sitter_c::HIGHLIGHT_QUERY,
+                tree_sitter_cpp::HIGHLIGHT_QUERY
+            ),
+            "",
+            "",
+        ),
+        "python" => (
+            tree_sitter_python::LANGUAGE,
+            tree_sitter_python::HIGHLIGHTS_QUERY.to_string(),
+            "",
+            "",
+        ),
+        "javascript" => (
+            tree_sitter_javascript::LANGUAGE,
+            format!(
+                "{}\n{}",
+                tree_sitter_javascript::HIGHLIGHT_QUERY,
+                tree_sitter_javascript::JSX_HIGHLIGHT_QUERY
+            ),
+            tree_sitter_javascript::INJECTIONS_QUERY,
+            tree_sitter_javascript::LOCALS_QUERY,
+        ),
+        "bash" => (
+            tree_sitter_bash::LANGUAGE,
+            tree_sitter_bash::HIGHLIGHT_QUERY.to_string(),
+            "",
+            "",
+        ),
+        "json" => (
+            tree_sitter_json::LANGUAGE,
+            tree_sitter_json::HIGHLIGHTS_QUERY.to_string(),
+            "",
+            "",
+        ),
+        "toml" => (
+            tree_sitter_toml_ng::LANGUAGE,
+            tree_sitter_toml_ng::HIGHLIGHTS_QUERY.to_string(),
+            "",
+            "",
+        ),
+        "css" => (
+            tree_sitter_css::LANGUAGE,
+            tree_sitter_css::HIGHLIGHTS_QUERY.to_string(),
+            "",
+            "",
+        ),
+        "html" => (
+            tree_sitter_html::LANGUAGE,
+            tree_sitter_html::HIGHLIGHTS_QUERY.to_string(),
+            tree_sitter_html::INJECTIONS_QUERY,
+            "",
+        ),
+        _ => return None,
+    };
+    Some(BuiltinGrammar {
+        language: Language::new(language),
+        highlights_query,
+        injections_query,
+        locals_query,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::{builtin_grammar, BUILTIN_GRAMMARS};
+    use crate::preview::{
+        tree_sitter_grammars::{
+            get_unique_local_tree_sitter_grammars_folder, TreeSitterGrammarsManager,
+        },
+        tree_sitter_highlight::TreeSitterHighlighter,
+    };
+
+    #[test]
+    fn test_all_builtin_grammars_can_highlight_without_installation() {
+        let m = TreeSitterGrammarsManager::new_with_grammars_folder(
+            get_unique_local_tree_sitter_grammars_folder(),
+        )
+        .unwrap();
+        let snippets = [
+            ("rust", "fn main() {}"),
+            ("c", "int main() { return 0; }"),
+            ("cpp", "class A { public: int a; };"),
+            ("python", "def hello(): pass"),
+            ("javascript", "const a = () => 2;"),
+            ("bash", "echo \"hello\""),
+            ("json", "{\"a\": 2}"),
+            ("toml", "a = 2"),
+            ("css", "a { color: red; }"),
+            ("html", "<div class=\"a\"></div>"),
+        ];
+        assert_eq!(snippets.len(), BUILTIN_GRAMMARS.len());
+        for (lang, snippet) in snippets {
+            assert!(BUILTIN_GRAMMARS.contains(&lang));
+            let h = TreeSitterHighlighter::new(lang, &m).unwrap();
+            let html = h.highlight(snippet).to_safe_html_string();
+            assert!(
+                html.contains("<span class="),
+                "{lang} was not highlighted: {html}"
+            );
+        }
+        assert!(builtin_grammar("zonk").is_none());
+    }
+}
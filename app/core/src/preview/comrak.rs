@@ -1,24 +1,32 @@
 use crate::preview::math::MathRenderer;
 
 // Previewable implementation via a Comrak based Markdown parser
+use super::alerts::render_alerts;
+use super::budget::{
+    BudgetWarning, DeadlineError, DeadlineWorker, HighlightDeadline, PreviewBudget,
+};
+use super::diff::{diff_block_lang, highlight_diff};
+use super::equations::EquationNumbers;
+use super::highlight_diagnostics::{HighlightDiagnostic, HighlightError};
+use super::highlighter_cache::HIGHLIGHTER_CACHE;
+use super::latex::looks_like_latex;
+use super::options::{MathLanguage, MathSyntax, PreviewOptions};
 use super::preview::{Html, Previewable};
 use super::tree_sitter_grammars::TreeSitterGrammarsManager;
-use super::tree_sitter_highlight::TreeSitterHighlighter;
-use comrak::html::escape;
-use comrak::nodes::NodeValue;
+use comrak::html::{
+    escape, format_document_with_formatter, format_node_default, ChildRendering, Context,
+};
+use comrak::nodes::{AstNode, NodeHtmlBlock, NodeValue};
 use comrak::options::Plugins;
 use comrak::{adapters::SyntaxHighlighterAdapter, html};
-use comrak::{format_html_with_plugins, parse_document, Arena, Options};
+use comrak::{parse_document, Arena};
 use core::fmt;
 use once_cell::sync::Lazy;
 use std::borrow::Cow;
-use std::collections::HashMap;
+use std::cell::Cell;
+use std::collections::{BTreeSet, HashMap};
 use std::path::PathBuf;
-use std::sync::RwLock;
-
-// Global TreeSitterHighlighter cache indexed by language
-static TSH_CACHE: Lazy<RwLock<HashMap<String, TreeSitterHighlighter>>> =
-    Lazy::new(|| RwLock::new(HashMap::new()));
+use std::sync::Mutex;
 
 static TREE_SITTER_GRAMMARS_FOLDER_VIA_ENV: Lazy<Option<String>> =
     Lazy::new(|| std::env::var("TREE_SITTER_GRAMMARS_FOLDER").ok());
@@ -26,15 +34,24 @@ static TREE_SITTER_GRAMMARS_FOLDER_VIA_ENV: Lazy<Option<String>> =
 // Global MathRenderer to avoid recreating a typst world all the time
 // and using an internal prefix id counter globally unique
 static MATH_RENDERER: Lazy<MathRenderer> = Lazy::new(MathRenderer::init);
+// The single thread rendering math, so an expression that took too long cannot pile up the
+// next ones waiting on the same Typst world
+static MATH_WORKER: Lazy<DeadlineWorker> = Lazy::new(DeadlineWorker::new);
 
 /// A prefix for security purpose, to avoid being able to create arbitrary ID in the DOM from the Markdown headings.
 /// This is a way to make it safe if some JavaScript code is relying on the id attribute of something outside of the article.
 pub const HEADER_IDS_SECURITY_PREFIX: &str = "h-";
+/// The IDs of the footnotes and of their references, generated by Comrak
+pub const FOOTNOTE_IDS_PREFIXES: [&str; 2] = ["fn-", "fnref-"];
 
 pub const FRONT_MATTER_DELIMITER: &str = "---";
 
 pub struct ComrakParser {
     manager: TreeSitterGrammarsManager,
+    /// The limits applied on each rendering, see `budget.rs`
+    budget: PreviewBudget,
+    /// The Markdown extensions, see `options.rs`
+    options: PreviewOptions,
 }
 
 impl ComrakParser {
@@ -48,7 +65,11 @@ impl ComrakParser {
             None => TreeSitterGrammarsManager::new(),
         }?;
 
-        Ok(ComrakParser { manager })
+        Ok(ComrakParser {
+            manager,
+            budget: PreviewBudget::default(),
+            options: PreviewOptions::default(),
+        })
     }
 
     /// A ComrakParser parser but with a different grammars folder than default
@@ -57,26 +78,40 @@ impl ComrakParser {
     pub(crate) fn new_with_configurable_grammars_folder(folder: String) -> Result<Self, String> {
         let manager =
             TreeSitterGrammarsManager::new_with_grammars_folder(PathBuf::from(folder.clone()))?;
-        Ok(ComrakParser { manager })
+        Ok(ComrakParser {
+            manager,
+            budget: PreviewBudget::default(),
+            options: PreviewOptions::default(),
+        })
+    }
+
+    /// Replace the default limits applied on each rendering
+    pub fn with_budget(mut self, budget: PreviewBudget) -> Self {
+        self.budget = budget;
+        self
+    }
+
+    /// Replace the default Markdown extensions
+    pub fn with_options(mut self, options: PreviewOptions) -> Self {
+        self.options = options;
+        self
     }
 }
 
 impl Previewable for ComrakParser {
     fn to_html(&self, source: &str) -> Html {
         // Configuring Comrak options and plugins before running them
-        let mut options = Options::default();
-        options.extension.table = true; // Enable tables
-        options.extension.tasklist = true; // Enable list of tasks
-        options.extension.autolink = true; // Enable creating links automatically for URLs in text
-        options.extension.math_dollars = true;
-        options.extension.front_matter_delimiter = Some(FRONT_MATTER_DELIMITER.into());
-        options.extension.header_ids = Some(HEADER_IDS_SECURITY_PREFIX.into());
-        options.render.figure_with_caption = true;
-
-        options.render.r#unsafe = true; // Unable unsafe mode to allow HTML to go through. To avoid XSS, we take care of it with ammonia sanitizer in the Html wrapper type
+        let options = self.options.to_comrak_options();
+        let code_blocks_highlighter = CodeBlocksHighlighter {
+            manager: &self.manager,
+            budget: &self.budget,
+            deadline: HighlightDeadline::new(&self.budget),
+            diagnostics: Mutex::new(Vec::new()),
+            highlight_names: Mutex::new(BTreeSet::new()),
+        };
         let plugins = Plugins {
             render: comrak::options::RenderPlugins {
-                codefence_syntax_highlighter: Some(self as &dyn SyntaxHighlighterAdapter),
+                codefence_syntax_highlighter: Some(&code_blocks_highlighter),
                 heading_adapter: None,
             },
         };
@@ -84,94 +119,368 @@ impl Previewable for ComrakParser {
         // Structure based on code inside markdown_to_html_with_plugins()
         let arena = Arena::new();
         let root = parse_document(&arena, source, &options);
+        let alert_types = render_alerts(&arena, root);
+        let mut equations = EquationNumbers::from_document(root);
+        equations.link_references(&arena, root);
 
+        let mut math_renderer = BudgetedMathRenderer::new(&self.budget);
+        let math_code_blocks =
+            matches!(self.options.get_math(), MathSyntax::Code | MathSyntax::Both);
+        let typst_blocks = self.options.get_typst_blocks();
         for node in root.descendants() {
+            // Taken before borrowing the node, the label is in the next one
+            let equation = equations.take_label(node);
             let node_borrow = &mut node.data.borrow_mut();
-            if let NodeValue::Math(node_math) = &node_borrow.value {
-                let math_exp = &node_math.literal;
-                let maybe_svg = MATH_RENDERER.convert_math_expression_into_svg(math_exp);
-                let mut result = match maybe_svg {
-                    Ok(svg) => svg,
-                    Err(err) => format!("<span class='parse-error'>{err}</span>"),
-                };
-                // We want to wrap the block inside a paragraph as the SVG itself is not different for inline or block
-                // Adding these class allow to easily style them differently
-                // Note: The attribute display_math is true when this is a block math expressions. display_math: opendollars == 2,
-                let (tag, css_class) = if node_math.display_math {
-                    ("p", "math-block")
-                } else {
-                    ("span", "math-inline")
-                };
-
-                result = format!("<{tag} class='{css_class}'>{result}</{tag}>");
-                node_borrow.value = NodeValue::HtmlInline(result);
-            }
+            // The math expression or Typst content, how it is rendered and its Markdown source
+            let (expression, kind, source) = match &node_borrow.value {
+                NodeValue::Math(node_math) => {
+                    let literal = &node_math.literal;
+                    let source = match (node_math.dollar_math, node_math.display_math) {
+                        (true, true) => format!("$${literal}$$"),
+                        (true, false) => format!("${literal}$"),
+                        (false, _) => format!("$`{literal}`$"),
+                    };
+                    // Note: The attribute display_math is true when this is a block math expressions. display_math: opendollars == 2,
+                    let kind = match node_math.display_math {
+                        true => TypstKind::MathBlock,
+                        false => TypstKind::MathInline,
+                    };
+                    (literal.clone(), kind, source)
+                }
+                NodeValue::CodeBlock(code_block)
+                    if math_code_blocks && code_block.info == "math" =>
+                {
+                    let literal = &code_block.literal;
+                    (
+                        literal.clone(),
+                        TypstKind::MathBlock,
+                        format!("```math\n{literal}```"),
+                    )
+                }
+                NodeValue::CodeBlock(code_block) if typst_blocks && code_block.info == "typst" => {
+                    let literal = &code_block.literal;
+                    (
+                        literal.clone(),
+                        TypstKind::Figure,
+                        format!("```typst\n{literal}```"),
+                    )
+                }
+                _ => continue,
+            };
+            let latex = match self.options.get_math_language() {
+                MathLanguage::Typst => false,
+                MathLanguage::Latex => true,
+                MathLanguage::Auto => looks_like_latex(&expression),
+            };
+            let convert = match kind {
+                TypstKind::Figure => MathRenderer::convert_typst_content_into_svg,
+                _ if latex => MathRenderer::convert_latex_math_expression_into_svg,
+                _ => MathRenderer::convert_math_expression_into_svg,
+            };
+            let mut result = match math_renderer.render(&expression, convert) {
+                Some(Ok(svg)) => svg,
+                Some(Err(err)) => {
+                    // The errors quote the expression or the Typst content
+                    let mut escaped = String::default();
+                    let _ = escape(&mut escaped, &err);
+                    format!("<span class='parse-error'>{escaped}</span>")
+                }
+                // Over budget, the expression is kept as is
+                None => {
+                    let mut escaped = String::default();
+                    let _ = escape(&mut escaped, &source);
+                    escaped
+                }
+            };
+            // We want to wrap the block inside a paragraph as the SVG itself is not different for inline or block
+            // Adding these class allow to easily style them differently
+            let (tag, css_class) = match kind {
+                TypstKind::MathInline => ("span", "math-inline"),
+                TypstKind::MathBlock => ("p", "math-block"),
+                TypstKind::Figure => ("figure", "typst-figure"),
+            };
+
+            result = match equation {
+                Some((id, number)) => {
+                    let id = id.map(|id| format!(" id='{id}'")).unwrap_or_default();
+                    format!("<{tag} class='{css_class}'{id}>{result}<span class='equation-number'>({number})</span></{tag}>")
+                }
+                None => format!("<{tag} class='{css_class}'>{result}</{tag}>"),
+            };
+            node_borrow.value = match node_borrow.value {
+                NodeValue::CodeBlock(_) => NodeValue::HtmlBlock(NodeHtmlBlock {
+                    block_type: 0,
+                    literal: result,
+                }),
+                _ => NodeValue::HtmlInline(result),
+            };
         }
+        let mut warnings = math_renderer.into_warnings();
 
-        // Normal
-        let mut rendered_html = String::default();
-        format_html_with_plugins(root, &options, &mut rendered_html, &plugins).unwrap();
+        // Normal, with the end of each top-level block
+        let written = Cell::new(0);
+        let mut output = CountingWriter {
+            html: String::default(),
+            written: &written,
+        };
+        let block_ends = BlockEnds {
+            written: &written,
+            ends: Vec::new(),
+        };
+        let block_ends = format_document_with_formatter(
+            root,
+            &options,
+            &mut output,
+            &plugins,
+            format_block,
+            block_ends,
+        )
+        .unwrap()
+        .ends;
+        let mut rendered_html = output.html;
+        if rendered_html.len() > self.budget.max_output_bytes {
+            warnings.push(BudgetWarning::OutputTooLarge {
+                size: rendered_html.len(),
+                limit: self.budget.max_output_bytes,
+            });
+            // The document is cut after the last block under the limit, to not cut a tag or an
+            // entity in the middle
+            let cut = block_ends
+                .into_iter()
+                .take_while(|end| *end <= self.budget.max_output_bytes)
+                .last()
+                .unwrap_or_default();
+            rendered_html.truncate(cut);
+        }
+        let diagnostics = code_blocks_highlighter
+            .diagnostics
+            .into_inner()
+            .unwrap_or_default();
+        let highlight_names = code_blocks_highlighter
+            .highlight_names
+            .into_inner()
+            .unwrap_or_default();
         Html::from(rendered_html)
+            .set_diagnostics(diagnostics)
+            .set_warnings(warnings)
+            .set_highlight_names(highlight_names)
+            .set_alert_types(alert_types)
+    }
+}
+
+/// Where the top-level blocks end in the HTML, to cut an output over budget between two blocks
+struct BlockEnds<'a> {
+    /// The length of the HTML written so far, see `CountingWriter`
+    written: &'a Cell<usize>,
+    ends: Vec<usize>,
+}
+
+/// Write the HTML of the document and count its length for `BlockEnds`
+struct CountingWriter<'a> {
+    html: String,
+    written: &'a Cell<usize>,
+}
+
+impl fmt::Write for CountingWriter<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.html.push_str(s);
+        self.written.set(self.html.len());
+        Ok(())
+    }
+}
+
+/// Format a node like Comrak and note where each top-level block ends
+fn format_block<'a>(
+    context: &mut Context<BlockEnds>,
+    node: &'a AstNode<'a>,
+    entering: bool,
+) -> Result<ChildRendering, fmt::Error> {
+    let rendering = format_node_default(context, node, entering)?;
+    let top_level = node
+        .parent()
+        .is_some_and(|parent| matches!(parent.data.borrow().value, NodeValue::Document));
+    if !entering && top_level {
+        let end = context.user.written.get();
+        context.user.ends.push(end);
+    }
+    Ok(rendering)
+}
+
+/// How a Typst source is rendered in the document
+#[derive(Clone, Copy)]
+enum TypstKind {
+    MathInline,
+    MathBlock,
+    /// A ```typst code block, compiled as a whole Typst content
+    Figure,
+}
+
+/// Render math expressions and Typst blocks with the global MathRenderer within the limits of a budget
+/// Typst cannot be stopped, so an expression over the time budget keeps running in background
+/// and the next expressions are skipped, as they would wait on the same Typst world.
+struct BudgetedMathRenderer<'a> {
+    budget: &'a PreviewBudget,
+    rendered: usize,
+    skipped: usize,
+    timed_out_expression: Option<String>,
+    /// An expression of a previous rendering is still running
+    still_busy: bool,
+}
+
+impl<'a> BudgetedMathRenderer<'a> {
+    fn new(budget: &'a PreviewBudget) -> Self {
+        BudgetedMathRenderer {
+            budget,
+            rendered: 0,
+            skipped: 0,
+            timed_out_expression: None,
+            still_busy: false,
+        }
+    }
+
+    /// Render the expression into SVG with `convert`, None if it is over budget
+    fn render(
+        &mut self,
+        expression: &str,
+        convert: fn(&MathRenderer, &str) -> Result<String, String>,
+    ) -> Option<Result<String, String>> {
+        if self.timed_out_expression.is_some()
+            || self.still_busy
+            || self.rendered >= self.budget.max_math_expressions
+        {
+            self.skipped += 1;
+            return None;
+        }
+        let owned_expression = expression.to_string();
+        let result = MATH_WORKER.run(self.budget.math_timeout, move || {
+            convert(&MATH_RENDERER, &owned_expression)
+        });
+        match result {
+            Ok(result) => {
+                self.rendered += 1;
+                Some(result)
+            }
+            Err(DeadlineError::TimedOut) => {
+                self.timed_out_expression = Some(expression.trim().to_string());
+                None
+            }
+            Err(DeadlineError::StillBusy) => {
+                self.still_busy = true;
+                self.skipped += 1;
+                None
+            }
+        }
+    }
+
+    fn into_warnings(self) -> Vec<BudgetWarning> {
+        match self.timed_out_expression {
+            Some(expression) => vec![BudgetWarning::MathTimeout {
+                expression,
+                skipped: self.skipped,
+            }],
+            None if self.still_busy => vec![BudgetWarning::MathStillBusy {
+                skipped: self.skipped,
+            }],
+            None if self.skipped > 0 => vec![BudgetWarning::TooManyMathExpressions {
+                limit: self.budget.max_math_expressions,
+                skipped: self.skipped,
+            }],
+            None => Vec::new(),
+        }
     }
 }
 
 /// The high level entrypoint to access a cached TreeSitterHighlighter and highlight a given piece of code
 /// If the grammar is not installed or cannot be used, the original code is used in its HTML
-/// escaped form to avoid being changed in sanitization
+/// escaped form to avoid being changed in sanitization, and the reason is in the diagnostics of
+/// the returned Html. A code without lang is not highlighted without any diagnostic.
+/// The code bigger than the budget or taking too long to highlight is not highlighted either.
 pub fn highlight_code_from_cached_highlighter(
     manager: &TreeSitterGrammarsManager,
     maybe_lang: Option<&str>,
     code: &str,
+    budget: &PreviewBudget,
 ) -> Html {
-    if let Some(lang) = maybe_lang {
-        if !lang.is_empty() {
-            let owned_lang = lang.to_owned();
-            let cache = TSH_CACHE.read().unwrap();
-            let highlighter = cache.get(&owned_lang);
-
-            match highlighter {
-                // We have a highlighter in cache, juse use it
-                Some(h_cached) => {
-                    return h_cached.highlight(code);
-                }
-                None => {
-                    // Otherwise create a new one, use it and save it in CACHE
-                    let new_h = TreeSitterHighlighter::new(&owned_lang, manager);
-                    if let Ok(valid_new_h) = new_h {
-                        let result = valid_new_h.highlight(code);
-                        drop(cache);
-                        let mut cache = TSH_CACHE.write().unwrap();
-                        cache.insert(owned_lang, valid_new_h);
-                        drop(cache);
-                        return result;
-                    }
-                }
-            }
-        }
-    }
-
-    // If we reach this point, we need to take the original code without highlighting
-    // BUT we need to escape it to support things like "#include <iostream>" and not have it
-    // removed by the sanitization
+    highlight_code_within_deadline(
+        manager,
+        maybe_lang,
+        code,
+        budget,
+        &HighlightDeadline::new(budget),
+    )
+}
 
-    let mut escaped = String::default();
-    if escape(&mut escaped, code).is_err() {
-        escaped.push_str("failed to escape code sorry...");
+/// Same as `highlight_code_from_cached_highlighter()` with the highlight time left for the
+/// whole document
+fn highlight_code_within_deadline(
+    manager: &TreeSitterGrammarsManager,
+    maybe_lang: Option<&str>,
+    code: &str,
+    budget: &PreviewBudget,
+    deadline: &HighlightDeadline,
+) -> Html {
+    let lang = match maybe_lang {
+        Some(lang) if !lang.is_empty() => lang,
+        _ => return Html::from_escaped_text(code),
+    };
+    let result = if code.len() > budget.max_code_block_bytes {
+        Err(HighlightError::CodeBlockTooLarge {
+            size: code.len(),
+            limit: budget.max_code_block_bytes,
+        })
+    } else if let Some(inner_lang) = diff_block_lang(lang) {
+        return highlight_diff(manager, inner_lang, code, deadline);
+    } else {
+        HIGHLIGHTER_CACHE
+            .get_or_create(manager, lang)
+            .and_then(|highlighter| {
+                deadline.run(|flag| highlighter.try_highlight_with_cancellation(code, Some(flag)))
+            })
+    };
+    match result {
+        Ok(html) => html,
+        // We need to escape the code to support things like "#include <iostream>" and not have
+        // it removed by the sanitization
+        Err(error) => Html::from_escaped_text(code).set_diagnostics(vec![HighlightDiagnostic {
+            lang: lang.to_string(),
+            error,
+        }]),
     }
-    Html::from(escaped)
 }
 
-/// Implement a TreeSitterHighlighter integration on Comrak
+/// Implement a TreeSitterHighlighter integration on Comrak, created for each rendering to
+/// collect the diagnostics and the highlight names of its code blocks
 // This is based on Syntect integration
 // https://docs.rs/comrak/latest/src/comrak/plugins/syntect.rs.html#71-133
-impl SyntaxHighlighterAdapter for ComrakParser {
+struct CodeBlocksHighlighter<'a> {
+    manager: &'a TreeSitterGrammarsManager,
+    budget: &'a PreviewBudget,
+    /// The highlight time left for the rest of the document
+    deadline: HighlightDeadline,
+    diagnostics: Mutex<Vec<HighlightDiagnostic>>,
+    highlight_names: Mutex<BTreeSet<String>>,
+}
+
+impl SyntaxHighlighterAdapter for CodeBlocksHighlighter<'_> {
     fn write_highlighted(
         &self,
         output: &mut dyn std::fmt::Write,
         maybe_lang: Option<&str>,
         code: &str,
     ) -> fmt::Result {
-        let html = highlight_code_from_cached_highlighter(&self.manager, maybe_lang, code);
+        let html = highlight_code_within_deadline(
+            self.manager,
+            maybe_lang,
+            code,
+            self.budget,
+            &self.deadline,
+        );
+        if let Ok(mut diagnostics) = self.diagnostics.lock() {
+            diagnostics.extend_from_slice(html.get_diagnostics());
+        }
+        if let Ok(mut highlight_names) = self.highlight_names.lock() {
+            highlight_names.extend(html.get_highlight_names().iter().cloned());
+        }
         // TODO: refactor this to avoid calling to_safe_html_string on each code snippet + on the whole final document
         // How can we call it only at the end ?
         let _ = output.write_str(&html.to_safe_html_string());
@@ -0,0 +1,345 @@
+// Diff-aware highlighting of ```diff code blocks. Each line is marked as added, removed, context
+// or header, and the code inside the lines is highlighted with the grammar of the diffed files.
+// The lang comes from ```diff-<lang> or from the extension of the file names in the headers.
+// The old and new versions of each file are highlighted separately, so multi-lines tokens like
+// block comments are still correct.
+
+use std::{collections::BTreeSet, path::Path};
+
+use comrak::html::escape;
+
+use crate::preview::{
+    budget::HighlightDeadline,
+    highlight_diagnostics::{HighlightDiagnostic, HighlightError},
+    highlighter_cache::HIGHLIGHTER_CACHE,
+    preview::Html,
+    tree_sitter_grammars::TreeSitterGrammarsManager,
+    tree_sitter_highlight::TreeSitterHighlighter,
+};
+
+/// The kind of a line, the name is used in the CSS class like `diff-added`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DiffLineKind {
+    /// Like "diff --git", "index", "--- a/file" and "+++ b/file"
+    Header,
+    /// Like "@@ -1,3 +1,4 @@"
+    Hunk,
+    Added,
+    Removed,
+    Context,
+}
+
+impl DiffLineKind {
+    fn css_class(&self) -> &'static str {
+        match self {
+            DiffLineKind::Header => "diff-header",
+            DiffLineKind::Hunk => "diff-hunk",
+            DiffLineKind::Added => "diff-added",
+            DiffLineKind::Removed => "diff-removed",
+            DiffLineKind::Context => "diff-context",
+        }
+    }
+}
+
+struct DiffLine<'a> {
+    kind: DiffLineKind,
+    /// The +, - or space at the start of the line, empty for headers
+    marker: &'a str,
+    /// The code after the marker, or the whole line for headers
+    content: &'a str,
+    /// The index of the file in the diff, to highlight it with the right lang
+    file: usize,
+}
+
+impl<'a> DiffLine<'a> {
+    fn header(kind: DiffLineKind, line: &'a str, file: usize) -> Self {
+        DiffLine {
+            kind,
+            marker: "",
+            content: line,
+            file,
+        }
+    }
+}
+
+/// Get the lang inside the diff block, if this lang is a diff one: "diff" and "patch" gives
+/// Some(None), "diff-rust" gives Some(Some("rust")), "rust" gives None
+pub fn diff_block_lang(lang: &str) -> Option<Option<&str>> {
+    match lang {
+        "diff" | "patch" => Some(None),
+        _ => lang
+            .strip_prefix("diff-")
+            .filter(|inner| !inner.is_empty())
+            .map(Some),
+    }
+}
+
+/// Render a diff with the code highlighted in the given lang, or the lang detected via the file
+/// names. A missing grammar is only reported when the lang is given explicitly.
+pub fn highlight_diff(
+    manager: &TreeSitterGrammarsManager,
+    explicit_lang: Option<&str>,
+    code: &str,
+    deadline: &HighlightDeadline,
+) -> Html {
+    let (lines, file_langs) = parse_diff(code);
+    let mut diagnostics = Vec::new();
+    let mut reported_langs = BTreeSet::new();
+    let mut highlight_names = BTreeSet::new();
+
+    // Highlight the old and new versions of each file, then pick the highlighted lines in order
+    let mut highlighted: Vec<Option<String>> = vec![None; lines.len()];
+    // All the versions share the timeout of this code block
+    deadline.run_block(|deadline| {
+        for (file, file_lang) in file_langs.iter().enumerate() {
+            let Some(lang) = explicit_lang.or(file_lang.as_deref()) else {
+                continue;
+            };
+            for side in [DiffLineKind::Removed, DiffLineKind::Added] {
+                let indexes: Vec<usize> = lines
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, line)| {
+                        line.file == file
+                            && (line.kind == side || line.kind == DiffLineKind::Context)
+                    })
+                    .map(|(index, _)| index)
+                    .collect();
+                if indexes.is_empty() {
+                    continue;
+                }
+                let version = indexes
+                    .iter()
+                    .map(|index| lines[*index].content)
+                    .collect::<Vec<&str>>()
+                    .join("\n");
+                match highlight_lines(manager, lang, &version, deadline) {
+                    Ok((version_lines, version_names)) => {
+                        highlight_names.extend(version_names);
+                        for (index, html) in indexes.iter().zip(version_lines) {
+                            // Context lines are part of both versions, the new one is kept
+                            highlighted[*index] = Some(html);
+                        }
+                    }
+                    Err(error) => {
+                        let reported =
+                            explicit_lang.is_some() || error != HighlightError::GrammarMissing;
+                        // Only one diagnostic per lang is enough
+                        if reported && reported_langs.insert(lang.to_string()) {
+                            diagnostics.push(HighlightDiagnostic {
+                                lang: lang.to_string(),
+                                error,
+                            });
+                        }
+                        break;
+                    }
+                }
+            }
+        }
+    });
+
+    let mut html = String::new();
+    for (line, highlighted) in lines.iter().zip(highlighted) {
+        let content = highlighted.unwrap_or_else(|| escaped(line.content));
+        html.push_str(&format!(
+            "<span class='diff-line {}'>",
+            line.kind.css_class()
+        ));
+        if !line.marker.is_empty() {
+            html.push_str(&format!(
+                "<span class='diff-marker'>{}</span>",
+                escaped(line.marker)
+            ));
+        }
+        html.push_str(&content);
+        // The line break is kept when the code is copied, whatever the CSS of the theme
+        html.push_str("</span>\n");
+    }
+    Html::from(html)
+        .set_diagnostics(diagnostics)
+        .set_highlight_names(highlight_names)
+}
+
+fn highlight_lines(
+    manager: &TreeSitterGrammarsManager,
+    lang: &str,
+    code: &str,
+    deadline: &HighlightDeadline,
+) -> Result<(Vec<String>, BTreeSet<String>), HighlightError> {
+    let highlighter = HIGHLIGHTER_CACHE.get_or_create(manager, lang)?;
+    deadline.run(|flag| highlighter.try_highlight_lines(code, Some(flag)))
+}
+
+/// Split the diff into lines and find the lang of each file via the extension in the headers
+fn parse_diff(code: &str) -> (Vec<DiffLine<'_>>, Vec<Option<String>>) {
+    let mut lines = Vec::new();
+    let mut file_langs: Vec<Option<String>> = vec![None];
+    let mut file = 0;
+    // The lines left in the current hunk, to not take a removed "-- comment" for a header
+    let (mut old_left, mut new_left) = (0usize, 0usize);
+    let mut in_file_headers = false;
+
+    for line in code.lines() {
+        let in_hunk = old_left > 0 || new_left > 0;
+        if !in_hunk && line.starts_with("@@") {
+            (old_left, new_left) = parse_hunk_lengths(line).unwrap_or((0, 0));
+            in_file_headers = false;
+            lines.push(DiffLine::header(DiffLineKind::Hunk, line, file));
+            continue;
+        }
+        if !in_hunk && is_file_header(line) {
+            // A new file starts with its first header
+            if !in_file_headers && lines.iter().any(|l: &DiffLine| l.file == file) {
+                file += 1;
+                file_langs.push(None);
+            }
+            in_file_headers = true;
+            if let Some(path) = line
+                .strip_prefix("+++ ")
+                .or_else(|| line.strip_prefix("--- "))
+            {
+                if let Some(lang) = lang_from_path(path) {
+                    file_langs[file] = Some(lang);
+                }
+            }
+            lines.push(DiffLine::header(DiffLineKind::Header, line, file));
+            continue;
+        }
+        in_file_headers = false;
+
+        let (kind, marker, content) = match line.chars().next() {
+            Some('+') => (DiffLineKind::Added, "+", &line[1..]),
+            Some('-') => (DiffLineKind::Removed, "-", &line[1..]),
+            Some(' ') => (DiffLineKind::Context, " ", &line[1..]),
+            // Like "\ No newline at end of file", or snippets written without the space
+            _ => (DiffLineKind::Context, "", line),
+        };
+        match kind {
+            DiffLineKind::Added => new_left = new_left.saturating_sub(1),
+            DiffLineKind::Removed => old_left = old_left.saturating_sub(1),
+            _ if marker == " " => {
+                old_left = old_left.saturating_sub(1);
+                new_left = new_left.saturating_sub(1);
+            }
+            _ => {}
+        }
+        lines.push(DiffLine {
+            kind,
+            marker,
+            content,
+            file,
+        });
+    }
+    (lines, file_langs)
+}
+
+fn is_file_header(line: &str) -> bool {
+    [
+        "diff ",
+        "index ",
+        "--- ",
+        "+++ ",
+        "new file mode",
+        "deleted file mode",
+        "old mode",
+        "new mode",
+        "similarity index",
+        "rename from",
+        "rename to",
+    ]
+    .iter()
+    .any(|prefix| line.starts_with(prefix))
+}
+
+/// Parse "@@ -1,3 +1,4 @@ fn main()" into (3, 4), a missing length is 1
+fn parse_hunk_lengths(line: &str) -> Option<(usize, usize)> {
+    let mut ranges = line.trim_start_matches('@').split_whitespace();
+    let length = |range: &str| -> Option<usize> {
+        match range[1..].split_once(',') {
+            Some((_, length)) => length.parse().ok(),
+            None => Some(1),
+        }
+    };
+    let old = ranges.next().filter(|r| r.starts_with('-'))?;
+    let new = ranges.next().filter(|r| r.starts_with('+'))?;
+    Some((length(old)?, length(new)?))
+}
+
+/// The lang of a path like "a/src/main.rs", ignoring /dev/null and the timestamps of diff -u
+fn lang_from_path(path: &str) -> Option<String> {
+    let path = path.split('\t').next().unwrap_or_default().trim();
+    if path == "/dev/null" {
+        return None;
+    }
+    let extension = Path::new(path).extension()?.to_str()?;
+    Some(TreeSitterHighlighter::normalize_lang(extension))
+}
+
+fn escaped(text: &str) -> String {
+    let mut escaped = String::default();
+    let _ = escape(&mut escaped, text);
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::{diff_block_lang, highlight_diff, parse_diff, DiffLineKind};
+    use crate::preview::{
+        budget::{HighlightDeadline, PreviewBudget},
+        tree_sitter_grammars::{
+            get_unique_local_tree_sitter_grammars_folder, TreeSitterGrammarsManager,
+        },
+    };
+
+    #[test]
+    fn test_diff_lines_are_classified_per_file() {
+        assert_eq!(diff_block_lang("diff-rust"), Some(Some("rust")));
+        assert_eq!(diff_block_lang("patch"), Some(None));
+        assert_eq!(diff_block_lang("rust"), None);
+
+        let diff = "diff --git a/a.rs b/a.rs\n--- a/a.rs\n+++ b/a.rs\n@@ -1,2 +1,2 @@\n-- x\n+let y;\n fn\n--- /dev/null\n+++ b/b.py\n@@ -0,0 +1 @@\n+x = 1";
+        let (lines, langs) = parse_diff(diff);
+        let kinds: Vec<DiffLineKind> = lines.iter().map(|l| l.kind).collect();
+        use DiffLineKind::*;
+        assert_eq!(
+            kinds,
+            vec![
+                Header, Header, Header, Hunk, Removed, Added, Context, Header, Header, Hunk, Added
+            ]
+        );
+        // "-- x" is a removed line inside the hunk, not a header
+        assert_eq!(lines[4].content, "- x");
+        assert_eq!(lines[10].file, 1);
+        assert_eq!(
+            langs,
+            vec![Some("rust".to_string()), Some("python".to_string())]
+        );
+
+        let manager = TreeSitterGrammarsManager::new_with_grammars_folder(
+            get_unique_local_tree_sitter_grammars_folder(),
+        )
+        .unwrap();
+        let html = highlight_diff(
+            &manager,
+            None,
+            "-<a>\n+b",
+            &HighlightDeadline::new(&PreviewBudget::default()),
+        );
+        assert!(html.get_diagnostics().is_empty());
+        assert_eq!(
+            html.to_safe_html_string(),
+            "<span class=\"diff-line diff-removed\"><span class=\"diff-marker\">-</span>&lt;a&gt;</span>\n<span class=\"diff-line diff-added\"><span class=\"diff-marker\">+</span>b</span>\n"
+        );
+
+        // A missing grammar is reported once, even with several files
+        let two_files = "--- a/a.zonk\n+++ b/a.zonk\n@@ -1 +1 @@\n-a\n+b\n--- a/b.zonk\n+++ b/b.zonk\n@@ -1 +1 @@\n-c\n+d";
+        let deadline = HighlightDeadline::new(&PreviewBudget::default());
+        let html = highlight_diff(&manager, Some("zonk"), two_files, &deadline);
+        assert_eq!(
+            html.get_diagnostics_summary(),
+            vec!["1 code block in `zonk` not highlighted: grammar not installed"]
+        );
+    }
+}
@@ -0,0 +1,175 @@
+// Numbered equations and their references. A block math expression followed by a label is
+// numbered in the order of the document, and `@eq:label` in the text becomes a link to it:
+//
+// $$E = m c^2$$ {#eq:energy}
+//
+// As shown in @eq:energy, ...
+
+use std::collections::HashMap;
+
+use comrak::{
+    html::escape,
+    nodes::{AstNode, NodeValue},
+    Arena,
+};
+
+/// The prefix of the IDs of the numbered equations, like `eq-energy` for `{#eq:energy}`
+pub const EQUATION_IDS_PREFIX: &str = "eq-";
+
+const LABEL_START: &str = "{#eq:";
+const REFERENCE_START: &str = "@eq:";
+
+/// The numbers of the labelled equations of a document
+#[derive(Debug, Default)]
+pub(crate) struct EquationNumbers {
+    numbers: HashMap<String, usize>,
+    count: usize,
+    /// The equations already rendered, see take_label()
+    taken: usize,
+}
+
+impl EquationNumbers {
+    /// Number the labelled block math expressions of the document, in their order. A label
+    /// used twice keeps the number of its first equation.
+    pub(crate) fn from_document<'a>(root: &'a AstNode<'a>) -> Self {
+        let mut equations = EquationNumbers::default();
+        for node in root.descendants() {
+            if let Some(label) = equation_label(node) {
+                equations.count += 1;
+                equations.numbers.entry(label).or_insert(equations.count);
+            }
+        }
+        equations
+    }
+
+    /// Remove the label after a block math expression, and give the HTML ID and the number of
+    /// the equation. It must be called in the order of the document. The equations reusing a label are numbered without an ID.
+    pub(crate) fn take_label<'a>(
+        &mut self,
+        node: &'a AstNode<'a>,
+    ) -> Option<(Option<String>, usize)> {
+        let label = equation_label(node)?;
+        let next = node.next_sibling()?;
+        if let NodeValue::Text(text) = &mut next.data.borrow_mut().value {
+            let rest = text.trim_start()[LABEL_START.len() + label.len() + 1..].to_string();
+            *text = rest.into();
+        }
+        self.taken += 1;
+        let id = (self.numbers.get(&label) == Some(&self.taken)).then(|| equation_id(&label));
+        Some((id, self.taken))
+    }
+
+    /// Replace the `@eq:label` references in the text by a link to their equation, or by an
+    /// error if no equation has this label
+    pub(crate) fn link_references<'a>(&self, arena: &'a Arena<'a>, root: &'a AstNode<'a>) {
+        let texts: Vec<&AstNode> = root
+            .descendants()
+            .filter(|node| match &node.data.borrow().value {
+                NodeValue::Text(text) => text.contains(REFERENCE_START),
+                _ => false,
+            })
+            .collect();
+        for node in texts {
+            let text = match &node.data.borrow().value {
+                NodeValue::Text(text) => text.to_string(),
+                _ => continue,
+            };
+            let mut rest = text.as_str();
+            while let Some((before, label, after)) = split_reference(rest) {
+                node.insert_before(arena.alloc(NodeValue::Text(before.to_string().into()).into()));
+                let html = match self.numbers.get(label) {
+                    Some(number) => format!("<a href=\"#{}\">({number})</a>", equation_id(label)),
+                    None => {
+                        let mut escaped = String::default();
+                        let _ = escape(&mut escaped, label);
+                        format!("<span class='parse-error'>Unknown equation @eq:{escaped}</span>")
+                    }
+                };
+                node.insert_before(arena.alloc(NodeValue::HtmlInline(html).into()));
+                rest = after;
+            }
+            node.data.borrow_mut().value = NodeValue::Text(rest.to_string().into());
+        }
+    }
+}
+
+fn equation_id(label: &str) -> String {
+    format!("{EQUATION_IDS_PREFIX}{label}")
+}
+
+fn is_label_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '-' || c == '_'
+}
+
+/// The label of a block math expression, written right after it like `{#eq:energy}`
+fn equation_label<'a>(node: &'a AstNode<'a>) -> Option<String> {
+    match &node.data.borrow().value {
+        NodeValue::Math(math) if math.display_math => {}
+        _ => return None,
+    }
+    let next = node.next_sibling()?;
+    let next = next.data.borrow();
+    let NodeValue::Text(text) = &next.value else {
+        return None;
+    };
+    let (label, _) = text
+        .trim_start()
+        .strip_prefix(LABEL_START)?
+        .split_once('}')?;
+    (!label.is_empty() && label.chars().all(is_label_char)).then(|| label.to_string())
+}
+
+/// Split the text around its first `@eq:label` reference. An `@` inside a word, like in an
+/// email address, is not a reference.
+fn split_reference(text: &str) -> Option<(&str, &str, &str)> {
+    let mut searched = 0;
+    while let Some(position) = text[searched..].find(REFERENCE_START) {
+        let start = searched + position;
+        let label_start = start + REFERENCE_START.len();
+        let label_length = text[label_start..]
+            .find(|c| !is_label_char(c))
+            .unwrap_or(text.len() - label_start);
+        let in_word = text[..start]
+            .chars()
+            .next_back()
+            .is_some_and(|c| c.is_alphanumeric());
+        if label_length > 0 && !in_word {
+            let end = label_start + label_length;
+            return Some((&text[..start], &text[label_start..end], &text[end..]));
+        }
+        searched = label_start;
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use crate::preview::{budget::PreviewBudget, comrak::ComrakParser, preview::Previewable};
+
+    #[test]
+    fn test_labelled_equations_are_numbered_and_referenced() {
+        // Without math rendering, to keep the SVG IDs of the other tests
+        let no_math = PreviewBudget {
+            max_math_expressions: 0,
+            ..PreviewBudget::default()
+        };
+        let given = "See @eq:second and @eq:first, not a@eq:first.\n\n$$a$$ {#eq:first}\n\n$$b$$\n\n$$c$$ {#eq:second}\n\n$$d$$ {#eq:first}\n\nAnd @eq:missing.\n";
+        let html = ComrakParser::new()
+            .unwrap()
+            .with_budget(no_math)
+            .to_html(given)
+            .to_safe_html_string();
+        assert_eq!(
+            html,
+            r##"<p>See <a href="#eq-second" rel="noopener noreferrer">(2)</a> and <a href="#eq-first" rel="noopener noreferrer">(1)</a>, not a@eq:first.</p>
+<p></p><p class="math-block" id="eq-first">$$a$$<span class="equation-number">(1)</span></p><p></p>
+<p></p><p class="math-block">$$b$$</p><p></p>
+<p></p><p class="math-block" id="eq-second">$$c$$<span class="equation-number">(2)</span></p><p></p>
+<p></p><p class="math-block">$$d$$<span class="equation-number">(3)</span></p><p></p>
+<p>And <span class="parse-error">Unknown equation @eq:missing</span>.</p>
+"##
+        );
+    }
+}
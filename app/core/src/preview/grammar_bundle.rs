@@ -0,0 +1,486 @@
+// Precompiled grammar bundles, to install grammars on machines without a C compiler or Git
+// A bundle is a .tar.gz archive with this structure
+// manifest.json               -> a GrammarBundleManifest with the target and checksums
+// tree-sitter-css/
+//     css.so                  -> the compiled shared library (.dylib on macOS, .dll on Windows)
+//     tree-sitter.json
+//     src/grammar.json        -> only used to get the grammar name
+//     queries/highlights.scm  -> all the queries
+//     ...
+
+use std::{
+    collections::BTreeMap,
+    env::consts::{ARCH, DLL_EXTENSION, OS},
+    fs::{create_dir_all, read, read_to_string, remove_dir_all},
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tree_sitter::{Language, LANGUAGE_VERSION, MIN_COMPATIBLE_LANGUAGE_VERSION};
+use tree_sitter_loader::{CompileConfig, Loader};
+use walkdir::WalkDir;
+
+use crate::{
+    preview::grammar_sources::{grammar_name, validate_grammar_name},
+    util::archive::{create_tar_gz, extract_archive},
+};
+
+/// Increase this when the structure of the bundle changes in an incompatible way
+pub const GRAMMAR_BUNDLE_FORMAT_VERSION: u32 = 1;
+const MANIFEST_FILENAME: &str = "manifest.json";
+
+/// The platform a bundle has been built for, shared libraries cannot be loaded on another one
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct BundleTarget {
+    /// Like "linux", "macos" or "windows"
+    pub os: String,
+    /// Like "x86_64" or "aarch64"
+    pub arch: String,
+    /// The C library ABI, like "gnu", "musl" or "msvc". Empty if unknown
+    pub env: String,
+}
+
+impl BundleTarget {
+    /// The target of the running binary
+    pub fn current() -> Self {
+        let env = if cfg!(target_env = "gnu") {
+            "gnu"
+        } else if cfg!(target_env = "musl") {
+            "musl"
+        } else if cfg!(target_env = "msvc") {
+            "msvc"
+        } else {
+            ""
+        };
+        BundleTarget {
+            os: OS.to_string(),
+            arch: ARCH.to_string(),
+            env: env.to_string(),
+        }
+    }
+}
+
+/// A grammar inside a bundle
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct BundledGrammar {
+    /// The language identifier, the grammar is stored under tree-sitter-{lang}
+    pub lang: String,
+    /// The grammar name in src/grammar.json, used to find the shared library and its symbol
+    pub name: String,
+    /// The Tree-Sitter ABI version of the compiled parser
+    pub abi_version: usize,
+    /// The SHA-256 checksum of each file of the grammar, indexed by path relative to the grammar folder
+    pub checksums: BTreeMap<String, String>,
+}
+
+/// Description of a bundle saved as manifest.json at the root of the archive
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct GrammarBundleManifest {
+    pub format_version: u32,
+    /// The version of dme-core that created the bundle, only informative
+    pub dme_core_version: String,
+    pub target: BundleTarget,
+    /// The most recent ABI version supported by the tree-sitter library that created the bundle
+    pub tree_sitter_language_version: usize,
+    pub grammars: Vec<BundledGrammar>,
+}
+
+impl GrammarBundleManifest {
+    /// Make sure the bundle can be used by this binary, on this machine
+    pub fn check_compatibility(&self) -> Result<(), String> {
+        if self.format_version != GRAMMAR_BUNDLE_FORMAT_VERSION {
+            return Err(format!(
+                "Unsupported bundle format version {}, expected {GRAMMAR_BUNDLE_FORMAT_VERSION}",
+                self.format_version
+            ));
+        }
+        let current = BundleTarget::current();
+        if self.target != current {
+            return Err(format!(
+                "This bundle has been built for {}-{}-{} but this machine is {}-{}-{}",
+                self.target.arch,
+                self.target.os,
+                self.target.env,
+                current.arch,
+                current.os,
+                current.env
+            ));
+        }
+        for grammar in &self.grammars {
+            if !(MIN_COMPATIBLE_LANGUAGE_VERSION..=LANGUAGE_VERSION).contains(&grammar.abi_version)
+            {
+                return Err(format!(
+                    "The grammar {} has been compiled with the Tree-Sitter ABI version {}, only versions {MIN_COMPATIBLE_LANGUAGE_VERSION} to {LANGUAGE_VERSION} are supported",
+                    grammar.lang, grammar.abi_version
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The path of the precompiled shared library inside a grammar folder imported from a bundle
+pub(crate) fn precompiled_library_path(grammar_folder: &Path, name: &str) -> PathBuf {
+    grammar_folder.join(format!("{name}.{DLL_EXTENSION}"))
+}
+
+/// Load the language from a precompiled shared library, without any compilation
+pub(crate) fn load_precompiled_language(grammar_folder: &Path) -> Result<Language, String> {
+    let name = grammar_name(grammar_folder)?;
+    let library = precompiled_library_path(grammar_folder, &name);
+    if !library.exists() {
+        return Err(format!(
+            "The grammar at {grammar_folder:?} has no generated parser nor precompiled library"
+        ));
+    }
+    Loader::load_language(&library, &format!("tree_sitter_{}", name.replace('-', "_")))
+        .map_err(|e| e.to_string())
+}
+
+/// Export the given grammars (lang and folder) inside a new bundle at `destination`
+/// The shared libraries are compiled again to make sure they match the current sources
+pub(crate) fn export_bundle(
+    grammars: &[(String, PathBuf)],
+    destination: &Path,
+) -> Result<GrammarBundleManifest, String> {
+    let staging = destination.with_extension("staging");
+    if staging.exists() {
+        remove_dir_all(&staging).map_err(|e| e.to_string())?;
+    }
+    let result = (|| {
+        create_dir_all(&staging).map_err(|e| e.to_string())?;
+        let mut manifest = GrammarBundleManifest {
+            format_version: GRAMMAR_BUNDLE_FORMAT_VERSION,
+            dme_core_version: env!("CARGO_PKG_VERSION").to_string(),
+            target: BundleTarget::current(),
+            tree_sitter_language_version: LANGUAGE_VERSION,
+            grammars: Vec::new(),
+        };
+        for (lang, grammar_folder) in grammars {
+            let bundled_folder = staging.join(format!("tree-sitter-{lang}"));
+            let name = grammar_name(grammar_folder)?;
+            let abi_version = copy_grammar_for_bundle(grammar_folder, &name, &bundled_folder)?;
+            manifest.grammars.push(BundledGrammar {
+                lang: lang.clone(),
+                name,
+                abi_version,
+                checksums: checksums_of_folder(&bundled_folder)?,
+            });
+        }
+        let json = serde_json::to_string_pretty(&manifest).map_err(|e| e.to_string())?;
+        std::fs::write(staging.join(MANIFEST_FILENAME), json).map_err(|e| e.to_string())?;
+        create_tar_gz(&staging, destination)?;
+        Ok(manifest)
+    })();
+    let _ = remove_dir_all(&staging);
+    result
+}
+
+/// Copy the files needed at runtime and compile the shared library inside `bundled_folder`.
+/// Returns the ABI version of the compiled parser
+fn copy_grammar_for_bundle(
+    grammar_folder: &Path,
+    name: &str,
+    bundled_folder: &Path,
+) -> Result<usize, String> {
+    create_dir_all(bundled_folder.join("src")).map_err(|e| e.to_string())?;
+    let library = precompiled_library_path(bundled_folder, name);
+    let src_path = grammar_folder.join("src");
+    let language = if src_path.join("parser.c").exists() {
+        let mut loader = Loader::new().map_err(|e| e.to_string())?;
+        loader.force_rebuild(true);
+        loader
+            .load_language_at_path(CompileConfig::new(&src_path, None, Some(library)))
+            .map_err(|e| e.to_string())?
+    } else {
+        // This grammar has been imported from another bundle, just take the existing library
+        std::fs::copy(precompiled_library_path(grammar_folder, name), &library)
+            .map_err(|e| e.to_string())?;
+        load_precompiled_language(grammar_folder)?
+    };
+
+    for file in ["tree-sitter.json", "src/grammar.json"] {
+        if grammar_folder.join(file).exists() {
+            std::fs::copy(grammar_folder.join(file), bundled_folder.join(file))
+                .map_err(|e| format!("Couldn't copy {file}: {e}"))?;
+        }
+    }
+    let queries = grammar_folder.join("queries");
+    for entry in WalkDir::new(&queries).into_iter().filter_map(|e| e.ok()) {
+        let relative = entry
+            .path()
+            .strip_prefix(grammar_folder)
+            .map_err(|e| e.to_string())?;
+        let target = bundled_folder.join(relative);
+        if entry.file_type().is_dir() {
+            create_dir_all(&target).map_err(|e| e.to_string())?;
+        } else {
+            std::fs::copy(entry.path(), &target).map_err(|e| e.to_string())?;
+        }
+    }
+    Ok(language.abi_version())
+}
+
+/// Import a bundle inside the grammars folder, replacing existing grammars of the same lang.
+/// Returns the manifest of the imported bundle
+pub(crate) fn import_bundle(
+    bundle: &Path,
+    grammars_folder: &Path,
+) -> Result<GrammarBundleManifest, String> {
+    let staging = grammars_folder.join(".staging-bundle");
+    if staging.exists() {
+        remove_dir_all(&staging).map_err(|e| e.to_string())?;
+    }
+    let result = (|| {
+        extract_archive(bundle, &staging)?;
+        let manifest_content = read_to_string(staging.join(MANIFEST_FILENAME))
+            .map_err(|e| format!("The bundle has no {MANIFEST_FILENAME}: {e}"))?;
+        let manifest: GrammarBundleManifest = serde_json::from_str(&manifest_content)
+            .map_err(|e| format!("Invalid {MANIFEST_FILENAME} in bundle: {e}"))?;
+        manifest.check_compatibility()?;
+
+        // Verify everything before touching the grammars folder
+        for grammar in &manifest.grammars {
+            validate_grammar_name(&grammar.lang)?;
+            let folder = staging.join(format!("tree-sitter-{}", grammar.lang));
+            if checksums_of_folder(&folder)? != grammar.checksums {
+                return Err(format!(
+                    "The files of the grammar {} do not match the checksums of the bundle",
+                    grammar.lang
+                ));
+            }
+        }
+
+        for grammar in &manifest.grammars {
+            let folder_name = format!("tree-sitter-{}", grammar.lang);
+            replace_grammar_folder(
+                &staging.join(&folder_name),
+                &grammars_folder.join(&folder_name),
+            )
+            .map_err(|e| format!("The grammar {} cannot be imported: {e}", grammar.lang))?;
+        }
+        Ok(manifest)
+    })();
+    let _ = remove_dir_all(&staging);
+    result
+}
+
+/// Move the imported grammar folder to `destination`. An existing grammar is moved aside until
+/// the new one can be loaded, and restored otherwise. The folder aside doesn't start with
+/// "tree-sitter-", so it is not seen as a grammar if the process dies before the end.
+fn replace_grammar_folder(imported: &Path, destination: &Path) -> Result<(), String> {
+    let folder_name = destination
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let previous = destination.with_file_name(format!(".previous-{folder_name}"));
+    if previous.exists() {
+        remove_dir_all(&previous).map_err(|e| e.to_string())?;
+    }
+    let has_previous = destination.exists();
+    if has_previous {
+        std::fs::rename(destination, &previous)
+            .map_err(|e| format!("Couldn't move the existing grammar aside: {e}"))?;
+    }
+    let result = std::fs::rename(imported, destination)
+        .map_err(|e| format!("Couldn't move grammar to {destination:?}: {e}"))
+        .and_then(|_| load_precompiled_language(destination).map(|_| ()));
+    match result {
+        Ok(()) if has_previous => {
+            let _ = remove_dir_all(&previous);
+        }
+        Ok(()) => {}
+        Err(_) => {
+            let _ = remove_dir_all(destination);
+            if has_previous {
+                std::fs::rename(&previous, destination)
+                    .map_err(|e| format!("Couldn't restore the previous grammar: {e}"))?;
+            }
+        }
+    }
+    result
+}
+
+/// Compute the SHA-256 checksum of all the files inside the given folder
+pub(crate) fn checksums_of_folder(folder: &Path) -> Result<BTreeMap<String, String>, String> {
+    let mut checksums = BTreeMap::new();
+    for entry in WalkDir::new(folder).into_iter() {
+        let entry = entry.map_err(|e| e.to_string())?;
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        let relative = entry
+            .path()
+            .strip_prefix(folder)
+            .map_err(|e| e.to_string())?
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy().to_string())
+            .collect::<Vec<_>>()
+            .join("/");
+        let content = read(entry.path()).map_err(|e| e.to_string())?;
+        checksums.insert(relative, sha256_hex(&content));
+    }
+    Ok(checksums)
+}
+
+/// The lowercase hexadecimal SHA-256 of some bytes
+pub(crate) fn sha256_hex(content: &[u8]) -> String {
+    Sha256::digest(content)
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::BTreeMap, path::PathBuf};
+
+    use pretty_assertions::assert_eq;
+
+    use super::{
+        import_bundle, sha256_hex, BundleTarget, BundledGrammar, GrammarBundleManifest,
+        GRAMMAR_BUNDLE_FORMAT_VERSION, MANIFEST_FILENAME,
+    };
+    use crate::{
+        preview::{
+            tree_sitter_grammars::{
+                get_test_grammar_repos, get_unique_local_tree_sitter_grammars_folder,
+                TreeSitterGrammarsManager, TEST_GRAMMAR,
+            },
+            tree_sitter_highlight::TreeSitterHighlighter,
+        },
+        util::archive::create_tar_gz,
+    };
+
+    fn fake_manifest(
+        lang: &str,
+        target: BundleTarget,
+        checksums: BTreeMap<String, String>,
+    ) -> String {
+        serde_json::to_string(&GrammarBundleManifest {
+            format_version: GRAMMAR_BUNDLE_FORMAT_VERSION,
+            dme_core_version: "0.1.0".to_string(),
+            target,
+            tree_sitter_language_version: tree_sitter::LANGUAGE_VERSION,
+            grammars: vec![BundledGrammar {
+                lang: lang.to_string(),
+                name: "fake".to_string(),
+                abi_version: tree_sitter::LANGUAGE_VERSION,
+                checksums,
+            }],
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_bundle_for_another_target_or_with_invalid_checksums_is_rejected() {
+        let folder = get_unique_local_tree_sitter_grammars_folder();
+        let bundle_folder = folder.join("bundle");
+        std::fs::create_dir_all(bundle_folder.join("tree-sitter-fake")).unwrap();
+        std::fs::write(
+            bundle_folder.join("tree-sitter-fake/fake.so"),
+            "not a library",
+        )
+        .unwrap();
+        let grammars_folder = folder.join("grammars");
+        std::fs::create_dir_all(&grammars_folder).unwrap();
+        let bundle = folder.join("bundle.tar.gz");
+
+        let windows = BundleTarget {
+            os: "windows".to_string(),
+            arch: "x86_64".to_string(),
+            env: "msvc".to_string(),
+        };
+        let manifest = fake_manifest("fake", windows, BTreeMap::new());
+        std::fs::write(bundle_folder.join(MANIFEST_FILENAME), manifest).unwrap();
+        create_tar_gz(&bundle_folder, &bundle).unwrap();
+        let error = import_bundle(&bundle, &grammars_folder).unwrap_err();
+        assert!(
+            error.contains("has been built for x86_64-windows-msvc"),
+            "{error}"
+        );
+
+        let checksums = BTreeMap::from([("fake.so".to_string(), sha256_hex(b"another content"))]);
+        let manifest = fake_manifest("fake", BundleTarget::current(), checksums);
+        std::fs::write(bundle_folder.join(MANIFEST_FILENAME), manifest).unwrap();
+        create_tar_gz(&bundle_folder, &bundle).unwrap();
+        let error = import_bundle(&bundle, &grammars_folder).unwrap_err();
+        assert!(error.contains("do not match the checksums"), "{error}");
+        assert!(!grammars_folder.join("tree-sitter-fake").exists());
+
+        // A lang going outside of the grammars folder is refused
+        let manifest = fake_manifest("../../fake", BundleTarget::current(), BTreeMap::new());
+        std::fs::write(bundle_folder.join(MANIFEST_FILENAME), manifest).unwrap();
+        create_tar_gz(&bundle_folder, &bundle).unwrap();
+        let error = import_bundle(&bundle, &grammars_folder).unwrap_err();
+        assert!(error.contains("Invalid grammar name"), "{error}");
+
+        // A grammar that cannot be loaded keeps the existing one
+        std::fs::create_dir_all(grammars_folder.join("tree-sitter-fake")).unwrap();
+        std::fs::write(grammars_folder.join("tree-sitter-fake/fake.so"), "previous").unwrap();
+        let checksums = BTreeMap::from([("fake.so".to_string(), sha256_hex(b"not a library"))]);
+        let manifest = fake_manifest("fake", BundleTarget::current(), checksums);
+        std::fs::write(bundle_folder.join(MANIFEST_FILENAME), manifest).unwrap();
+        create_tar_gz(&bundle_folder, &bundle).unwrap();
+        let error = import_bundle(&bundle, &grammars_folder).unwrap_err();
+        assert!(error.contains("cannot be imported"), "{error}");
+        assert_eq!(
+            std::fs::read_to_string(grammars_folder.join("tree-sitter-fake/fake.so")).unwrap(),
+            "previous"
+        );
+    }
+
+    #[test]
+    fn test_can_export_and_import_local_grammar_bundle() {
+        let fixture =
+            PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/tree-sitter-json");
+        let mut m = TreeSitterGrammarsManager::new_with_grammars_folder(
+            get_unique_local_tree_sitter_grammars_folder(),
+        )
+        .unwrap();
+        m.install(fixture.to_str().unwrap()).unwrap();
+        let bundle = get_unique_local_tree_sitter_grammars_folder().join("grammars.tar.gz");
+        let manifest = m.export_bundle(&[], &bundle).unwrap();
+        assert_eq!(manifest.grammars.len(), 1);
+
+        // Imported twice, to replace an existing grammar
+        let mut other = TreeSitterGrammarsManager::new_with_grammars_folder(
+            get_unique_local_tree_sitter_grammars_folder(),
+        )
+        .unwrap();
+        other.import_bundle(&bundle).unwrap();
+        other.import_bundle(&bundle).unwrap();
+        assert_eq!(other.list_installed_langs().unwrap(), vec!["json"]);
+        let h = TreeSitterHighlighter::new("json", &other).unwrap();
+        assert!(h
+            .highlight("{\"a\": 1}")
+            .to_safe_html_string()
+            .contains("<span"));
+    }
+
+    #[test]
+    #[ignore = "Slow and network usage"]
+    fn test_can_export_and_import_test_grammar_bundle() {
+        let mut m = TreeSitterGrammarsManager::new_with_grammars_folder(
+            get_unique_local_tree_sitter_grammars_folder(),
+        )
+        .unwrap();
+        m.install(&get_test_grammar_repos()).unwrap();
+        let bundle = get_unique_local_tree_sitter_grammars_folder().join("grammars.tar.gz");
+        let manifest = m.export_bundle(&[], &bundle).unwrap();
+        assert_eq!(manifest.grammars.len(), 1);
+
+        let mut other = TreeSitterGrammarsManager::new_with_grammars_folder(
+            get_unique_local_tree_sitter_grammars_folder(),
+        )
+        .unwrap();
+        other.import_bundle(&bundle).unwrap();
+        assert_eq!(other.list_installed_langs().unwrap(), vec![TEST_GRAMMAR]);
+        let h = TreeSitterHighlighter::new(TEST_GRAMMAR, &other).unwrap();
+        assert!(h
+            .highlight("color: blue")
+            .to_safe_html_string()
+            .contains("<span"));
+    }
+}
@@ -0,0 +1,287 @@
+// Health check of installed grammars. A grammar folder can be half-cloned, fail to compile or have
+// queries that don't compile anymore with our Tree-Sitter version. Without this check, the only
+// symptom is code blocks silently not highlighted.
+
+use std::{
+    env::consts::DLL_EXTENSION,
+    path::{Path, PathBuf},
+};
+
+use serde::Serialize;
+use tree_sitter::{Language, Query};
+use tree_sitter_loader::Loader;
+
+use crate::{
+    preview::{
+        grammar_bundle::load_precompiled_language, grammar_sources::grammar_name,
+        tree_sitter_highlight::TreeSitterHighlighter,
+    },
+    theming::theme::Theme,
+    util::git::GitRepos,
+};
+
+/// The state of the grammar folder itself
+#[derive(Debug, Serialize, Clone, PartialEq, Eq)]
+#[serde(tag = "state", rename_all = "kebab-case")]
+pub enum RepositoryState {
+    /// A git clone at the given commit
+    Git { revision: String },
+    /// Copied from a local folder or an archive, or imported from a bundle
+    NotGit,
+    /// Half-cloned or without the files of a grammar
+    Broken { reason: String },
+}
+
+/// The result of a single check
+#[derive(Debug, Serialize, Clone, PartialEq, Eq)]
+#[serde(tag = "status", rename_all = "kebab-case")]
+pub enum HealthCheck {
+    Ok,
+    /// Nothing to check, like a grammar without injections queries
+    Missing,
+    Failed {
+        reason: String,
+    },
+    /// Not checked because a previous check failed
+    Skipped,
+}
+
+impl HealthCheck {
+    fn is_failed(&self) -> bool {
+        matches!(self, HealthCheck::Failed { .. })
+    }
+}
+
+/// How a broken grammar can be repaired
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum RepairAction {
+    /// Compile the generated parser again
+    Rebuild,
+    /// Delete the grammar folder and install it again from its original source
+    Reinstall,
+}
+
+/// The health of a single grammar folder
+#[derive(Debug, Serialize, Clone, PartialEq, Eq)]
+pub struct GrammarHealth {
+    pub lang: String,
+    pub folder: PathBuf,
+    pub repository: RepositoryState,
+    /// The compiled library is present and can be loaded
+    pub library: HealthCheck,
+    pub highlights_query: HealthCheck,
+    pub injections_query: HealthCheck,
+    pub locals_query: HealthCheck,
+    /// Highlight names captured by the highlights query without any style in the theme
+    /// These tokens are not broken but will not be coloured
+    pub unstyled_highlight_names: Vec<String>,
+}
+
+impl GrammarHealth {
+    /// Check the grammar inside the given folder, the theme is only used to look at highlight names
+    pub fn check(folder: &Path, theme: &Theme) -> Self {
+        let folder_name = folder
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let mut health = GrammarHealth {
+            lang: folder_name.trim_start_matches("tree-sitter-").to_string(),
+            folder: folder.to_path_buf(),
+            repository: Self::check_repository(folder),
+            library: HealthCheck::Skipped,
+            highlights_query: HealthCheck::Skipped,
+            injections_query: HealthCheck::Skipped,
+            locals_query: HealthCheck::Skipped,
+            unstyled_highlight_names: Vec::new(),
+        };
+        if matches!(health.repository, RepositoryState::Broken { .. }) {
+            return health;
+        }
+
+        let language = match Self::load_compiled_library(folder) {
+            Ok(Some(language)) => {
+                health.library = HealthCheck::Ok;
+                language
+            }
+            Ok(None) => {
+                health.library = HealthCheck::Missing;
+                return health;
+            }
+            Err(reason) => {
+                health.library = HealthCheck::Failed { reason };
+                return health;
+            }
+        };
+
+        let (highlights, injections, locals) =
+            match TreeSitterHighlighter::read_queries_at_path(folder) {
+                Ok(queries) => queries,
+                Err(reason) => {
+                    health.highlights_query = HealthCheck::Failed { reason };
+                    return health;
+                }
+            };
+        health.injections_query = Self::check_query(&language, &injections).0;
+        health.locals_query = Self::check_query(&language, &locals).0;
+        let (highlights_check, highlights_query) = Self::check_query(&language, &highlights);
+        health.highlights_query = highlights_check;
+        if let Some(query) = highlights_query {
+            health.unstyled_highlight_names = query
+                .capture_names()
+                .iter()
+                // Captures starting with _ are only used inside predicates
+                .filter(|name| !name.starts_with('_'))
+                .filter(|name| !theme.is_highlight_name_styled(name))
+                .map(|name| name.to_string())
+                .collect();
+        }
+        health
+    }
+
+    /// True if the grammar can be used for highlighting, unstyled names are not a problem
+    pub fn is_healthy(&self) -> bool {
+        !matches!(self.repository, RepositoryState::Broken { .. })
+            && self.library == HealthCheck::Ok
+            && self.highlights_query == HealthCheck::Ok
+            && !self.injections_query.is_failed()
+            && !self.locals_query.is_failed()
+    }
+
+    /// The repair action that has a chance to fix this grammar, if any
+    pub fn suggested_repair(&self) -> Option<RepairAction> {
+        if self.is_healthy() {
+            return None;
+        }
+        let has_generated_parser = self.folder.join("src").join("parser.c").exists();
+        match (&self.repository, &self.library) {
+            // A missing or corrupted library can be compiled again if we have the parser
+            (RepositoryState::Git { .. } | RepositoryState::NotGit, HealthCheck::Missing)
+            | (RepositoryState::Git { .. } | RepositoryState::NotGit, HealthCheck::Failed { .. })
+                if has_generated_parser =>
+            {
+                Some(RepairAction::Rebuild)
+            }
+            // Broken queries or repository may come from a corrupted folder, a fresh copy
+            // is the best we can do. If the queries are broken upstream, this will not help
+            _ => Some(RepairAction::Reinstall),
+        }
+    }
+
+    fn check_repository(folder: &Path) -> RepositoryState {
+        if folder.join(".git").exists() {
+            let revision = GitRepos::from_existing_folder(&folder.to_path_buf())
+                .and_then(|repos| repos.get_last_commit_hash())
+                .unwrap_or_default();
+            // An interrupted clone has a .git folder without any commit checked out
+            if revision.is_empty() || revision == "HEAD" {
+                return RepositoryState::Broken {
+                    reason: "The git repository has no commit checked out, the clone has probably been interrupted".to_string(),
+                };
+            }
+            if !folder.join("src").join("grammar.json").exists() {
+                return RepositoryState::Broken {
+                    reason: "src/grammar.json is missing".to_string(),
+                };
+            }
+            RepositoryState::Git { revision }
+        } else if folder.join("src").join("grammar.json").exists() {
+            RepositoryState::NotGit
+        } else {
+            RepositoryState::Broken {
+                reason: "src/grammar.json is missing".to_string(),
+            }
+        }
+    }
+
+    /// Load the library compiled by the Loader, or the precompiled one of a bundle, without
+    /// compiling anything. Returns None if there is no library yet.
+    fn load_compiled_library(folder: &Path) -> Result<Option<Language>, String> {
+        let name = grammar_name(folder)?;
+        if !folder.join("src").join("parser.c").exists() {
+            return load_precompiled_language(folder).map(Some);
+        }
+        let loader = Loader::new().map_err(|e| e.to_string())?;
+        let library = loader
+            .parser_lib_path
+            .join(format!("{name}.{DLL_EXTENSION}"));
+        if !library.exists() {
+            return Ok(None);
+        }
+        Loader::load_language(&library, &format!("tree_sitter_{}", name.replace('-', "_")))
+            .map(Some)
+            .map_err(|e| e.to_string())
+    }
+
+    /// Compile the query if not empty and give it back to inspect it
+    fn check_query(language: &Language, source: &str) -> (HealthCheck, Option<Query>) {
+        if source.trim().is_empty() {
+            return (HealthCheck::Missing, None);
+        }
+        match Query::new(language, source) {
+            Ok(query) => (HealthCheck::Ok, Some(query)),
+            Err(e) => (
+                HealthCheck::Failed {
+                    reason: format!("{:?} error at line {}: {}", e.kind, e.row + 1, e.message),
+                },
+                None,
+            ),
+        }
+    }
+}
+
+/// The health of all grammars in the grammars folder
+#[derive(Debug, Serialize, Clone, PartialEq, Eq)]
+pub struct DoctorReport {
+    pub grammars: Vec<GrammarHealth>,
+}
+
+impl DoctorReport {
+    /// The grammars that cannot be used for highlighting
+    pub fn broken(&self) -> Vec<&GrammarHealth> {
+        self.grammars.iter().filter(|g| !g.is_healthy()).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::create_dir_all;
+
+    use pretty_assertions::assert_eq;
+
+    use super::{GrammarHealth, HealthCheck, RepairAction, RepositoryState};
+    use crate::{
+        preview::tree_sitter_grammars::get_unique_local_tree_sitter_grammars_folder,
+        theming::theme::DEFAULT_THEME,
+        theming::{helix::ALL_HIGHLIGHT_NAMES_SUPPORTED_BY_HELIX, theme::Theme},
+        util::git::GitRepos,
+    };
+
+    #[test]
+    fn test_half_cloned_or_not_compiled_grammars_are_broken() {
+        let theme =
+            Theme::from_helix(DEFAULT_THEME, ALL_HIGHLIGHT_NAMES_SUPPORTED_BY_HELIX).unwrap();
+        let folder = get_unique_local_tree_sitter_grammars_folder().join("tree-sitter-fake");
+        create_dir_all(folder.join("src")).unwrap();
+        GitRepos::run_git_cmd(&vec!["init", "--quiet"], &folder).unwrap();
+        let health = GrammarHealth::check(&folder, &theme);
+        assert!(matches!(health.repository, RepositoryState::Broken { .. }));
+        assert_eq!(health.library, HealthCheck::Skipped);
+        assert_eq!(health.suggested_repair(), Some(RepairAction::Reinstall));
+
+        // A copied grammar never compiled yet only needs a rebuild
+        std::fs::remove_dir_all(folder.join(".git")).unwrap();
+        std::fs::write(
+            folder.join("src/grammar.json"),
+            r#"{"name": "fake_never_compiled"}"#,
+        )
+        .unwrap();
+        std::fs::write(folder.join("src/parser.c"), "").unwrap();
+        let health = GrammarHealth::check(&folder, &theme);
+        assert_eq!(health.lang, "fake");
+        assert_eq!(health.repository, RepositoryState::NotGit);
+        assert_eq!(health.library, HealthCheck::Missing);
+        assert!(!health.is_healthy());
+        assert_eq!(health.suggested_repair(), Some(RepairAction::Rebuild));
+    }
+}
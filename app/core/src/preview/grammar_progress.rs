@@ -0,0 +1,145 @@
+// Progress reporting and cancellation for long grammar operations, cloning and compiling a grammar
+// can take more than 10 seconds on slow connections or machines
+
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    mpsc::Sender,
+    Arc,
+};
+
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+/// The phases of an installation or an update, in this order
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[serde(tag = "phase", rename_all = "kebab-case")]
+pub enum GrammarProgress {
+    /// Checking the source or finding the local grammar to update
+    Resolving {
+        source: String,
+    },
+    /// A line of progress written by git clone, like "Receiving objects:  45% (450/1000)"
+    Cloning {
+        message: String,
+        percent: Option<u8>,
+    },
+    /// A line of progress written by git pull
+    Pulling {
+        message: String,
+        percent: Option<u8>,
+    },
+    /// The C compiler is running on the generated parser
+    Compiling,
+    /// The highlight, injections and locals queries are loaded to make sure they are valid
+    LoadingQueries,
+    Done,
+    Failed {
+        error: String,
+    },
+    /// The operation has been cancelled and everything fetched has been cleaned up
+    Cancelled,
+}
+
+static GIT_PROGRESS_PERCENT_REGEX: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"(\d{1,3})%").expect("GIT_PROGRESS_PERCENT_REGEX failed to compile"));
+
+impl GrammarProgress {
+    /// Extract the percentage of a git progress line, if any
+    pub(crate) fn git_percent(line: &str) -> Option<u8> {
+        GIT_PROGRESS_PERCENT_REGEX
+            .captures(line)
+            .and_then(|c| c[1].parse().ok())
+    }
+}
+
+/// Where progress events are sent during an operation, with a way to cancel it from another thread
+/// Clones share the same cancellation flag. The default sink sends events nowhere.
+#[derive(Debug, Clone, Default)]
+pub struct GrammarProgressSink {
+    sender: Option<Sender<GrammarProgress>>,
+    cancelled: Arc<AtomicBool>,
+}
+
+impl GrammarProgressSink {
+    /// Create a sink sending all events to the given channel
+    pub fn new(sender: Sender<GrammarProgress>) -> Self {
+        Self {
+            sender: Some(sender),
+            cancelled: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// Ask to stop the operation as soon as possible
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn cancelled_flag(&self) -> &AtomicBool {
+        &self.cancelled
+    }
+
+    /// Send an event, ignored if nobody is listening anymore
+    pub(crate) fn emit(&self, progress: GrammarProgress) {
+        if let Some(sender) = &self.sender {
+            let _ = sender.send(progress);
+        }
+    }
+
+    /// Return an error if the operation has been cancelled, to be used between phases
+    pub(crate) fn check_cancelled(&self) -> Result<(), String> {
+        if self.is_cancelled() {
+            Err("The operation has been cancelled".to_string())
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Send the final event depending on the result of the operation
+    pub(crate) fn finish<T>(&self, result: &Result<T, String>) {
+        self.emit(match result {
+            Ok(_) => GrammarProgress::Done,
+            Err(_) if self.is_cancelled() => GrammarProgress::Cancelled,
+            Err(error) => GrammarProgress::Failed {
+                error: error.clone(),
+            },
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc;
+
+    use pretty_assertions::assert_eq;
+
+    use super::{GrammarProgress, GrammarProgressSink};
+
+    #[test]
+    fn test_sink_sends_final_event_and_shares_cancellation() {
+        let (tx, rx) = mpsc::channel();
+        let sink = GrammarProgressSink::new(tx);
+        sink.finish(&Err::<(), String>("boom".to_string()));
+        sink.clone().cancel();
+        assert!(sink.check_cancelled().is_err());
+        sink.finish(&Err::<(), String>("cancelled".to_string()));
+        assert_eq!(
+            rx.try_iter().collect::<Vec<_>>(),
+            vec![
+                GrammarProgress::Failed {
+                    error: "boom".to_string()
+                },
+                GrammarProgress::Cancelled
+            ]
+        );
+        assert_eq!(
+            GrammarProgress::git_percent("Receiving objects:  45% (450/1000)"),
+            Some(45)
+        );
+        assert_eq!(GrammarProgress::git_percent("Cloning into 'a'..."), None);
+    }
+}
@@ -0,0 +1,352 @@
+use std::{
+    fmt::Display,
+    fs::{create_dir_all, read_to_string, remove_dir_all},
+    path::{Path, PathBuf},
+};
+
+use serde::Deserialize;
+use walkdir::WalkDir;
+
+use crate::{
+    preview::grammar_progress::{GrammarProgress, GrammarProgressSink},
+    util::{
+        archive::{extract_archive, ArchiveKind},
+        git::GitRepos,
+    },
+};
+
+/// All the places a Tree-Sitter grammar can be installed from. Git sources are cloned so they can
+/// be updated later, local folders and archives are copied or extracted in the grammars folder.
+/// This makes it possible to install grammars on machines without internet access.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GrammarSource {
+    /// A Git repository cloned via HTTPS, like "https://github.com/tree-sitter/tree-sitter-css".
+    /// Nested groups are supported, like "https://gitlab.company.com/tools/grammars/tree-sitter-css"
+    HttpsGit(String),
+    /// A Git repository cloned via SSH, like "git@github.com:tree-sitter/tree-sitter-css.git"
+    /// or "ssh://git@gitlab.company.com:2222/tools/tree-sitter-css.git"
+    SshGit(String),
+    /// A local folder containing a grammar, like an existing checkout of a grammar repository.
+    /// If this is a git repository, it is cloned, otherwise it is copied.
+    LocalFolder(PathBuf),
+    /// A local Git mirror given as a file:// URL, like "file:///srv/mirrors/tree-sitter-css.git"
+    LocalGitMirror(String),
+    /// A .tar.gz, .tgz or .zip archive of a grammar repository, like the ones generated for Git tags
+    Archive(PathBuf),
+}
+
+/// The few fields we need from the src/grammar.json generated by Tree-Sitter
+#[derive(Deserialize)]
+struct GrammarJson {
+    name: String,
+}
+
+impl GrammarSource {
+    /// Detect the kind of source behind a raw string given by the user
+    pub fn parse(raw: &str) -> Result<Self, String> {
+        let raw = raw.trim();
+        let source = if raw.starts_with("https://") {
+            GrammarSource::HttpsGit(raw.to_string())
+        } else if raw.starts_with("file://") {
+            GrammarSource::LocalGitMirror(raw.to_string())
+        } else if raw.starts_with("ssh://")
+            || GitRepos::validate_and_extract_repos_name_from_ssh_url(raw).is_ok()
+        {
+            GrammarSource::SshGit(raw.to_string())
+        } else if ArchiveKind::from_path(Path::new(raw)).is_some() {
+            GrammarSource::Archive(PathBuf::from(raw))
+        } else if Path::new(raw).is_dir() {
+            GrammarSource::LocalFolder(PathBuf::from(raw))
+        } else {
+            return Err(format!(
+                "Given grammar source {raw} is not a HTTPS, SSH or file:// git URL, nor an existing folder or a .tar.gz/.zip archive"
+            ));
+        };
+        source.validate()?;
+        Ok(source)
+    }
+
+    /// Make sure the source is valid before trying to fetch it
+    pub fn validate(&self) -> Result<(), String> {
+        match self {
+            GrammarSource::HttpsGit(url) => {
+                GitRepos::validate_and_extract_repos_name_from_https_url(url).map(|_| ())
+            }
+            GrammarSource::SshGit(url) => {
+                GitRepos::validate_and_extract_repos_name_from_ssh_url(url).map(|_| ())
+            }
+            GrammarSource::LocalGitMirror(url) => {
+                GitRepos::validate_and_extract_repos_name_from_file_url(url).map(|_| ())
+            }
+            GrammarSource::LocalFolder(path) => {
+                if !path.is_dir() {
+                    return Err(format!("The folder {path:?} doesn't exist"));
+                }
+                if !path.join("src").join("grammar.json").exists() {
+                    return Err(format!(
+                        "The folder {path:?} doesn't look like a Tree-Sitter grammar, src/grammar.json is missing"
+                    ));
+                }
+                Ok(())
+            }
+            GrammarSource::Archive(path) => {
+                if ArchiveKind::from_path(path).is_none() {
+                    return Err(format!(
+                        "The archive {path:?} must be a .tar.gz, .tgz or .zip file"
+                    ));
+                }
+                if !path.is_file() {
+                    return Err(format!("The archive {path:?} doesn't exist"));
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Fetch the grammar inside the grammars folder, if not already present, and return the final
+    /// folder of this grammar. Git sources are cloned with only the last commit of a single branch.
+    /// The git progress is sent to the given sink, which can cancel the clone.
+    pub(crate) fn fetch_into(
+        &self,
+        grammars_folder: &Path,
+        progress: &GrammarProgressSink,
+    ) -> Result<PathBuf, String> {
+        self.validate()?;
+        let grammars_folder = grammars_folder.to_path_buf();
+        match self {
+            GrammarSource::HttpsGit(url)
+            | GrammarSource::SshGit(url)
+            | GrammarSource::LocalGitMirror(url) => {
+                let repos_name = GitRepos::validate_and_extract_repos_name(url)?;
+                Self::clone_if_missing(url, &grammars_folder, &repos_name, Some(1), progress)
+            }
+            GrammarSource::LocalFolder(path) => {
+                // The relative paths are given from the current folder, but git runs inside the
+                // grammars folder
+                let path = path
+                    .canonicalize()
+                    .map_err(|e| format!("Couldn't find the folder {path:?}: {e}"))?;
+                let destination =
+                    grammars_folder.join(format!("tree-sitter-{}", grammar_name(&path)?));
+                if destination.exists() {
+                    return Ok(destination);
+                }
+                if path.join(".git").exists() {
+                    // --depth is ignored by git for local clones, the whole history is cloned.
+                    // The clone is made in a staging folder, as it is named like the source folder.
+                    let raw_path = path.to_str().ok_or("The folder path is not valid UTF8")?;
+                    let staging = grammars_folder.join(".staging-local");
+                    if staging.exists() {
+                        remove_dir_all(&staging).map_err(|e| e.to_string())?;
+                    }
+                    create_dir_all(&staging).map_err(|e| e.to_string())?;
+                    let repos_name = GitRepos::validate_and_extract_repos_name(raw_path)?;
+                    let result =
+                        Self::clone_if_missing(raw_path, &staging, &repos_name, None, progress)
+                            .and_then(|clone| {
+                                std::fs::rename(&clone, &destination).map_err(|e| {
+                                    format!("Couldn't move cloned grammar to {destination:?}: {e}")
+                                })
+                            });
+                    let _ = remove_dir_all(&staging);
+                    result.map(|_| destination)
+                } else {
+                    copy_folder(&path, &destination)?;
+                    Ok(destination)
+                }
+            }
+            GrammarSource::Archive(path) => {
+                // We cannot know the folder name before extraction, as archives of Git tags
+                // generally contain a "tree-sitter-css-0.23.0" folder. We extract it in a staging
+                // folder and then use the grammar name found in src/grammar.json
+                let staging = grammars_folder.join(".staging-archive");
+                if staging.exists() {
+                    remove_dir_all(&staging).map_err(|e| e.to_string())?;
+                }
+                let result = extract_archive(path, &staging)
+                    .and_then(|_| Self::find_grammar_root(&staging))
+                    .and_then(|root| {
+                        let destination =
+                            grammars_folder.join(format!("tree-sitter-{}", grammar_name(&root)?));
+                        if !destination.exists() {
+                            std::fs::rename(&root, &destination).map_err(|e| {
+                                format!("Couldn't move extracted grammar to {destination:?}: {e}")
+                            })?;
+                        }
+                        Ok(destination)
+                    });
+                let _ = remove_dir_all(&staging);
+                result
+            }
+        }
+    }
+
+    /// Clone the repository only if it is not already present
+    /// Note: we consider 2 repositories with the name folder name to be equivalent for now
+    fn clone_if_missing(
+        url: &str,
+        grammars_folder: &PathBuf,
+        repos_name: &str,
+        only_latest_commits: Option<usize>,
+        progress: &GrammarProgressSink,
+    ) -> Result<PathBuf, String> {
+        let repos = match GitRepos::from_existing_folder(&grammars_folder.join(repos_name)) {
+            Ok(repos) => repos,
+            Err(_) => GitRepos::from_clone_with_progress(
+                url,
+                grammars_folder,
+                only_latest_commits,
+                true,
+                &|line| {
+                    progress.emit(GrammarProgress::Cloning {
+                        message: line.to_string(),
+                        percent: GrammarProgress::git_percent(line),
+                    })
+                },
+                progress.cancelled_flag(),
+            )?,
+        };
+        Ok(repos.path().clone())
+    }
+
+    /// Find the folder containing src/grammar.json, at the root or inside the single top level folder
+    fn find_grammar_root(extracted: &Path) -> Result<PathBuf, String> {
+        if extracted.join("src").join("grammar.json").exists() {
+            return Ok(extracted.to_path_buf());
+        }
+        let entries: Vec<PathBuf> = std::fs::read_dir(extracted)
+            .map_err(|e| e.to_string())?
+            .filter_map(|e| e.ok().map(|e| e.path()))
+            .filter(|p| p.is_dir())
+            .collect();
+        match entries.as_slice() {
+            [single] if single.join("src").join("grammar.json").exists() => Ok(single.clone()),
+            _ => Err(
+                "The archive doesn't contain a Tree-Sitter grammar, src/grammar.json is missing"
+                    .to_string(),
+            ),
+        }
+    }
+}
+
+impl Display for GrammarSource {
+    /// The raw source as given by the user
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            GrammarSource::HttpsGit(url)
+            | GrammarSource::SshGit(url)
+            | GrammarSource::LocalGitMirror(url) => write!(f, "{url}"),
+            GrammarSource::LocalFolder(path) | GrammarSource::Archive(path) => {
+                write!(f, "{}", path.display())
+            }
+        }
+    }
+}
+
+/// Read the grammar name inside src/grammar.json of the given grammar folder
+pub(crate) fn grammar_name(grammar_folder: &Path) -> Result<String, String> {
+    let path = grammar_folder.join("src").join("grammar.json");
+    let content = read_to_string(&path).map_err(|e| format!("Couldn't read {path:?}: {e}"))?;
+    let json: GrammarJson =
+        serde_json::from_str(&content).map_err(|e| format!("Invalid {path:?}: {e}"))?;
+    validate_grammar_name(&json.name)?;
+    Ok(json.name)
+}
+
+/// Make sure a grammar name coming from a grammar or a bundle can be used in a folder name,
+/// like "tree-sitter-{name}", without going outside of the grammars folder
+pub(crate) fn validate_grammar_name(name: &str) -> Result<(), String> {
+    let valid = !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_' || c == '-');
+    if valid {
+        Ok(())
+    } else {
+        Err(format!(
+            "Invalid grammar name {name:?}, only a-z, 0-9, _ and - are allowed"
+        ))
+    }
+}
+
+/// Recursively copy a folder, without the .git folder if any
+fn copy_folder(from: &Path, to: &Path) -> Result<(), String> {
+    for entry in WalkDir::new(from)
+        .into_iter()
+        .filter_entry(|e| e.file_name() != ".git")
+    {
+        let entry = entry.map_err(|e| e.to_string())?;
+        let relative = entry.path().strip_prefix(from).map_err(|e| e.to_string())?;
+        let target = to.join(relative);
+        if entry.file_type().is_dir() {
+            create_dir_all(&target).map_err(|e| format!("Couldn't create {target:?}: {e}"))?;
+        } else {
+            std::fs::copy(entry.path(), &target)
+                .map_err(|e| format!("Couldn't copy {:?}: {e}", entry.path()))?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs::create_dir_all, path::PathBuf};
+
+    use pretty_assertions::assert_eq;
+
+    use super::GrammarSource;
+    use crate::preview::tree_sitter_grammars::get_unique_local_tree_sitter_grammars_folder;
+
+    #[test]
+    fn test_grammar_sources_are_detected() {
+        assert_eq!(
+            GrammarSource::parse("https://github.com/tree-sitter/tree-sitter-css").unwrap(),
+            GrammarSource::HttpsGit("https://github.com/tree-sitter/tree-sitter-css".to_string())
+        );
+        assert_eq!(
+            GrammarSource::parse("git@gitlab.company.com:tools/grammars/tree-sitter-css.git")
+                .unwrap(),
+            GrammarSource::SshGit(
+                "git@gitlab.company.com:tools/grammars/tree-sitter-css.git".to_string()
+            )
+        );
+        assert_eq!(
+            GrammarSource::parse("file:///srv/mirrors/tree-sitter-css.git").unwrap(),
+            GrammarSource::LocalGitMirror("file:///srv/mirrors/tree-sitter-css.git".to_string())
+        );
+        assert!(GrammarSource::parse("https://github.com/tree-sitter").is_err());
+        assert!(GrammarSource::parse("/not/existing/tree-sitter-css-0.23.0.tar.gz").is_err());
+        assert!(GrammarSource::parse("not a source").is_err());
+    }
+
+    #[test]
+    fn test_local_folder_source_must_contain_a_grammar() {
+        let folder = get_unique_local_tree_sitter_grammars_folder().join("fake-checkout");
+        create_dir_all(folder.join("src")).unwrap();
+        assert!(GrammarSource::parse(folder.to_str().unwrap()).is_err());
+
+        std::fs::write(folder.join("src/grammar.json"), r#"{"name": "fake"}"#).unwrap();
+        assert_eq!(
+            GrammarSource::parse(folder.to_str().unwrap()).unwrap(),
+            GrammarSource::LocalFolder(PathBuf::from(folder.to_str().unwrap()))
+        );
+
+        // The folder is copied in the grammars folder, named after the grammar
+        let grammars_folder = get_unique_local_tree_sitter_grammars_folder();
+        let installed = GrammarSource::LocalFolder(folder.clone())
+            .fetch_into(&grammars_folder, &Default::default())
+            .unwrap();
+        assert_eq!(installed, grammars_folder.join("tree-sitter-fake"));
+        assert!(installed.join("src/grammar.json").exists());
+
+        // A grammar name cannot go outside of the grammars folder
+        std::fs::write(folder.join("src/grammar.json"), r#"{"name": "../../x"}"#).unwrap();
+        let error = GrammarSource::LocalFolder(folder)
+            .fetch_into(&grammars_folder, &Default::default())
+            .unwrap_err();
+        assert_eq!(
+            error,
+            "Invalid grammar name \"../../x\", only a-z, 0-9, _ and - are allowed"
+        );
+    }
+}
@@ -0,0 +1,362 @@
+// Supply-chain trust policy for Tree-Sitter grammars. Grammars are C code compiled and loaded in
+// our process, so we restrict where they can come from, we remember what we compiled and we
+// refuse to silently compile new generated code on update.
+// Everything is stored next to the grammars, inside the grammars folder
+// trust.toml        -> the GrammarTrustPolicy, edited by the user, defaults are used if missing
+// trust-lock.json   -> the GrammarTrustStore, the revision and checksum of each installed grammar
+// audit.log         -> one AuditEntry in JSON per line, only appended
+
+use std::{
+    collections::BTreeMap,
+    fs::{read_to_string, OpenOptions},
+    io::Write,
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::preview::{
+    grammar_bundle::{checksums_of_folder, sha256_hex},
+    grammar_sources::GrammarSource,
+};
+
+const POLICY_FILENAME: &str = "trust.toml";
+const STORE_FILENAME: &str = "trust-lock.json";
+const AUDIT_LOG_FILENAME: &str = "audit.log";
+
+/// Which grammars sources can be installed and how updates are accepted
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(default)]
+pub struct GrammarTrustPolicy {
+    /// Hosts allowed for HTTPS and SSH git sources, like "github.com". Empty means any host
+    pub allowed_hosts: Vec<String>,
+    /// Organisations allowed for HTTPS and SSH git sources, this is the first segment of the
+    /// path, like "tree-sitter" in "https://github.com/tree-sitter/tree-sitter-css".
+    /// Empty means any organisation
+    pub allowed_organisations: Vec<String>,
+    /// Allow local folders, file:// mirrors, archives and precompiled bundles
+    pub allow_local_sources: bool,
+    /// Refuse to recompile a grammar on update when its generated files in src/ have changed,
+    /// until the new revision is approved via `approve_update()`
+    pub require_approval_for_generated_changes: bool,
+}
+
+impl Default for GrammarTrustPolicy {
+    /// Only the official grammars proposed in `proposed_grammars.rs` are allowed by default
+    fn default() -> Self {
+        Self {
+            allowed_hosts: vec!["github.com".to_string()],
+            allowed_organisations: vec![
+                "tree-sitter".to_string(),
+                "tree-sitter-grammars".to_string(),
+            ],
+            allow_local_sources: true,
+            require_approval_for_generated_changes: true,
+        }
+    }
+}
+
+impl GrammarTrustPolicy {
+    /// Load the policy from the trust.toml of the grammars folder, or the default one if missing
+    pub fn load(grammars_folder: &Path) -> Result<Self, String> {
+        let path = grammars_folder.join(POLICY_FILENAME);
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let content = read_to_string(&path).map_err(|e| format!("Couldn't read {path:?}: {e}"))?;
+        toml::from_str(&content).map_err(|e| format!("Invalid trust policy in {path:?}: {e}"))
+    }
+
+    /// Save the policy in the trust.toml of the grammars folder
+    pub fn save(&self, grammars_folder: &Path) -> Result<(), String> {
+        let path = grammars_folder.join(POLICY_FILENAME);
+        let content = toml::to_string_pretty(self).map_err(|e| e.to_string())?;
+        std::fs::write(&path, content).map_err(|e| format!("Couldn't write {path:?}: {e}"))
+    }
+
+    /// Make sure the given source is allowed before fetching anything
+    pub fn check_source(&self, source: &GrammarSource) -> Result<(), String> {
+        match source {
+            GrammarSource::HttpsGit(url) | GrammarSource::SshGit(url) => {
+                let (host, organisation) = remote_host_and_organisation(url).ok_or_else(|| {
+                    format!("Couldn't extract the host and organisation of {url}")
+                })?;
+                if !self.allowed_hosts.is_empty()
+                    && !self
+                        .allowed_hosts
+                        .iter()
+                        .any(|h| h.eq_ignore_ascii_case(&host))
+                {
+                    return Err(format!(
+                        "The host {host} of {url} is not allowed by the grammars trust policy, allowed hosts: {}",
+                        self.allowed_hosts.join(", ")
+                    ));
+                }
+                if !self.allowed_organisations.is_empty()
+                    && !self.allowed_organisations.contains(&organisation)
+                {
+                    return Err(format!(
+                        "The organisation {organisation} of {url} is not allowed by the grammars trust policy, allowed organisations: {}",
+                        self.allowed_organisations.join(", ")
+                    ));
+                }
+                Ok(())
+            }
+            GrammarSource::LocalFolder(_)
+            | GrammarSource::LocalGitMirror(_)
+            | GrammarSource::Archive(_) => self.check_local_sources_allowed(),
+        }
+    }
+
+    /// Local sources include precompiled bundles, which are not a `GrammarSource`
+    pub fn check_local_sources_allowed(&self) -> Result<(), String> {
+        if self.allow_local_sources {
+            Ok(())
+        } else {
+            Err("Local grammars sources are not allowed by the grammars trust policy".to_string())
+        }
+    }
+}
+
+/// Extract the host and first path segment of an HTTPS or SSH git URL
+/// "git@github.com:tree-sitter/tree-sitter-css.git" gives ("github.com", "tree-sitter")
+fn remote_host_and_organisation(url: &str) -> Option<(String, String)> {
+    let (host_and_port, path) = if let Some(rest) = url.strip_prefix("https://") {
+        rest.split_once('/')?
+    } else if let Some(rest) = url.strip_prefix("ssh://") {
+        rest.split_once('@')
+            .map(|(_, r)| r)
+            .unwrap_or(rest)
+            .split_once('/')?
+    } else {
+        url.split_once('@')?.1.split_once(':')?
+    };
+    let host = host_and_port.split(':').next()?.to_lowercase();
+    let organisation = path.split('/').next()?.to_string();
+    if host.is_empty() || organisation.is_empty() {
+        return None;
+    }
+    Some((host, organisation))
+}
+
+/// What we know about an installed grammar at the time it was compiled
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct TrustedGrammar {
+    /// The raw source given at installation, like a git URL or a local path
+    pub source: String,
+    /// The commit hash of the compiled code, None for grammars not installed via Git
+    pub revision: Option<String>,
+    /// A SHA-256 over all files in src/, these are mostly generated by `tree-sitter generate`
+    pub source_checksum: String,
+    /// A revision approved by the user even if it changes generated files
+    pub approved_revision: Option<String>,
+}
+
+/// The trusted state of all installed grammars, indexed by their folder name
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct GrammarTrustStore {
+    pub grammars: BTreeMap<String, TrustedGrammar>,
+}
+
+impl GrammarTrustStore {
+    /// Load the store from the grammars folder, empty if it doesn't exist yet
+    pub fn load(grammars_folder: &Path) -> Result<Self, String> {
+        let path = grammars_folder.join(STORE_FILENAME);
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let content = read_to_string(&path).map_err(|e| format!("Couldn't read {path:?}: {e}"))?;
+        serde_json::from_str(&content).map_err(|e| format!("Invalid {path:?}: {e}"))
+    }
+
+    /// Save the store in the grammars folder
+    pub fn save(&self, grammars_folder: &Path) -> Result<(), String> {
+        let path = grammars_folder.join(STORE_FILENAME);
+        let content = serde_json::to_string_pretty(self).map_err(|e| e.to_string())?;
+        std::fs::write(&path, content).map_err(|e| format!("Couldn't write {path:?}: {e}"))
+    }
+}
+
+/// The checksum of all files in the src/ folder of a grammar, where the generated parser.c,
+/// the hand written scanner.c and the grammar.json live
+pub fn generated_files_checksum(grammar_folder: &Path) -> Result<String, String> {
+    let checksums = checksums_of_folder(&grammar_folder.join("src"))?;
+    let summary = checksums
+        .iter()
+        .map(|(file, checksum)| format!("{checksum}  {file}\n"))
+        .collect::<String>();
+    Ok(sha256_hex(summary.as_bytes()))
+}
+
+/// The kind of operation recorded in the audit log
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum AuditAction {
+    Install,
+    InstallRefused,
+    Update,
+    UpdateRefused,
+    UpdateRolledBack,
+    ApproveUpdate,
+    ImportBundle,
+    Delete,
+}
+
+/// A line of the audit log
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct AuditEntry {
+    /// UTC time in RFC 3339 format, like "2025-06-01T14:03:12Z"
+    pub timestamp: String,
+    pub action: AuditAction,
+    /// The grammar folder name like "tree-sitter-css", or the raw source when refused
+    pub grammar: String,
+    pub previous_revision: Option<String>,
+    pub revision: Option<String>,
+    /// A free text to understand why, like the reason of a refusal
+    pub details: String,
+}
+
+impl AuditEntry {
+    /// Create an entry with the current time
+    pub fn now(action: AuditAction, grammar: &str) -> Self {
+        let seconds = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        Self {
+            timestamp: format_utc_timestamp(seconds),
+            action,
+            grammar: grammar.to_string(),
+            previous_revision: None,
+            revision: None,
+            details: String::new(),
+        }
+    }
+
+    pub fn with_revisions(mut self, previous: Option<String>, current: Option<String>) -> Self {
+        self.previous_revision = previous;
+        self.revision = current;
+        self
+    }
+
+    pub fn with_details(mut self, details: &str) -> Self {
+        self.details = details.to_string();
+        self
+    }
+}
+
+/// Append an entry at the end of the audit log of the grammars folder
+pub fn append_audit_entry(grammars_folder: &Path, entry: &AuditEntry) -> Result<(), String> {
+    let path = grammars_folder.join(AUDIT_LOG_FILENAME);
+    let line = serde_json::to_string(entry).map_err(|e| e.to_string())?;
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .and_then(|mut file| writeln!(file, "{line}"))
+        .map_err(|e| format!("Couldn't append to audit log {path:?}: {e}"))
+}
+
+/// Read all entries of the audit log of the grammars folder, oldest first
+pub fn read_audit_log(grammars_folder: &Path) -> Result<Vec<AuditEntry>, String> {
+    let path = grammars_folder.join(AUDIT_LOG_FILENAME);
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    read_to_string(&path)
+        .map_err(|e| format!("Couldn't read audit log {path:?}: {e}"))?
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| serde_json::from_str(line).map_err(|e| format!("Invalid audit entry: {e}")))
+        .collect()
+}
+
+/// Format a UNIX timestamp as RFC 3339 in UTC, without pulling a date library for that
+/// The date conversion is the days_from_civil algorithm from Howard Hinnant, in reverse
+fn format_utc_timestamp(seconds: u64) -> String {
+    let days = (seconds / 86400) as i64;
+    let time = seconds % 86400;
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}Z",
+        time / 3600,
+        time % 3600 / 60,
+        time % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use pretty_assertions::assert_eq;
+
+    use super::{format_utc_timestamp, remote_host_and_organisation, GrammarTrustPolicy};
+    use crate::preview::grammar_sources::GrammarSource;
+
+    #[test]
+    fn test_default_policy_only_allows_official_grammars() {
+        let policy = GrammarTrustPolicy::default();
+        let allowed = [
+            "https://github.com/tree-sitter/tree-sitter-css",
+            "https://github.com/tree-sitter-grammars/tree-sitter-lua.git",
+            "git@github.com:tree-sitter/tree-sitter-css.git",
+        ];
+        for url in allowed {
+            assert!(
+                policy
+                    .check_source(&GrammarSource::parse(url).unwrap())
+                    .is_ok(),
+                "{url} should be allowed"
+            );
+        }
+        let refused = [
+            "https://github.com/someone/tree-sitter-css",
+            "https://gitlab.com/tree-sitter/tree-sitter-css",
+            "ssh://git@gitlab.company.com:2222/tree-sitter/tree-sitter-css.git",
+        ];
+        for url in refused {
+            assert!(
+                policy
+                    .check_source(&GrammarSource::parse(url).unwrap())
+                    .is_err(),
+                "{url} should be refused"
+            );
+        }
+
+        let archive = GrammarSource::Archive(PathBuf::from("tree-sitter-css.tar.gz"));
+        assert!(policy.check_source(&archive).is_ok());
+        let policy = GrammarTrustPolicy {
+            allow_local_sources: false,
+            ..Default::default()
+        };
+        assert!(policy.check_source(&archive).is_err());
+    }
+
+    #[test]
+    fn test_remote_host_and_organisation_and_timestamps() {
+        assert_eq!(
+            remote_host_and_organisation("ssh://git@gitlab.company.com:2222/tools/tree-sitter-css"),
+            Some(("gitlab.company.com".to_string(), "tools".to_string()))
+        );
+        assert_eq!(
+            remote_host_and_organisation("https://GitHub.com:443/tree-sitter/tree-sitter-css"),
+            Some(("github.com".to_string(), "tree-sitter".to_string()))
+        );
+        assert_eq!(format_utc_timestamp(0), "1970-01-01T00:00:00Z");
+        assert_eq!(
+            format_utc_timestamp(951782400 + 3661),
+            "2000-02-29T01:01:01Z"
+        );
+    }
+}
@@ -0,0 +1,45 @@
+// Types used to update grammars, one by one or all at once via
+// TreeSitterGrammarsManager::update_all()
+
+use serde::Serialize;
+
+use crate::{preview::grammar_trust::TrustedGrammar, util::git::GitRepos};
+
+/// What happened to a grammar during `update_all()`
+#[derive(Debug, Serialize, Clone, PartialEq, Eq)]
+#[serde(tag = "outcome", rename_all = "kebab-case")]
+pub enum UpdateOutcome {
+    /// Nothing new to pull
+    UpToDate,
+    /// New commits have been pulled, compiled and their queries loaded
+    Updated,
+    /// The new revision changes generated files and must be approved first, see the trust policy
+    Refused { reason: String },
+    /// The new revision failed to compile or its queries are broken, the previous revision
+    /// has been restored and compiled again
+    RolledBack { reason: String },
+    /// The grammar couldn't be pulled, like a grammar not installed via Git or a network error
+    Failed { reason: String },
+}
+
+/// The change report of a single grammar
+#[derive(Debug, Serialize, Clone, PartialEq, Eq)]
+pub struct GrammarUpdate {
+    pub lang: String,
+    /// The commit before the update, None if not a Git repository
+    pub previous_revision: Option<String>,
+    /// The new commit pulled, even if it has been refused or rolled back. This is the previous
+    /// commit if there was nothing new
+    pub revision: Option<String>,
+    pub outcome: UpdateOutcome,
+}
+
+/// The state of a grammar before pulling, to check and possibly revert what has been pulled
+pub(crate) struct PendingUpdate {
+    pub lang: String,
+    pub repos: GitRepos,
+    pub folder_name: String,
+    pub previous_revision: String,
+    pub previous_checksum: String,
+    pub record: Option<TrustedGrammar>,
+}
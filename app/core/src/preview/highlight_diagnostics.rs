@@ -0,0 +1,104 @@
+// Why a code block has not been highlighted. Without them, a missing grammar or a broken query
+// just gives a code block without colour and nobody knows why.
+
+use std::{collections::BTreeMap, fmt};
+
+use serde::Serialize;
+
+/// The reason why a code block couldn't be highlighted
+#[derive(Debug, Serialize, Clone, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(tag = "kind", rename_all = "kebab-case")]
+pub enum HighlightError {
+    /// No grammar installed or built-in for this lang
+    GrammarMissing,
+    /// The grammar is installed but its library couldn't be compiled or loaded
+    GrammarLoadError { message: String },
+    /// The queries of the grammar, or their overrides, don't compile
+    QueryCompileError { message: String },
+    /// Parsing took too long and has been cancelled
+    ParseTimeout,
+    /// The other code blocks of the document used all its highlight time
+    DocumentHighlightTimeout,
+    /// The code is bigger than the limit of the `PreviewBudget`
+    CodeBlockTooLarge { size: usize, limit: usize },
+    /// The highlighter or the HTML renderer failed on this code
+    HighlightFailed { message: String },
+}
+
+impl fmt::Display for HighlightError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HighlightError::GrammarMissing => write!(f, "grammar not installed"),
+            HighlightError::GrammarLoadError { message } => {
+                write!(f, "grammar couldn't be loaded: {message}")
+            }
+            HighlightError::QueryCompileError { message } => {
+                write!(f, "queries don't compile: {message}")
+            }
+            HighlightError::ParseTimeout => write!(f, "parsing took too long"),
+            HighlightError::DocumentHighlightTimeout => {
+                write!(f, "the document took too long to highlight")
+            }
+            HighlightError::CodeBlockTooLarge { size, limit } => {
+                write!(f, "{size} bytes of code is over the limit of {limit} bytes")
+            }
+            HighlightError::HighlightFailed { message } => {
+                write!(f, "highlighting failed: {message}")
+            }
+        }
+    }
+}
+
+/// A code block not highlighted, its code has been escaped and rendered without colour
+#[derive(Debug, Serialize, Clone, PartialEq, Eq)]
+pub struct HighlightDiagnostic {
+    /// The lang as written after the opening backticks
+    pub lang: String,
+    pub error: HighlightError,
+}
+
+/// Group identical diagnostics into human readable lines like
+/// "3 code blocks in `lua` not highlighted: grammar not installed"
+pub fn summarize_diagnostics(diagnostics: &[HighlightDiagnostic]) -> Vec<String> {
+    let mut counts: BTreeMap<(&str, &HighlightError), usize> = BTreeMap::new();
+    for diagnostic in diagnostics {
+        *counts
+            .entry((diagnostic.lang.as_str(), &diagnostic.error))
+            .or_default() += 1;
+    }
+    counts
+        .into_iter()
+        .map(|((lang, error), count)| {
+            let blocks = if count == 1 { "block" } else { "blocks" };
+            format!("{count} code {blocks} in `{lang}` not highlighted: {error}")
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::{summarize_diagnostics, HighlightDiagnostic, HighlightError};
+
+    #[test]
+    fn test_diagnostics_are_grouped_by_lang_and_error() {
+        let diagnostic = |lang: &str, error: HighlightError| HighlightDiagnostic {
+            lang: lang.to_string(),
+            error,
+        };
+        let diagnostics = vec![
+            diagnostic("lua", HighlightError::GrammarMissing),
+            diagnostic("c", HighlightError::ParseTimeout),
+            diagnostic("lua", HighlightError::GrammarMissing),
+            diagnostic("lua", HighlightError::GrammarMissing),
+        ];
+        assert_eq!(
+            summarize_diagnostics(&diagnostics),
+            vec![
+                "1 code block in `c` not highlighted: parsing took too long",
+                "3 code blocks in `lua` not highlighted: grammar not installed",
+            ]
+        );
+    }
+}
@@ -0,0 +1,210 @@
+// Global cache of TreeSitterHighlighter, as creating one needs to load the grammar library and
+// compile its queries, which is way slower than highlighting a code snippet.
+// Managers are created on demand (like in each Tauri command), so the cache cannot live in
+// the manager itself. Instead, entries are keyed by grammars folder and lang, and the manager
+// evicts the entries of a grammar when it is installed, updated or deleted. The entries created
+// before a change in the query overrides are created again.
+
+use std::{
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::SystemTime,
+};
+
+use mini_moka::sync::{Cache, ConcurrentCacheExt};
+use once_cell::sync::Lazy;
+use serde::Serialize;
+
+use super::{
+    highlight_diagnostics::HighlightError, query_overrides::last_overrides_change,
+    tree_sitter_grammars::TreeSitterGrammarsManager, tree_sitter_highlight::TreeSitterHighlighter,
+};
+
+/// The maximum number of highlighters kept in memory, the least used ones are evicted first
+pub const HIGHLIGHTER_CACHE_CAPACITY: u64 = 64;
+
+/// The cache used by the preview and highlight_code()
+pub static HIGHLIGHTER_CACHE: Lazy<HighlighterCache> =
+    Lazy::new(|| HighlighterCache::new(HIGHLIGHTER_CACHE_CAPACITY));
+
+/// Some numbers to understand how useful the cache is
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq)]
+pub struct HighlighterCacheStats {
+    pub entries: u64,
+    pub capacity: u64,
+    pub hits: u64,
+    /// Including the failed creations, like for a lang without grammar
+    pub misses: u64,
+}
+
+/// A highlighter and the last change of the query overrides when it was created
+#[derive(Clone)]
+struct CachedHighlighter {
+    highlighter: Arc<TreeSitterHighlighter>,
+    overrides_change: Option<SystemTime>,
+}
+
+/// A bounded cache of highlighters indexed by grammars folder and normalized lang
+pub struct HighlighterCache {
+    cache: Cache<(PathBuf, String), CachedHighlighter>,
+    capacity: u64,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl HighlighterCache {
+    pub fn new(capacity: u64) -> Self {
+        HighlighterCache {
+            cache: Cache::new(capacity),
+            capacity,
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    /// Get the cached highlighter of this lang for the grammars folder of the manager,
+    /// or create it and save it in cache. A highlighter older than the query overrides is
+    /// created again.
+    pub fn get_or_create(
+        &self,
+        manager: &TreeSitterGrammarsManager,
+        lang: &str,
+    ) -> Result<Arc<TreeSitterHighlighter>, HighlightError> {
+        let key = Self::key(manager.get_grammars_folder(), lang);
+        let overrides_change = last_overrides_change(&manager.get_queries_folder());
+        if let Some(cached) = self.cache.get(&key) {
+            if cached.overrides_change == overrides_change {
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                return Ok(cached.highlighter);
+            }
+        }
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        let highlighter = Arc::new(TreeSitterHighlighter::try_new(&key.1, manager)?);
+        self.cache.insert(
+            key,
+            CachedHighlighter {
+                highlighter: highlighter.clone(),
+                overrides_change,
+            },
+        );
+        Ok(highlighter)
+    }
+
+    /// Forget the highlighter of a lang, to be called when its grammar changes
+    pub fn evict(&self, grammars_folder: &Path, lang: &str) {
+        self.cache.invalidate(&Self::key(grammars_folder, lang));
+    }
+
+    /// Forget all highlighters of a grammars folder
+    pub fn evict_grammars_folder(&self, grammars_folder: &Path) {
+        // Keys are collected first because updating the cache while iterating can deadlock
+        let keys: Vec<(PathBuf, String)> = self
+            .cache
+            .iter()
+            .filter(|entry| entry.key().0 == grammars_folder)
+            .map(|entry| entry.key().clone())
+            .collect();
+        for key in keys {
+            self.cache.invalidate(&key);
+        }
+    }
+
+    /// Forget all highlighters and reset the statistics
+    pub fn clear(&self) {
+        self.cache.invalidate_all();
+        self.hits.store(0, Ordering::Relaxed);
+        self.misses.store(0, Ordering::Relaxed);
+    }
+
+    pub fn stats(&self) -> HighlighterCacheStats {
+        // Without sync(), the entry count can miss recent insertions and evictions
+        self.cache.sync();
+        HighlighterCacheStats {
+            entries: self.cache.entry_count(),
+            capacity: self.capacity,
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+        }
+    }
+
+    fn key(grammars_folder: &Path, lang: &str) -> (PathBuf, String) {
+        (
+            grammars_folder.to_path_buf(),
+            TreeSitterHighlighter::normalize_lang(lang),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{create_dir_all, write};
+
+    use pretty_assertions::assert_eq;
+
+    use super::HighlighterCache;
+    use crate::preview::tree_sitter_grammars::{
+        get_unique_local_tree_sitter_grammars_folder, TreeSitterGrammarsManager,
+    };
+
+    #[test]
+    fn test_missing_grammars_are_not_cached() {
+        let m = TreeSitterGrammarsManager::new_with_grammars_folder(
+            get_unique_local_tree_sitter_grammars_folder(),
+        )
+        .unwrap();
+        let cache = HighlighterCache::new(2);
+        assert!(cache.get_or_create(&m, "zonk").is_err());
+        assert!(cache.get_or_create(&m, "zonk").is_err());
+        let stats = cache.stats();
+        assert_eq!((stats.entries, stats.hits, stats.misses), (0, 0, 2));
+        cache.clear();
+        assert_eq!(cache.stats().misses, 0);
+    }
+
+    #[test]
+    #[cfg(feature = "builtin-grammars")]
+    fn test_highlighters_are_cached_until_evicted() {
+        let m = TreeSitterGrammarsManager::new_with_grammars_folder(
+            get_unique_local_tree_sitter_grammars_folder(),
+        )
+        .unwrap();
+        let cache = HighlighterCache::new(2);
+        cache.get_or_create(&m, "rust").unwrap();
+        cache.get_or_create(&m, "rs").unwrap();
+        assert_eq!(cache.stats().hits, 1);
+
+        cache.evict(m.get_grammars_folder(), "rust");
+        cache.get_or_create(&m, "rust").unwrap();
+        cache.get_or_create(&m, "css").unwrap();
+        let stats = cache.stats();
+        assert_eq!((stats.entries, stats.hits, stats.misses), (2, 1, 3));
+
+        // The cache is bounded
+        cache.get_or_create(&m, "json").unwrap();
+        assert_eq!(cache.stats().entries, 2);
+
+        cache.evict_grammars_folder(m.get_grammars_folder());
+        assert_eq!(cache.stats().entries, 0);
+    }
+
+    #[test]
+    #[cfg(feature = "builtin-grammars")]
+    fn test_highlighters_are_created_again_after_a_query_override() {
+        let m = TreeSitterGrammarsManager::new_with_grammars_folder(
+            get_unique_local_tree_sitter_grammars_folder(),
+        )
+        .unwrap();
+        let cache = HighlighterCache::new(2);
+        cache.get_or_create(&m, "rust").unwrap();
+        let rust_queries = m.get_queries_folder().join("rust");
+        create_dir_all(&rust_queries).unwrap();
+        write(rust_queries.join("highlights.scm"), "; extends\n").unwrap();
+        cache.get_or_create(&m, "rust").unwrap();
+        cache.get_or_create(&m, "rust").unwrap();
+        let stats = cache.stats();
+        assert_eq!((stats.entries, stats.hits, stats.misses), (1, 1, 2));
+    }
+}
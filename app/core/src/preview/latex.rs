@@ -0,0 +1,746 @@
+// Translation of LaTeX math expressions into Typst math, to render them with the MathRenderer.
+// Only the math mode is supported: symbols, fractions, roots, accents, fonts, scripts,
+// \left...\right and the common environments (matrices, cases, align...). The unknown commands
+// give an error instead of a wrong rendering.
+// The Typst names come from https://typst.app/docs/reference/symbols/sym/
+
+/// The LaTeX symbols and operators, with their Typst name
+const SYMBOLS: &[(&str, &str)] = &[
+    // Greek letters, the "var" versions are the other way in Typst
+    ("alpha", "alpha"),
+    ("beta", "beta"),
+    ("gamma", "gamma"),
+    ("delta", "delta"),
+    ("epsilon", "epsilon.alt"),
+    ("varepsilon", "epsilon"),
+    ("zeta", "zeta"),
+    ("eta", "eta"),
+    ("theta", "theta"),
+    ("vartheta", "theta.alt"),
+    ("iota", "iota"),
+    ("kappa", "kappa"),
+    ("lambda", "lambda"),
+    ("mu", "mu"),
+    ("nu", "nu"),
+    ("xi", "xi"),
+    ("omicron", "omicron"),
+    ("pi", "pi"),
+    ("varpi", "pi.alt"),
+    ("rho", "rho"),
+    ("varrho", "rho.alt"),
+    ("sigma", "sigma"),
+    ("varsigma", "sigma.alt"),
+    ("tau", "tau"),
+    ("upsilon", "upsilon"),
+    ("phi", "phi.alt"),
+    ("varphi", "phi"),
+    ("chi", "chi"),
+    ("psi", "psi"),
+    ("omega", "omega"),
+    ("Gamma", "Gamma"),
+    ("Delta", "Delta"),
+    ("Theta", "Theta"),
+    ("Lambda", "Lambda"),
+    ("Xi", "Xi"),
+    ("Pi", "Pi"),
+    ("Sigma", "Sigma"),
+    ("Upsilon", "Upsilon"),
+    ("Phi", "Phi"),
+    ("Psi", "Psi"),
+    ("Omega", "Omega"),
+    // Operators and relations
+    ("times", "times"),
+    ("cdot", "dot.op"),
+    ("div", "div"),
+    ("pm", "plus.minus"),
+    ("mp", "minus.plus"),
+    ("ast", "ast.op"),
+    ("star", "star.op"),
+    ("circ", "compose"),
+    ("bullet", "bullet"),
+    ("oplus", "plus.circle"),
+    ("ominus", "minus.circle"),
+    ("otimes", "times.circle"),
+    ("odot", "dot.circle"),
+    ("dagger", "dagger"),
+    ("leq", "<="),
+    ("le", "<="),
+    ("geq", ">="),
+    ("ge", ">="),
+    ("neq", "!="),
+    ("ne", "!="),
+    ("ll", "<<"),
+    ("gg", ">>"),
+    ("approx", "approx"),
+    ("equiv", "equiv"),
+    ("sim", "tilde.op"),
+    ("simeq", "tilde.eq"),
+    ("cong", "tilde.equiv"),
+    ("propto", "prop"),
+    ("perp", "perp"),
+    ("parallel", "parallel"),
+    ("mid", "divides"),
+    ("vdash", "tack.r"),
+    ("models", "models"),
+    // Sets and logic
+    ("in", "in"),
+    ("notin", "in.not"),
+    ("ni", "in.rev"),
+    ("subset", "subset"),
+    ("subseteq", "subset.eq"),
+    ("supset", "supset"),
+    ("supseteq", "supset.eq"),
+    ("cup", "union"),
+    ("cap", "inter"),
+    ("bigcup", "union.big"),
+    ("bigcap", "inter.big"),
+    ("setminus", "without"),
+    ("emptyset", "emptyset"),
+    ("varnothing", "emptyset"),
+    ("forall", "forall"),
+    ("exists", "exists"),
+    ("nexists", "exists.not"),
+    ("neg", "not"),
+    ("lnot", "not"),
+    ("land", "and"),
+    ("wedge", "and"),
+    ("lor", "or"),
+    ("vee", "or"),
+    ("therefore", "therefore"),
+    ("because", "because"),
+    // Arrows
+    ("to", "arrow.r"),
+    ("rightarrow", "arrow.r"),
+    ("leftarrow", "arrow.l"),
+    ("gets", "arrow.l"),
+    ("leftrightarrow", "arrow.l.r"),
+    ("uparrow", "arrow.t"),
+    ("downarrow", "arrow.b"),
+    ("Rightarrow", "arrow.r.double"),
+    ("Leftarrow", "arrow.l.double"),
+    ("Leftrightarrow", "arrow.l.r.double"),
+    ("implies", "==>"),
+    ("impliedby", "<=="),
+    ("iff", "<==>"),
+    ("longrightarrow", "arrow.r.long"),
+    ("longleftarrow", "arrow.l.long"),
+    ("Longrightarrow", "arrow.r.double.long"),
+    ("Longleftarrow", "arrow.l.double.long"),
+    ("mapsto", "arrow.r.bar"),
+    ("hookrightarrow", "arrow.r.hook"),
+    // Big operators
+    ("sum", "sum"),
+    ("prod", "product"),
+    ("coprod", "product.co"),
+    ("int", "integral"),
+    ("iint", "integral.double"),
+    ("iiint", "integral.triple"),
+    ("oint", "integral.cont"),
+    // Functions
+    ("sin", "sin"),
+    ("cos", "cos"),
+    ("tan", "tan"),
+    ("cot", "cot"),
+    ("sec", "sec"),
+    ("csc", "csc"),
+    ("arcsin", "arcsin"),
+    ("arccos", "arccos"),
+    ("arctan", "arctan"),
+    ("sinh", "sinh"),
+    ("cosh", "cosh"),
+    ("tanh", "tanh"),
+    ("coth", "coth"),
+    ("log", "log"),
+    ("ln", "ln"),
+    ("lg", "lg"),
+    ("exp", "exp"),
+    ("det", "det"),
+    ("dim", "dim"),
+    ("ker", "ker"),
+    ("deg", "deg"),
+    ("gcd", "gcd"),
+    ("hom", "hom"),
+    ("arg", "arg"),
+    ("lim", "lim"),
+    ("liminf", "liminf"),
+    ("limsup", "limsup"),
+    ("max", "max"),
+    ("min", "min"),
+    ("sup", "sup"),
+    ("inf", "inf"),
+    ("Pr", "Pr"),
+    ("bmod", "mod"),
+    // Delimiters
+    ("langle", "chevron.l"),
+    ("rangle", "chevron.r"),
+    ("lfloor", "floor.l"),
+    ("rfloor", "floor.r"),
+    ("lceil", "ceil.l"),
+    ("rceil", "ceil.r"),
+    ("lvert", "|"),
+    ("rvert", "|"),
+    ("vert", "|"),
+    ("lVert", "||"),
+    ("rVert", "||"),
+    ("Vert", "||"),
+    ("|", "||"),
+    ("{", "\\{"),
+    ("}", "\\}"),
+    ("lbrace", "\\{"),
+    ("rbrace", "\\}"),
+    // Others
+    ("infty", "infinity"),
+    ("partial", "partial"),
+    ("nabla", "nabla"),
+    ("prime", "prime"),
+    ("ell", "ell"),
+    ("hbar", "planck"),
+    ("aleph", "aleph"),
+    ("Re", "Re"),
+    ("Im", "Im"),
+    ("angle", "angle"),
+    ("top", "top"),
+    ("bot", "bot"),
+    ("checkmark", "checkmark"),
+    ("ldots", "dots.h"),
+    ("dots", "dots.h"),
+    ("cdots", "dots.c"),
+    ("vdots", "dots.v"),
+    ("ddots", "dots.down"),
+    ("colon", "colon"),
+    ("_", "\\_"),
+    ("%", "%"),
+    ("$", "\\$"),
+    ("#", "\\#"),
+    ("&", "\\&"),
+    // Spaces
+    (",", "thin"),
+    (":", "med"),
+    (";", "thick"),
+    ("quad", "quad"),
+    ("qquad", "wide"),
+    (" ", "space"),
+];
+
+/// The LaTeX commands with one argument, with the Typst function
+const FUNCTIONS: &[(&str, &str)] = &[
+    // Accents
+    ("hat", "hat"),
+    ("widehat", "hat"),
+    ("bar", "macron"),
+    ("overline", "overline"),
+    ("underline", "underline"),
+    ("vec", "arrow"),
+    ("overrightarrow", "arrow"),
+    ("dot", "dot"),
+    ("ddot", "dot.double"),
+    ("tilde", "tilde"),
+    ("widetilde", "tilde"),
+    ("check", "caron"),
+    ("breve", "breve"),
+    ("acute", "acute"),
+    ("grave", "grave"),
+    ("overbrace", "overbrace"),
+    ("underbrace", "underbrace"),
+    // Fonts
+    ("mathbb", "bb"),
+    ("mathcal", "cal"),
+    ("mathfrak", "frak"),
+    ("mathbf", "bold"),
+    ("boldsymbol", "bold"),
+    ("mathit", "italic"),
+    ("mathrm", "upright"),
+    ("mathsf", "sans"),
+    ("mathtt", "mono"),
+];
+
+/// The commands without any effect on the rendering
+const IGNORED: &[&str] = &[
+    "displaystyle",
+    "textstyle",
+    "limits",
+    "nolimits",
+    "nonumber",
+    "notag",
+    "!",
+];
+
+/// Translate a LaTeX math expression, without the dollars, into Typst math
+pub fn latex_to_typst(latex: &str) -> Result<String, String> {
+    let mut parser = Parser {
+        tokens: tokenize(latex)?,
+        position: 0,
+        depth: 0,
+    };
+    let (typst, end) = parser.sequence(false)?;
+    match end {
+        End::Eof => Ok(typst),
+        End::Close => Err("unexpected `}`".to_string()),
+        End::Right => Err("`\\right` without `\\left`".to_string()),
+        End::Environment(name) => Err(format!("`\\end{{{name}}}` without `\\begin{{{name}}}`")),
+        End::Ampersand | End::NewLine => unreachable!("only in environments"),
+    }
+}
+
+/// Guess if a math expression is written in LaTeX, like `\frac{a}{b}` or `x^{2}`. Typst uses
+/// the backslash only to escape a character or break a line.
+pub fn looks_like_latex(expression: &str) -> bool {
+    let mut chars = expression.chars().peekable();
+    while let Some(c) = chars.next() {
+        // `\u{1F600}` is a unicode escape in Typst
+        let unicode_escape = c == '\\' && chars.clone().take(2).eq(['u', '{']);
+        match (c, chars.peek()) {
+            ('\\', Some(next)) if next.is_ascii_alphabetic() && !unicode_escape => return true,
+            ('^' | '_', Some('{')) => return true,
+            _ => {}
+        }
+    }
+    false
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    /// `\frac` gives "frac", `\,` gives ","
+    Command(String),
+    Char(char),
+    Open,
+    Close,
+    Space,
+}
+
+fn tokenize(latex: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = latex.chars().peekable();
+    while let Some(c) = chars.next() {
+        let token = match c {
+            '\\' => {
+                let mut name = String::new();
+                while let Some(letter) = chars.next_if(|c| c.is_ascii_alphabetic()) {
+                    name.push(letter);
+                }
+                if name.is_empty() {
+                    match chars.next() {
+                        Some(c) if c.is_whitespace() => name.push(' '),
+                        Some(c) => name.push(c),
+                        None => return Err("`\\` at the end of the expression".to_string()),
+                    }
+                }
+                Token::Command(name)
+            }
+            '%' => {
+                while chars.next_if(|c| *c != '\n').is_some() {}
+                continue;
+            }
+            '{' => Token::Open,
+            '}' => Token::Close,
+            c if c.is_whitespace() => Token::Space,
+            c => Token::Char(c),
+        };
+        tokens.push(token);
+    }
+    Ok(tokens)
+}
+
+/// Why a sequence of tokens stopped
+#[derive(Debug, PartialEq)]
+enum End {
+    Eof,
+    Close,
+    Right,
+    /// `&` inside an environment
+    Ampersand,
+    /// `\\` inside an environment
+    NewLine,
+    /// `\end{name}`
+    Environment(String),
+}
+
+/// How deep groups and commands can be nested, a deeper expression would overflow the stack
+const MAX_NESTING: usize = 64;
+
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+    /// The number of sequences and commands being translated
+    depth: usize,
+}
+
+impl Parser {
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn skip_spaces(&mut self) {
+        while self.tokens.get(self.position) == Some(&Token::Space) {
+            self.position += 1;
+        }
+    }
+
+    /// Run a translation one level deeper, it fails past `MAX_NESTING`
+    fn nested<T>(
+        &mut self,
+        translate: impl FnOnce(&mut Self) -> Result<T, String>,
+    ) -> Result<T, String> {
+        if self.depth >= MAX_NESTING {
+            return Err("expression nested too deeply".to_string());
+        }
+        self.depth += 1;
+        let result = translate(self);
+        self.depth -= 1;
+        result
+    }
+
+    /// Translate the tokens until the end of the group, the expression or the cell
+    fn sequence(&mut self, in_environment: bool) -> Result<(String, End), String> {
+        self.nested(|parser| parser.sequence_pieces(in_environment))
+    }
+
+    fn sequence_pieces(&mut self, in_environment: bool) -> Result<(String, End), String> {
+        let mut pieces: Vec<String> = Vec::new();
+        let end = loop {
+            let Some(token) = self.next() else {
+                break End::Eof;
+            };
+            match token {
+                Token::Space => continue,
+                Token::Close => break End::Close,
+                Token::Char('&') if in_environment => break End::Ampersand,
+                Token::Command(name) if name == "\\" && in_environment => break End::NewLine,
+                Token::Command(name) if name == "right" => break End::Right,
+                Token::Command(name) if name == "end" => {
+                    break End::Environment(self.environment_name()?)
+                }
+                Token::Char(c @ ('^' | '_')) => {
+                    let base = pieces.pop().unwrap_or_else(|| "\"\"".to_string());
+                    let script = self.argument()?;
+                    pieces.push(format!("{base}{c}{}", script_argument(&script)));
+                }
+                Token::Char('\'') => {
+                    let base = pieces.pop().unwrap_or_else(|| "\"\"".to_string());
+                    pieces.push(format!("{base}'"));
+                }
+                Token::Char(c) if c.is_ascii_digit() => {
+                    let mut number = c.to_string();
+                    while let Some(Token::Char(c)) = self.tokens.get(self.position) {
+                        let decimal_point = *c == '.'
+                            && matches!(self.tokens.get(self.position + 1), Some(Token::Char(c)) if c.is_ascii_digit());
+                        if !c.is_ascii_digit() && !decimal_point {
+                            break;
+                        }
+                        number.push(*c);
+                        self.position += 1;
+                    }
+                    pieces.push(number);
+                }
+                token => {
+                    let piece = self.atom(token)?;
+                    if !piece.is_empty() {
+                        pieces.push(piece);
+                    }
+                }
+            }
+        };
+        Ok((pieces.join(" "), end))
+    }
+
+    /// The argument of a command or a script: a group or a single token
+    fn argument(&mut self) -> Result<String, String> {
+        self.skip_spaces();
+        match self.next() {
+            Some(Token::Open) => self.group(),
+            Some(token @ (Token::Char(_) | Token::Command(_))) => self.atom(token),
+            _ => Err("missing argument".to_string()),
+        }
+    }
+
+    /// The rest of a group after its `{`
+    fn group(&mut self) -> Result<String, String> {
+        match self.sequence(false)? {
+            (typst, End::Close) => Ok(typst),
+            _ => Err("missing `}`".to_string()),
+        }
+    }
+
+    /// The raw content of a group, like the text of `\text{...}`
+    fn raw_group(&mut self) -> Result<String, String> {
+        self.skip_spaces();
+        if self.next() != Some(Token::Open) {
+            return Err("missing `{`".to_string());
+        }
+        let mut text = String::new();
+        let mut depth = 0;
+        loop {
+            match self.next() {
+                Some(Token::Open) => depth += 1,
+                Some(Token::Close) if depth == 0 => return Ok(text),
+                Some(Token::Close) => depth -= 1,
+                Some(Token::Char(c)) => text.push(c),
+                Some(Token::Space) => text.push(' '),
+                Some(Token::Command(name)) => match name.as_str() {
+                    "\\" => text.push(' '),
+                    name if name.len() == 1 => text.push_str(name),
+                    name => return Err(format!("unsupported LaTeX command `\\{name}` in text")),
+                },
+                None => return Err("missing `}`".to_string()),
+            }
+        }
+    }
+
+    fn environment_name(&mut self) -> Result<String, String> {
+        self.raw_group()
+    }
+
+    /// Translate a single token that isn't a script, a number or a separator
+    fn atom(&mut self, token: Token) -> Result<String, String> {
+        match token {
+            Token::Open => self.group(),
+            Token::Char(c) => Ok(match c {
+                // "/" is a fraction in Typst
+                '/' | ',' | ';' | '"' | '#' | '$' | '@' | '[' | ']' => format!("\\{c}"),
+                '~' => "space.nobreak".to_string(),
+                c => c.to_string(),
+            }),
+            Token::Command(name) => self.nested(|parser| parser.command(&name)),
+            Token::Close | Token::Space => Ok(String::new()),
+        }
+    }
+
+    fn command(&mut self, name: &str) -> Result<String, String> {
+        if let Some((_, typst)) = SYMBOLS.iter().find(|(latex, _)| *latex == name) {
+            return Ok(typst.to_string());
+        }
+        if let Some((_, function)) = FUNCTIONS.iter().find(|(latex, _)| *latex == name) {
+            return Ok(format!("{function}({})", self.argument()?));
+        }
+        if IGNORED.contains(&name) {
+            return Ok(String::new());
+        }
+        match name {
+            "frac" | "dfrac" | "tfrac" | "cfrac" => {
+                let (numerator, denominator) = (self.argument()?, self.argument()?);
+                Ok(format!("frac({numerator}, {denominator})"))
+            }
+            "binom" | "dbinom" | "tbinom" => {
+                let (n, k) = (self.argument()?, self.argument()?);
+                Ok(format!("binom({n}, {k})"))
+            }
+            "sqrt" => {
+                self.skip_spaces();
+                let index = match self.tokens.get(self.position) {
+                    Some(Token::Char('[')) => {
+                        self.position += 1;
+                        Some(self.until_closing_bracket()?)
+                    }
+                    _ => None,
+                };
+                let radicand = self.argument()?;
+                Ok(match index {
+                    Some(index) => format!("root({index}, {radicand})"),
+                    None => format!("sqrt({radicand})"),
+                })
+            }
+            "text" | "textrm" | "textit" | "textbf" | "mbox" | "operatorname" => {
+                let text = self.raw_group()?;
+                let string = format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""));
+                Ok(match name {
+                    "textit" => format!("italic({string})"),
+                    "textbf" => format!("bold({string})"),
+                    "operatorname" => format!("op({string})"),
+                    _ => format!("upright({string})"),
+                })
+            }
+            "pmod" => Ok(format!("(mod {})", self.argument()?)),
+            "not" => match self.argument()?.as_str() {
+                "=" => Ok("!=".to_string()),
+                symbol @ ("in" | "subset" | "subset.eq" | "supset" | "supset.eq" | "exists") => {
+                    Ok(format!("{symbol}.not"))
+                }
+                other => Err(format!("unsupported LaTeX negation `\\not {other}`")),
+            },
+            "left" => self.left_right(),
+            "begin" => self.environment(),
+            "\\" => Ok("\\".to_string()),
+            name => Err(format!("unsupported LaTeX command `\\{name}`")),
+        }
+    }
+
+    /// The index of `\sqrt[n]{x}`
+    fn until_closing_bracket(&mut self) -> Result<String, String> {
+        let start = self.position;
+        let Some(length) = self.tokens[start..]
+            .iter()
+            .position(|token| token == &Token::Char(']'))
+        else {
+            return Err("missing `]`".to_string());
+        };
+        let mut inner = Parser {
+            tokens: self.tokens[start..start + length].to_vec(),
+            position: 0,
+            depth: self.depth,
+        };
+        self.position = start + length + 1;
+        inner.sequence(false).map(|(typst, _)| typst)
+    }
+
+    /// `\left( ... \right)`, Typst scales the delimiters of `lr()`
+    fn left_right(&mut self) -> Result<String, String> {
+        let left = self.delimiter()?;
+        let (content, end) = self.sequence(false)?;
+        if end != End::Right {
+            return Err("`\\left` without `\\right`".to_string());
+        }
+        let right = self.delimiter()?;
+        Ok(format!("lr({left} {content} {right})"))
+    }
+
+    fn delimiter(&mut self) -> Result<String, String> {
+        self.skip_spaces();
+        match self.next() {
+            Some(Token::Char('.')) => Ok(String::new()),
+            Some(Token::Char(c @ ('(' | ')' | '|'))) => Ok(c.to_string()),
+            Some(token @ (Token::Char(_) | Token::Command(_))) => self.atom(token),
+            _ => Err("missing delimiter after `\\left` or `\\right`".to_string()),
+        }
+    }
+
+    /// `\begin{name} ... \end{name}`, the rows and cells are split by `\\` and `&`
+    fn environment(&mut self) -> Result<String, String> {
+        let name = self.environment_name()?;
+        if name == "array" {
+            // The columns alignment is ignored
+            self.raw_group()?;
+        }
+        let mut rows: Vec<Vec<String>> = vec![Vec::new()];
+        loop {
+            let (cell, end) = self.sequence(true)?;
+            rows.last_mut().unwrap().push(cell);
+            match end {
+                End::Ampersand => {}
+                End::NewLine => rows.push(Vec::new()),
+                End::Environment(end_name) if end_name == name => break,
+                End::Environment(end_name) => {
+                    return Err(format!(
+                        "`\\end{{{end_name}}}` doesn't close `\\begin{{{name}}}`"
+                    ))
+                }
+                _ => return Err(format!("`\\begin{{{name}}}` without `\\end{{{name}}}`")),
+            }
+        }
+        // The `\\` at the end of the last row
+        if rows.len() > 1
+            && rows
+                .last()
+                .is_some_and(|row| row.iter().all(String::is_empty))
+        {
+            rows.pop();
+        }
+
+        let delimiter = match name.as_str() {
+            "matrix" | "smallmatrix" | "array" => Some("#none"),
+            "pmatrix" => Some("\"(\""),
+            "bmatrix" => Some("\"[\""),
+            "Bmatrix" => Some("\"{\""),
+            "vmatrix" => Some("\"|\""),
+            "Vmatrix" => Some("\"||\""),
+            _ => None,
+        };
+        if let Some(delimiter) = delimiter {
+            let rows: Vec<String> = rows.iter().map(|row| row.join(", ")).collect();
+            return Ok(format!("mat(delim: {delimiter}, {})", rows.join("; ")));
+        }
+        let rows: Vec<String> = rows.iter().map(|row| row.join(" & ")).collect();
+        match name.as_str() {
+            "cases" => Ok(format!("cases({})", rows.join(", "))),
+            "align" | "align*" | "aligned" | "alignat" | "alignat*" | "alignedat" | "gather"
+            | "gather*" | "gathered" | "equation" | "equation*" | "split" | "multline"
+            | "multline*" | "eqnarray" | "eqnarray*" => Ok(rows.join(" \\\n")),
+            name => Err(format!("unsupported LaTeX environment `{name}`")),
+        }
+    }
+}
+
+/// The Typst script, with parentheses if this is more than a single character or number
+fn script_argument(script: &str) -> String {
+    let is_single = script.chars().count() == 1 || script.chars().all(|c| c.is_ascii_digit());
+    match is_single && !script.is_empty() {
+        true => script.to_string(),
+        false => format!("({script})"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::{latex_to_typst, looks_like_latex, FUNCTIONS, SYMBOLS};
+    use crate::preview::math::MathRenderer;
+
+    #[test]
+    fn test_latex_is_translated_into_typst() {
+        let cases = [
+            (r"\frac{a}{b} + \alpha x^2", "frac(a, b) + alpha x^2"),
+            (r"\sum_{i=1}^{n} i", "sum_(i = 1)^n i"),
+            (r"\sqrt[3]{xy} \leq \sqrt 2", "root(3, x y) <= sqrt(2)"),
+            (
+                r"f(x) = \left\{ \frac{1}{2}, \text{if } x \right.",
+                "f ( x ) = lr(\\{ frac(1, 2) \\, upright(\"if \") x )",
+            ),
+            (r"\mathbb{R}^{n \times n}", "bb(R)^(n times n)"),
+            (
+                r"\begin{pmatrix} a & b \\ c & d \end{pmatrix}",
+                "mat(delim: \"(\", a, b; c, d)",
+            ),
+            (
+                "\\begin{cases} 1 & x > 0 \\\\ 0 & \\text{otherwise} \\end{cases}",
+                "cases(1 & x > 0, 0 & upright(\"otherwise\"))",
+            ),
+            (
+                "\\begin{align} a &= b \\\\ c &= d \\\\ \\end{align}",
+                "a & = b \\\nc & = d",
+            ),
+            ("3.14 a/b % comment", "3.14 a \\/ b"),
+        ];
+        for (latex, typst) in cases {
+            assert_eq!(latex_to_typst(latex).as_deref(), Ok(typst), "{latex}");
+        }
+
+        assert_eq!(
+            latex_to_typst(r"\foo x"),
+            Err("unsupported LaTeX command `\\foo`".to_string())
+        );
+        assert_eq!(
+            latex_to_typst(r"\begin{tikzcd} a \end{tikzcd}"),
+            Err("unsupported LaTeX environment `tikzcd`".to_string())
+        );
+        assert_eq!(
+            latex_to_typst(r"\frac{a}{b"),
+            Err("missing `}`".to_string())
+        );
+
+        assert!(looks_like_latex(r"\frac12"));
+        assert!(looks_like_latex("x^{2}"));
+        assert!(!looks_like_latex("frac(1, 2) \\ x^2 \\$"));
+        assert!(!looks_like_latex(r"\u{1F600} + x"));
+        assert!(looks_like_latex(r"\u{1F600} + \underline{x}"));
+
+        let too_deep = Err("expression nested too deeply".to_string());
+        assert_eq!(latex_to_typst(&"{".repeat(1000)), too_deep);
+        assert_eq!(latex_to_typst(&r"\frac".repeat(1000)), too_deep);
+        assert_eq!(latex_to_typst(&r"\left".repeat(1000)), too_deep);
+        assert!(latex_to_typst(&format!("{}x{}", "{".repeat(30), "}".repeat(30))).is_ok());
+    }
+
+    #[test]
+    fn test_all_translated_names_exist_in_typst() {
+        let renderer = MathRenderer::init();
+        let symbols = SYMBOLS.iter().map(|(_, typst)| typst.to_string());
+        let functions = FUNCTIONS.iter().map(|(_, typst)| format!("{typst}(x)"));
+        let invalid: Vec<String> = symbols
+            .chain(functions)
+            .filter(|typst| renderer.convert_math_expression_into_svg(typst).is_err())
+            .collect();
+        assert_eq!(invalid, Vec::<String>::new());
+    }
+}
@@ -1,6 +1,7 @@
 // This math module provides a way to convert Math expressions into SVG text
 // To allow for backend only HTML rendering, it currently only supports the Typst syntax
 // See more about the Typst syntax on https://typst.app/docs/reference/math/
+// The ```typst code blocks are compiled in the same world, as whole Typst content into figures
 // The part TYPST IMPLEMENTATION is based upon https://github.com/tfachmann/typst-as-library/ released under Apache-2.0
 // But simplified - to focus on math rendering
 // - use embedded fonts
@@ -15,7 +16,7 @@ use mini_moka::sync::Cache;
 use oxvg_ast::{parse::roxmltree::parse, serialize::Node as _, visitor::Info};
 use oxvg_optimiser::{Jobs, PrefixIds};
 use std::convert::TryInto;
-use typst::diag::{FileError, FileResult};
+use typst::diag::{FileError, FileResult, SourceDiagnostic};
 use typst::foundations::{Bytes, Datetime};
 use typst::introspection::Introspector;
 use typst::layout::{Abs, PagedDocument};
@@ -25,6 +26,11 @@ use typst::utils::LazyHash;
 use typst::{Library, LibraryExt};
 use typst_kit::fonts::{FontSearcher, FontSlot};
 
+use crate::preview::latex::latex_to_typst;
+
+/// The page fits its content, the first line of the compiled sources
+const PAGE_SETTINGS: &str = "#set page(height: auto, width: auto, margin: 0pt)";
+
 /// General Math abstraction that should be created once and kept in memory for all future use
 /// Keeping in memory is useful to avoid repeating the cost of starting the Typst world which can be 60ms
 /// compared to <1ms to render a given equation to SVG. It also contains a cache of the rendered SVG.
@@ -53,29 +59,72 @@ impl MathRenderer {
     }
 
     pub fn convert_math_expression_into_svg(&self, exp: &str) -> Result<String, String> {
-        let maybe_cached_svg = self.cache.get(&exp.to_string());
+        let page = format!("{PAGE_SETTINGS}\n${exp}$");
+        self.convert_page_into_svg(page, |error| {
+            format!(
+                "Typst parsing error: ${}$\n{}\n{}",
+                exp.trim(),
+                error.message,
+                error.hints.join("\n")
+            )
+        })
+    }
+
+    /// Same as `convert_math_expression_into_svg()` with a LaTeX expression, translated into
+    /// Typst math before, see `latex.rs`
+    pub fn convert_latex_math_expression_into_svg(&self, exp: &str) -> Result<String, String> {
+        let typst = latex_to_typst(exp)
+            .map_err(|e| format!("LaTeX translation error: ${}$\n{e}", exp.trim()))?;
+        let page = format!("{PAGE_SETTINGS}\n${typst}$");
+        self.convert_page_into_svg(page, |error| {
+            format!(
+                "Typst parsing error: ${}$ translated from LaTeX ${}$\n{}\n{}",
+                typst.trim(),
+                exp.trim(),
+                error.message,
+                error.hints.join("\n")
+            )
+        })
+    }
+
+    /// Compile Typst content, like the content of a ```typst code block, into a SVG figure.
+    /// It runs in the same sandbox as the math expressions, without any file or package.
+    pub fn convert_typst_content_into_svg(&self, content: &str) -> Result<String, String> {
+        let page = format!("{PAGE_SETTINGS}\n{content}");
+        // The errors give the line inside the content, without the settings line
+        let source = Source::detached(page.clone());
+        self.convert_page_into_svg(page, |error| {
+            let line = source
+                .range(error.span)
+                .and_then(|range| source.lines().byte_to_line(range.start));
+            let mut message = match line {
+                Some(line) if line > 0 => format!("Typst error at line {line}: {}", error.message),
+                _ => format!("Typst error: {}", error.message),
+            };
+            for hint in &error.hints {
+                message.push_str(&format!("\n{hint}"));
+            }
+            message
+        })
+    }
+
+    /// Compile a whole Typst page into an optimised SVG, the page is the key of the cache
+    fn convert_page_into_svg(
+        &self,
+        page: String,
+        format_error: impl Fn(&SourceDiagnostic) -> String,
+    ) -> Result<String, String> {
+        let maybe_cached_svg = self.cache.get(&page);
         if let Some(cached) = maybe_cached_svg {
-            // println!("Cache HIT for {}", exp.replace("\n", ""));
             return Ok(cached.clone());
-        } else {
-            // println!("Cache miss for {}", exp.replace("\n", ""));
         }
 
-        let prefix = "#set page(height: auto, width: auto, margin: 0pt)";
-        let page_with_settings = format!("{prefix}\n${exp}$");
         let mut typst = self.typst.lock().unwrap();
-        typst.set_source(page_with_settings);
+        typst.set_source(page.clone());
 
         let document: PagedDocument = typst::compile(&*typst).output.map_err(|e| {
             e.iter()
-                .map(|e| {
-                    format!(
-                        "Typst parsing error: ${}$\n{}\n{}",
-                        exp.trim(),
-                        e.message,
-                        e.hints.join("\n")
-                    )
-                })
+                .map(&format_error)
                 .collect::<Vec<String>>()
                 .join("\n")
         })?;
@@ -113,7 +162,7 @@ impl MathRenderer {
                 unoptimized_svg
             }
         };
-        self.cache.insert(exp.to_string(), final_svg.clone());
+        self.cache.insert(page, final_svg.clone());
         Ok(final_svg)
     }
 
@@ -239,4 +288,44 @@ or if you meant to display this as text, try placing it in quotes: `\"blabla\"`"
             panic!("oups")
         }
     }
+
+    #[test]
+    fn test_typst_content_is_rendered_in_the_same_sandbox() {
+        let renderer = MathRenderer::init();
+        let svg = renderer
+            .convert_typst_content_into_svg("#rect(fill: red)[*Hi*]\n")
+            .unwrap();
+        assert!(svg.starts_with("<svg"));
+        assert!(svg.contains("fill=\"#ff4136\""));
+
+        let error = |content: &str| {
+            renderer
+                .convert_typst_content_into_svg(content)
+                .unwrap_err()
+        };
+        assert_eq!(
+            error("Hi\n#rect(\n"),
+            "Typst error at line 2: unclosed delimiter"
+        );
+        assert!(error("#image(\"/etc/passwd\")")
+            .starts_with("Typst error at line 1: failed to load file"));
+        assert!(error("#import \"@preview/cetz:0.3.0\"").starts_with("Typst error at line 1:"));
+    }
+
+    #[test]
+    fn test_latex_math_expression_gives_the_same_svg_as_typst() {
+        let latex = MathRenderer::init()
+            .convert_latex_math_expression_into_svg(r"\frac{\alpha}{2} \leq \sqrt{x}")
+            .unwrap();
+        let typst = MathRenderer::init()
+            .convert_math_expression_into_svg("frac(alpha, 2) <= sqrt(x)")
+            .unwrap();
+        assert_eq!(latex, typst);
+
+        let renderer = MathRenderer::init();
+        assert_eq!(
+            renderer.convert_latex_math_expression_into_svg(r"\frac{a}{b} + \weird"),
+            Err("LaTeX translation error: $\\frac{a}{b} + \\weird$\nunsupported LaTeX command `\\weird`".to_string())
+        );
+    }
 }
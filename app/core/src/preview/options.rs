@@ -0,0 +1,351 @@
+// The Markdown extensions enabled in the preview. They can be defined in a TOML config file,
+// and each document can override them in a [preview] table of its TOML front matter:
+//
+// ---
+// [preview]
+// footnotes = true
+// math = "both"
+// ---
+
+use std::{fs::read_to_string, path::Path};
+
+use comrak::Options;
+use serde::{Deserialize, Serialize};
+use toml::{value::Table, Value};
+
+use crate::preview::comrak::{FRONT_MATTER_DELIMITER, HEADER_IDS_SECURITY_PREFIX};
+
+/// How math expressions are written
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum MathSyntax {
+    /// `$x^2$` and `$$x^2$$`
+    Dollars,
+    /// $`x^2`$ and ```math code blocks, like on GitHub
+    Code,
+    Both,
+    /// Math expressions are left as text
+    None,
+}
+
+/// The language of the math expressions
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum MathLanguage {
+    Typst,
+    /// Translated into Typst, see `latex.rs`
+    Latex,
+    /// LaTeX for the expressions with commands like `\frac`, Typst for the others
+    Auto,
+}
+
+/// The Markdown extensions of the preview, the default ones are the same as before they could
+/// be configured
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct PreviewOptions {
+    tables: bool,
+    tasklists: bool,
+    /// Links created from URLs in the text
+    autolinks: bool,
+    footnotes: bool,
+    /// `~~text~~`
+    strikethrough: bool,
+    /// `^text^`
+    superscript: bool,
+    /// `~text~`, it requires strikethrough to use `~~` only
+    subscript: bool,
+    description_lists: bool,
+    /// `__text__` instead of bold
+    underline: bool,
+    /// `||text||`
+    spoilers: bool,
+    /// Curly quotes, dashes and ellipses
+    smart_punctuation: bool,
+    /// GitHub alerts like `> [!NOTE]`, see `alerts.rs`
+    alerts: bool,
+    /// ```typst code blocks compiled into SVG figures, instead of highlighted code
+    typst_blocks: bool,
+    math: MathSyntax,
+    math_language: MathLanguage,
+}
+
+impl Default for PreviewOptions {
+    fn default() -> Self {
+        PreviewOptions {
+            tables: true,
+            tasklists: true,
+            autolinks: true,
+            footnotes: false,
+            strikethrough: false,
+            superscript: false,
+            subscript: false,
+            description_lists: false,
+            underline: false,
+            spoilers: false,
+            smart_punctuation: false,
+            alerts: true,
+            typst_blocks: true,
+            math: MathSyntax::Dollars,
+            math_language: MathLanguage::Typst,
+        }
+    }
+}
+
+impl PreviewOptions {
+    pub fn tables(mut self, enabled: bool) -> Self {
+        self.tables = enabled;
+        self
+    }
+
+    pub fn tasklists(mut self, enabled: bool) -> Self {
+        self.tasklists = enabled;
+        self
+    }
+
+    pub fn autolinks(mut self, enabled: bool) -> Self {
+        self.autolinks = enabled;
+        self
+    }
+
+    pub fn footnotes(mut self, enabled: bool) -> Self {
+        self.footnotes = enabled;
+        self
+    }
+
+    pub fn strikethrough(mut self, enabled: bool) -> Self {
+        self.strikethrough = enabled;
+        self
+    }
+
+    pub fn superscript(mut self, enabled: bool) -> Self {
+        self.superscript = enabled;
+        self
+    }
+
+    pub fn subscript(mut self, enabled: bool) -> Self {
+        self.subscript = enabled;
+        self
+    }
+
+    pub fn description_lists(mut self, enabled: bool) -> Self {
+        self.description_lists = enabled;
+        self
+    }
+
+    pub fn underline(mut self, enabled: bool) -> Self {
+        self.underline = enabled;
+        self
+    }
+
+    pub fn spoilers(mut self, enabled: bool) -> Self {
+        self.spoilers = enabled;
+        self
+    }
+
+    pub fn smart_punctuation(mut self, enabled: bool) -> Self {
+        self.smart_punctuation = enabled;
+        self
+    }
+
+    pub fn alerts(mut self, enabled: bool) -> Self {
+        self.alerts = enabled;
+        self
+    }
+
+    pub fn typst_blocks(mut self, enabled: bool) -> Self {
+        self.typst_blocks = enabled;
+        self
+    }
+
+    pub fn get_typst_blocks(&self) -> bool {
+        self.typst_blocks
+    }
+
+    pub fn math(mut self, syntax: MathSyntax) -> Self {
+        self.math = syntax;
+        self
+    }
+
+    pub fn get_math(&self) -> MathSyntax {
+        self.math
+    }
+
+    pub fn math_language(mut self, language: MathLanguage) -> Self {
+        self.math_language = language;
+        self
+    }
+
+    pub fn get_math_language(&self) -> MathLanguage {
+        self.math_language
+    }
+
+    /// Parse options from a TOML config, the missing keys keep their default value
+    pub fn from_toml(content: &str) -> Result<Self, String> {
+        toml::from_str(content).map_err(|e| format!("Invalid preview options: {e}"))
+    }
+
+    pub fn from_toml_file(path: &Path) -> Result<Self, String> {
+        let content = read_to_string(path)
+            .map_err(|e| format!("Couldn't read the preview options at {path:?}: {e}"))?;
+        Self::from_toml(&content)
+    }
+
+    /// Override these options with the [preview] table of the TOML front matter of a Markdown
+    /// document. A front matter that is not TOML, like YAML, is ignored. An invalid [preview]
+    /// table is ignored too, the error is given to be reported without failing the rendering.
+    pub fn with_front_matter(self, markdown: &str) -> (Self, Option<String>) {
+        let Some(Ok(Value::Table(mut front_matter))) =
+            front_matter(markdown).map(|front_matter| front_matter.parse::<Value>())
+        else {
+            return (self, None);
+        };
+        let Some(overrides) = front_matter.remove("preview") else {
+            return (self, None);
+        };
+        let overridden =
+            match overrides {
+                Value::Table(overrides) => Table::try_from(&self)
+                    .map_err(|e| e.to_string())
+                    .and_then(|mut options| {
+                        options.extend(overrides);
+                        Value::Table(options).try_into().map_err(|e| e.to_string())
+                    }),
+                _ => Err("[preview] must be a table".to_string()),
+            };
+        match overridden {
+            Ok(options) => (options, None),
+            Err(e) => (
+                self,
+                Some(format!(
+                    "Invalid preview options in front matter, they are ignored: {e}"
+                )),
+            ),
+        }
+    }
+
+    /// The comrak options with these extensions, and the ones that are always enabled
+    pub(crate) fn to_comrak_options(&self) -> Options<'static> {
+        let mut options = Options::default();
+        options.extension.table = self.tables;
+        options.extension.tasklist = self.tasklists;
+        options.extension.autolink = self.autolinks;
+        options.extension.footnotes = self.footnotes;
+        options.extension.strikethrough = self.strikethrough;
+        options.extension.superscript = self.superscript;
+        options.extension.subscript = self.subscript;
+        options.extension.description_lists = self.description_lists;
+        options.extension.underline = self.underline;
+        options.extension.spoiler = self.spoilers;
+        options.parse.smart = self.smart_punctuation;
+        options.extension.alerts = self.alerts;
+        options.extension.math_dollars =
+            matches!(self.math, MathSyntax::Dollars | MathSyntax::Both);
+        options.extension.math_code = matches!(self.math, MathSyntax::Code | MathSyntax::Both);
+        options.extension.front_matter_delimiter = Some(FRONT_MATTER_DELIMITER.into());
+        options.extension.header_ids = Some(HEADER_IDS_SECURITY_PREFIX.into());
+        options.render.figure_with_caption = true;
+        options.render.r#unsafe = true; // Unable unsafe mode to allow HTML to go through. To avoid XSS, we take care of it with ammonia sanitizer in the Html wrapper type
+        options
+    }
+}
+
+/// The content between the front matter delimiters at the start of the document
+fn front_matter(markdown: &str) -> Option<&str> {
+    let rest = markdown
+        .strip_prefix(FRONT_MATTER_DELIMITER)?
+        .strip_prefix('\n')
+        .or_else(|| markdown.strip_prefix("---\r\n"))?;
+    let mut end = 0;
+    for line in rest.split_inclusive('\n') {
+        if line.trim_end() == FRONT_MATTER_DELIMITER {
+            return Some(&rest[..end]);
+        }
+        end += line.len();
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::{MathLanguage, MathSyntax, PreviewOptions};
+    use crate::preview::{budget::PreviewBudget, comrak::ComrakParser, preview::Previewable};
+
+    #[test]
+    fn test_options_from_config_and_front_matter() {
+        let config = PreviewOptions::from_toml("footnotes = true\nautolinks = false").unwrap();
+        assert_eq!(
+            config,
+            PreviewOptions::default().footnotes(true).autolinks(false)
+        );
+        assert!(PreviewOptions::from_toml("footnote = true").is_err());
+
+        let document =
+            "---\ntitle = \"Notes\"\n[preview]\nmath = \"both\"\nmath_language = \"auto\"\nautolinks = true\n---\n# Notes";
+        let (options, error) = config.clone().with_front_matter(document);
+        assert_eq!(error, None);
+        assert_eq!(
+            options,
+            PreviewOptions::default()
+                .footnotes(true)
+                .math(MathSyntax::Both)
+                .math_language(MathLanguage::Auto)
+        );
+
+        // A YAML front matter or no front matter keep the options
+        let yaml = "---\ntitle: Notes\n---\n";
+        assert_eq!(
+            config.clone().with_front_matter(yaml),
+            (config.clone(), None)
+        );
+        assert_eq!(
+            config.clone().with_front_matter("# Notes"),
+            (config.clone(), None)
+        );
+        // An invalid [preview] table keeps the options, with the error
+        let (options, error) = config
+            .clone()
+            .with_front_matter("---\n[preview]\nmath = \"latex\"\n---\n");
+        assert_eq!(options, config);
+        assert!(error
+            .unwrap()
+            .starts_with("Invalid preview options in front matter"));
+        let (options, error) = config.clone().with_front_matter("---\npreview = 1\n---\n");
+        assert_eq!(options, config);
+        assert!(error.unwrap().ends_with("[preview] must be a table"));
+    }
+
+    #[test]
+    fn test_extensions_follow_the_options() {
+        // Without math rendering, to keep the SVG IDs of the other tests
+        let no_math = PreviewBudget {
+            max_math_expressions: 0,
+            ..PreviewBudget::default()
+        };
+        let given = "```math\nx^2\n```\n\nA note[^1] and $y$\n\n[^1]: The note\n";
+        let default = ComrakParser::new()
+            .unwrap()
+            .with_budget(no_math.clone())
+            .to_html(given)
+            .to_safe_html_string();
+        assert!(default.contains("<code class=\"language-math\">"));
+        assert!(default.contains("<span class=\"math-inline\">$y$</span>"));
+        assert!(!default.contains("fn-1"));
+
+        let options = PreviewOptions::default()
+            .footnotes(true)
+            .math(MathSyntax::Code);
+        let html = ComrakParser::new()
+            .unwrap()
+            .with_budget(no_math)
+            .with_options(options)
+            .to_html(given)
+            .to_safe_html_string();
+        assert!(html.contains("<p class=\"math-block\">```math\nx^2\n```</p>"));
+        assert!(html.contains("and $y$"));
+        assert!(html.contains("<a href=\"#fn-1\" id=\"fnref-1\""));
+        assert!(html.contains("<li id=\"fn-1\">"));
+    }
+}
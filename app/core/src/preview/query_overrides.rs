@@ -0,0 +1,190 @@
+// User queries overriding the ones shipped with grammars. Grammar repositories often ship minimal
+// highlights.scm using few captures, while Helix and nvim-treesitter maintain much richer queries.
+// The queries folder uses the same layout as Helix runtime/queries and nvim-treesitter queries:
+// <queries folder>/<lang>/highlights.scm, injections.scm and locals.scm
+// so these folders can be copied as is.
+//
+// An override file replaces the query of the grammar, unless it starts with one of these modelines
+// - `; extends`: the override is appended to the query of the grammar (nvim-treesitter convention)
+// - `; inherits: c,cpp`: the queries of these langs are included first (Helix and nvim convention)
+
+use std::{fs::read_dir, path::Path, time::SystemTime};
+
+use super::grammar_sources::validate_grammar_name;
+
+/// The queries files that can be overridden, the kind is the file name without .scm
+pub const QUERY_KINDS: [&str; 3] = ["highlights", "injections", "locals"];
+
+/// The modelines found at the start of an override file
+#[derive(Debug, Default, PartialEq, Eq)]
+struct Modelines {
+    extends: bool,
+    inherits: Vec<String>,
+}
+
+impl Modelines {
+    /// Only the comments at the start of the file are modelines, like in nvim-treesitter
+    fn parse(query: &str) -> Self {
+        let mut modelines = Modelines::default();
+        for line in query.lines().map(str::trim) {
+            if line.is_empty() {
+                continue;
+            }
+            let Some(comment) = line.strip_prefix(';') else {
+                break;
+            };
+            let comment = comment.trim_start_matches(';').trim();
+            if comment == "extends" {
+                modelines.extends = true;
+            } else if let Some(langs) = comment.strip_prefix("inherits") {
+                modelines.inherits.extend(
+                    langs
+                        .trim_start_matches([' ', ':'])
+                        .split(',')
+                        // nvim-treesitter uses (lang) for langs only inherited via extends
+                        .map(|lang| lang.trim().trim_matches(['(', ')']).to_string())
+                        .filter(|lang| !lang.is_empty()),
+                );
+            }
+        }
+        modelines
+    }
+}
+
+/// Resolve the final query of the given kind for this lang. `grammar_query` is the query shipped
+/// with the grammar, `grammar_query_of` gives it for inherited langs.
+/// Returns `grammar_query` untouched if there is no override.
+pub(crate) fn resolve_query(
+    queries_folder: &Path,
+    lang: &str,
+    kind: &str,
+    grammar_query: String,
+    grammar_query_of: &dyn Fn(&str) -> String,
+) -> Result<String, String> {
+    resolve_query_with_visited(
+        queries_folder,
+        lang,
+        kind,
+        grammar_query,
+        grammar_query_of,
+        &mut Vec::new(),
+    )
+}
+
+fn resolve_query_with_visited(
+    queries_folder: &Path,
+    lang: &str,
+    kind: &str,
+    grammar_query: String,
+    grammar_query_of: &dyn Fn(&str) -> String,
+    visited: &mut Vec<String>,
+) -> Result<String, String> {
+    if visited.iter().any(|l| l == lang) {
+        visited.push(lang.to_string());
+        return Err(format!(
+            "Cyclic inherits in {kind} query overrides: {}",
+            visited.join(" -> ")
+        ));
+    }
+    let override_path = queries_folder.join(lang).join(format!("{kind}.scm"));
+    if !override_path.exists() {
+        return Ok(grammar_query);
+    }
+    let content = std::fs::read_to_string(&override_path)
+        .map_err(|e| format!("Couldn't read query override {override_path:?}: {e}"))?;
+    let modelines = Modelines::parse(&content);
+
+    visited.push(lang.to_string());
+    let mut parts = Vec::new();
+    for parent in &modelines.inherits {
+        // The parent is a folder name, it must not escape the queries folder
+        validate_grammar_name(parent)
+            .map_err(|e| format!("Invalid inherits in {override_path:?}: {e}"))?;
+        parts.push(resolve_query_with_visited(
+            queries_folder,
+            parent,
+            kind,
+            grammar_query_of(parent),
+            grammar_query_of,
+            visited,
+        )?);
+    }
+    visited.pop();
+
+    if modelines.extends {
+        parts.push(grammar_query);
+    }
+    parts.push(content);
+    Ok(parts.join("\n"))
+}
+
+/// The last change in the queries folder, an override file or a lang folder, None if there is
+/// no override. The highlighters created before this change are stale.
+pub(crate) fn last_overrides_change(queries_folder: &Path) -> Option<SystemTime> {
+    let modified = |path: &Path| path.metadata().and_then(|m| m.modified()).ok();
+    let mut last = modified(queries_folder)?;
+    for lang_folder in read_dir(queries_folder).ok()?.flatten() {
+        let lang_folder = lang_folder.path();
+        let files = read_dir(&lang_folder).into_iter().flatten().flatten();
+        for path in std::iter::once(lang_folder.clone()).chain(files.map(|file| file.path())) {
+            last = last.max(modified(&path).unwrap_or(last));
+        }
+    }
+    Some(last)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{create_dir_all, write};
+
+    use pretty_assertions::assert_eq;
+
+    use super::{resolve_query, Modelines};
+    use crate::preview::tree_sitter_grammars::get_unique_local_tree_sitter_grammars_folder;
+
+    #[test]
+    fn test_overrides_replace_extend_or_inherit_grammar_queries() {
+        assert_eq!(
+            Modelines::parse(";; extends\n; inherits: (c), cpp\n\n(a) @b\n; inherits: d"),
+            Modelines {
+                extends: true,
+                inherits: vec!["c".to_string(), "cpp".to_string()]
+            }
+        );
+
+        let folder = get_unique_local_tree_sitter_grammars_folder().join("queries");
+        let grammar_query_of = |lang: &str| format!("({lang}_grammar)");
+        let resolve = |lang: &str| {
+            resolve_query(
+                &folder,
+                lang,
+                "highlights",
+                grammar_query_of(lang),
+                &grammar_query_of,
+            )
+        };
+        for (lang, content) in [
+            ("c", "(c_override)"),
+            ("cpp", "; inherits: c\n; extends\n(cpp_override)"),
+            ("loop", "; inherits: loop"),
+            ("escape", "; inherits: ../../x"),
+        ] {
+            create_dir_all(folder.join(lang)).unwrap();
+            write(folder.join(lang).join("highlights.scm"), content).unwrap();
+        }
+
+        assert_eq!(resolve("rust").unwrap(), "(rust_grammar)");
+        assert_eq!(resolve("c").unwrap(), "(c_override)");
+        assert_eq!(
+            resolve("cpp").unwrap(),
+            "(c_override)\n(cpp_grammar)\n; inherits: c\n; extends\n(cpp_override)"
+        );
+        assert_eq!(
+            resolve("loop").unwrap_err(),
+            "Cyclic inherits in highlights query overrides: loop -> loop"
+        );
+        assert!(resolve("escape")
+            .unwrap_err()
+            .ends_with("Invalid grammar name \"../../x\", only a-z, 0-9, _ and - are allowed"));
+    }
+}
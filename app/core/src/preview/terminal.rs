@@ -0,0 +1,377 @@
+// Render Markdown for terminals, with ANSI escape sequences instead of HTML. Headings, lists,
+// quotes, tables and highlighted code blocks use the colours of the same Theme as the HTML preview.
+
+use comrak::{
+    arena_tree::Node,
+    nodes::{Ast, ListType, NodeValue},
+    parse_document, Arena,
+};
+use std::cell::RefCell;
+use unicode_width::UnicodeWidthStr;
+
+use crate::{
+    preview::{
+        budget::{run_with_cancellation_deadline, PreviewBudget},
+        highlight_diagnostics::HighlightError,
+        highlighter_cache::HIGHLIGHTER_CACHE,
+        options::PreviewOptions,
+        tree_sitter_grammars::TreeSitterGrammarsManager,
+    },
+    theming::{
+        ansi::{strip_control_chars, AnsiRenderer, ColorMode},
+        theme::Theme,
+    },
+};
+
+type AstNode<'a> = Node<'a, RefCell<Ast>>;
+
+const BOLD: &str = "\x1b[1m";
+const RESET: &str = "\x1b[0m";
+
+/// Markdown renderer for terminals
+pub struct TerminalRenderer<'a> {
+    manager: &'a TreeSitterGrammarsManager,
+    ansi: AnsiRenderer<'a>,
+    budget: PreviewBudget,
+    options: PreviewOptions,
+}
+
+impl<'a> TerminalRenderer<'a> {
+    pub fn new(manager: &'a TreeSitterGrammarsManager, theme: &'a Theme, mode: ColorMode) -> Self {
+        TerminalRenderer {
+            manager,
+            ansi: AnsiRenderer::new(theme, mode),
+            budget: PreviewBudget::default(),
+            options: PreviewOptions::default(),
+        }
+    }
+
+    /// Replace the default Markdown extensions, the [preview] table of the front matter of the
+    /// document can still override them
+    pub fn with_options(mut self, options: PreviewOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Replace the default limits applied on code blocks
+    pub fn with_budget(mut self, budget: PreviewBudget) -> Self {
+        self.budget = budget;
+        self
+    }
+
+    /// Render the Markdown document into text with ANSI escape sequences. An invalid [preview]
+    /// table in the front matter is ignored, like in the HTML preview.
+    pub fn render(&self, source: &str) -> String {
+        let (options, _) = self.options.clone().with_front_matter(source);
+        let arena = Arena::new();
+        let root = parse_document(&arena, source, &options.to_comrak_options());
+        self.render_block(root)
+    }
+
+    /// Render a block node into lines ending with a new line
+    fn render_block<'b>(&self, node: &'b AstNode<'b>) -> String {
+        let value = node.data.borrow().value.clone();
+        match value {
+            NodeValue::FrontMatter(_) => String::new(),
+            NodeValue::Paragraph => format!("{}\n", self.render_inlines(node)),
+            NodeValue::Heading(heading) => {
+                let text = format!(
+                    "{} {}",
+                    "#".repeat(heading.level as usize),
+                    plain_text(node)
+                );
+                format!(
+                    "{}\n",
+                    self.ansi
+                        .paint(&text, &format!("markup.heading.{}", heading.level))
+                )
+            }
+            NodeValue::BlockQuote | NodeValue::MultilineBlockQuote(_) | NodeValue::Alert(_) => {
+                let marker = self.ansi.paint("│ ", "markup.quote");
+                prefix_lines(&self.render_children(node, false), &marker, &marker)
+            }
+            NodeValue::List(list) => self.render_list(node, list.list_type, list.start, list.tight),
+            NodeValue::CodeBlock(code_block) => {
+                let lang = code_block
+                    .info
+                    .split_whitespace()
+                    .next()
+                    .unwrap_or_default();
+                self.render_code(lang, &code_block.literal)
+            }
+            NodeValue::HtmlBlock(html) => strip_control_chars(&html.literal).to_string(),
+            NodeValue::ThematicBreak => format!("{}\n", "─".repeat(40)),
+            NodeValue::Table(_) => self.render_table(node),
+            NodeValue::Math(math) => format!(
+                "{}\n",
+                self.ansi
+                    .paint(&format!("$${}$$", math.literal), "markup.raw.block")
+            ),
+            NodeValue::FootnoteDefinition(footnote) => {
+                let marker = format!("[^{}]: ", strip_control_chars(&footnote.name));
+                let indent = " ".repeat(visible_width(&marker));
+                prefix_lines(&self.render_children(node, true), &marker, &indent)
+            }
+            _ => self.render_children(node, false),
+        }
+    }
+
+    /// Render the children blocks, separated by an empty line if not tight
+    fn render_children<'b>(&self, node: &'b AstNode<'b>, tight: bool) -> String {
+        let separator = if tight { "" } else { "\n" };
+        node.children()
+            .map(|child| self.render_block(child))
+            .filter(|block| !block.is_empty())
+            .collect::<Vec<String>>()
+            .join(separator)
+    }
+
+    fn render_list<'b>(
+        &self,
+        node: &'b AstNode<'b>,
+        list_type: ListType,
+        start: usize,
+        tight: bool,
+    ) -> String {
+        let items: Vec<String> = node
+            .children()
+            .enumerate()
+            .map(|(index, item)| {
+                let mut marker = match list_type {
+                    ListType::Bullet => self.ansi.paint("•", "markup.list.unnumbered"),
+                    ListType::Ordered => self
+                        .ansi
+                        .paint(&format!("{}.", start + index), "markup.list.numbered"),
+                };
+                marker.push(' ');
+                if let NodeValue::TaskItem(task) = &item.data.borrow().value {
+                    marker.push_str(&match task.symbol {
+                        Some(_) => self.ansi.paint("[x]", "markup.list.checked"),
+                        None => self.ansi.paint("[ ]", "markup.list.unchecked"),
+                    });
+                    marker.push(' ');
+                }
+                let indent = " ".repeat(visible_width(&marker));
+                prefix_lines(&self.render_children(item, tight), &marker, &indent)
+            })
+            .collect();
+        items.join(if tight { "" } else { "\n" })
+    }
+
+    /// Highlight the code with the grammar of the lang, or print it as is
+    fn render_code(&self, lang: &str, code: &str) -> String {
+        if lang.is_empty() || code.len() > self.budget.max_code_block_bytes {
+            return self.ansi.plain_block(code);
+        }
+        HIGHLIGHTER_CACHE
+            .get_or_create(self.manager, lang)
+            .and_then(|highlighter| {
+                run_with_cancellation_deadline(self.budget.highlight_timeout, |flag| {
+                    self.ansi.highlight(&highlighter, code, Some(flag))
+                })
+            })
+            .unwrap_or_else(|_: HighlightError| self.ansi.plain_block(code))
+    }
+
+    /// Render the table with aligned columns, the header row is bold
+    fn render_table<'b>(&self, node: &'b AstNode<'b>) -> String {
+        let rows: Vec<(bool, Vec<String>)> = node
+            .children()
+            .map(|row| {
+                let is_header = matches!(row.data.borrow().value, NodeValue::TableRow(true));
+                (is_header, row.children().map(plain_text).collect())
+            })
+            .collect();
+        let columns = rows.iter().map(|(_, cells)| cells.len()).max().unwrap_or(0);
+        let widths: Vec<usize> = (0..columns)
+            .map(|column| {
+                rows.iter()
+                    .filter_map(|(_, cells)| cells.get(column))
+                    .map(|cell| cell.width())
+                    .max()
+                    .unwrap_or_default()
+            })
+            .collect();
+
+        let mut output = String::new();
+        for (is_header, cells) in rows {
+            let line = (0..columns)
+                .map(|column| {
+                    let cell = cells.get(column).map(String::as_str).unwrap_or_default();
+                    let padding = " ".repeat(widths[column] - cell.width());
+                    match is_header {
+                        true => format!("{BOLD}{cell}{RESET}{padding}"),
+                        false => format!("{cell}{padding}"),
+                    }
+                })
+                .collect::<Vec<String>>()
+                .join(" │ ");
+            output.push_str(line.trim_end());
+            output.push('\n');
+            if is_header {
+                let separator = widths
+                    .iter()
+                    .map(|width| "─".repeat(*width))
+                    .collect::<Vec<String>>()
+                    .join("─┼─");
+                output.push_str(&separator);
+                output.push('\n');
+            }
+        }
+        output
+    }
+
+    /// Render the inline children of a block, like text, emphasis, links and inline code
+    fn render_inlines<'b>(&self, node: &'b AstNode<'b>) -> String {
+        node.children()
+            .map(|child| {
+                let value = child.data.borrow().value.clone();
+                match value {
+                    NodeValue::Text(text) => strip_control_chars(&text).to_string(),
+                    NodeValue::SoftBreak => " ".to_string(),
+                    NodeValue::LineBreak => "\n".to_string(),
+                    NodeValue::Code(code) => self.ansi.paint(&code.literal, "markup.raw.inline"),
+                    NodeValue::HtmlInline(html) => strip_control_chars(&html).to_string(),
+                    NodeValue::Math(math) => {
+                        let dollars = if math.display_math { "$$" } else { "$" };
+                        self.ansi.paint(
+                            &format!("{dollars}{}{dollars}", math.literal),
+                            "markup.raw.inline",
+                        )
+                    }
+                    // Nested styles are lost, as the reset of the inner style would end the outer one
+                    NodeValue::Emph => self.ansi.paint(&plain_text(child), "markup.italic"),
+                    NodeValue::Strong => self.ansi.paint(&plain_text(child), "markup.bold"),
+                    NodeValue::Strikethrough => {
+                        self.ansi.paint(&plain_text(child), "markup.strikethrough")
+                    }
+                    NodeValue::Link(link) => {
+                        let text = plain_text(child);
+                        let mut rendered = self.ansi.paint(&text, "markup.link.text");
+                        if text != link.url && !link.url.is_empty() {
+                            rendered.push(' ');
+                            rendered.push_str(&self.ansi.paint(&link.url, "markup.link.url"));
+                        }
+                        rendered
+                    }
+                    NodeValue::Image(link) => format!(
+                        "{} {}",
+                        self.ansi.paint(
+                            &format!("[image: {}]", plain_text(child)),
+                            "markup.link.text"
+                        ),
+                        self.ansi.paint(&link.url, "markup.link.url")
+                    ),
+                    NodeValue::FootnoteReference(footnote) => self
+                        .ansi
+                        .paint(&format!("[^{}]", footnote.name), "markup.link.label"),
+                    _ => self.render_inlines(child),
+                }
+            })
+            .collect()
+    }
+}
+
+/// The text of all descendants without any style
+fn plain_text<'b>(node: &'b AstNode<'b>) -> String {
+    let mut text = String::new();
+    for descendant in node.descendants() {
+        match &descendant.data.borrow().value {
+            NodeValue::Text(t) => text.push_str(t),
+            NodeValue::Code(code) => text.push_str(&code.literal),
+            NodeValue::Math(math) => text.push_str(&math.literal),
+            NodeValue::SoftBreak | NodeValue::LineBreak => text.push(' '),
+            _ => {}
+        }
+    }
+    strip_control_chars(&text).to_string()
+}
+
+/// Put the first prefix before the first line, and the other prefix before the next lines
+fn prefix_lines(text: &str, first: &str, others: &str) -> String {
+    text.lines()
+        .enumerate()
+        .map(|(index, line)| {
+            let prefix = if index == 0 { first } else { others };
+            format!("{prefix}{line}\n")
+        })
+        .collect()
+}
+
+/// The number of columns displayed, without the escape sequences
+fn visible_width(text: &str) -> usize {
+    strip_escape_sequences(text).width()
+}
+
+/// The text without the escape sequences of colours and styles
+fn strip_escape_sequences(text: &str) -> String {
+    let mut visible = String::with_capacity(text.len());
+    let mut in_sequence = false;
+    for c in text.chars() {
+        match (in_sequence, c) {
+            (false, '\x1b') => in_sequence = true,
+            (true, 'm') => in_sequence = false,
+            (false, _) => visible.push(c),
+            _ => {}
+        }
+    }
+    visible
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::{strip_escape_sequences, visible_width, TerminalRenderer};
+    use crate::{
+        preview::{
+            options::PreviewOptions,
+            tree_sitter_grammars::{
+                get_unique_local_tree_sitter_grammars_folder, TreeSitterGrammarsManager,
+            },
+        },
+        theming::{
+            ansi::ColorMode,
+            helix::ALL_HIGHLIGHT_NAMES_SUPPORTED_BY_HELIX,
+            theme::{Theme, DEFAULT_THEME},
+        },
+    };
+
+    #[test]
+    fn test_markdown_is_rendered_for_terminals() {
+        let manager = TreeSitterGrammarsManager::new_with_grammars_folder(
+            get_unique_local_tree_sitter_grammars_folder(),
+        )
+        .unwrap();
+        let theme =
+            Theme::from_helix(DEFAULT_THEME, ALL_HIGHLIGHT_NAMES_SUPPORTED_BY_HELIX).unwrap();
+        let renderer = TerminalRenderer::new(&manager, &theme, ColorMode::TrueColor);
+        let given = "---\ntitle: a\n---\n# Notes\n\n> a **b**\n\n- [x] done\n- todo\n  second\n\n| a | long |\n|---|---|\n| ccc | d |\n\n```zonk\nx\x1b[2J\n```\n";
+        let result = renderer.render(given);
+        // Without the escape sequences, to check the layout
+        assert_eq!(
+            strip_escape_sequences(&result),
+            "# Notes\n\n│ a b\n\n• [x] done\n• todo second\n\na   │ long\n────┼─────\nccc │ d\n\nx[2J\n"
+        );
+        // "markup.heading.1" = "red"
+        assert!(result.starts_with("\x1b[38;2;210;15;57m# Notes\x1b[0m\n"));
+        assert_eq!(visible_width("\x1b[1m\x1b[38;2;1;2;3mab\x1b[0m c"), 4);
+
+        // Wide characters take 2 columns
+        let result = renderer.render("| 名前 | b |\n|---|---|\n| 😀x | cc |\n");
+        assert_eq!(
+            strip_escape_sequences(&result),
+            "名前 │ b\n─────┼───\n😀x  │ cc\n"
+        );
+
+        // The extensions follow the options and the front matter
+        assert_eq!(
+            strip_escape_sequences(&renderer.render("~~b~~\n")),
+            "~~b~~\n"
+        );
+        let given = "---\n[preview]\nstrikethrough = true\n---\n~~b~~\n";
+        assert_eq!(strip_escape_sequences(&renderer.render(given)), "b\n");
+        let renderer = renderer.with_options(PreviewOptions::default().strikethrough(true));
+        assert_eq!(strip_escape_sequences(&renderer.render("~~b~~\n")), "b\n");
+    }
+}
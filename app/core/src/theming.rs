@@ -1,4 +1,10 @@
+pub mod alerts;
+pub mod ansi;
+pub mod catalog;
+pub mod contrast;
+pub mod document_style;
 pub mod error;
 pub mod helix;
 pub mod renderer;
+pub mod textmate;
 pub mod theme;
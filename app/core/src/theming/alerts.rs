@@ -0,0 +1,26 @@
+// The style of the GitHub alerts rendered by `preview::alerts`: the layout and icons are the
+// same for all themes, the colours come from the highlight names of the theme, see
+// `Renderer::css_for_alerts()`.
+
+/// The alert types, with the highlight name of the theme giving their colour
+pub const ALERT_COLORS: [(&str, &str); 5] = [
+    ("note", "function"),
+    ("tip", "diff.plus"),
+    ("important", "keyword"),
+    ("warning", "diff.delta"),
+    ("caution", "diff.minus"),
+];
+
+/// The style of the alerts that doesn't depend on the theme
+pub const ALERTS_CSS: &str = r#".markdown-alert{margin:0 0 1em;padding:.5em 1em;border-left:.25em solid;}
+.markdown-alert>:last-child{margin-bottom:0;}
+.markdown-alert-title{margin:0 0 .5em;font-weight:600;}
+details.markdown-alert>summary{cursor:pointer;}
+details.markdown-alert:not([open])>summary{margin:0;}
+.markdown-alert-icon{margin-right:.5em;}
+.markdown-alert-note .markdown-alert-icon::before{content:"\2139";}
+.markdown-alert-tip .markdown-alert-icon::before{content:"\1F4A1";}
+.markdown-alert-important .markdown-alert-icon::before{content:"\1F4AC";}
+.markdown-alert-warning .markdown-alert-icon::before{content:"\26A0";}
+.markdown-alert-caution .markdown-alert-icon::before{content:"\1F6D1";}
+"#;
@@ -0,0 +1,277 @@
+// Terminal syntax highlighting renderer, the equivalent of `Renderer` for ANSI escape sequences.
+// It uses the same Theme, so code looks the same in a terminal as in the desktop app.
+
+use std::{borrow::Cow, fmt::Write, sync::atomic::AtomicUsize};
+
+use unicode_width::UnicodeWidthStr;
+
+use crate::{
+    preview::{
+        highlight_diagnostics::HighlightError, tree_sitter_highlight::TreeSitterHighlighter,
+    },
+    theming::theme::{parse_hex_color, Style, Theme, UnderlineStyle},
+};
+
+pub const RESET: &str = "\x1b[0m";
+
+/// The colours supported by the terminal
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorMode {
+    /// 24-bit colours, the exact colours of the theme
+    TrueColor,
+    /// The 256 colours palette of xterm, the colours of the theme are approximated
+    Ansi256,
+}
+
+impl ColorMode {
+    /// Detect the colours supported by the terminal via the COLORTERM environment variable
+    /// that is set by most terminals supporting 24-bit colours
+    pub fn detect() -> Self {
+        match std::env::var("COLORTERM").as_deref() {
+            Ok("truecolor") | Ok("24bit") => ColorMode::TrueColor,
+            _ => ColorMode::Ansi256,
+        }
+    }
+}
+
+/// Terminal syntax highlighting renderer
+pub struct AnsiRenderer<'a> {
+    theme: &'a Theme<'a>,
+    mode: ColorMode,
+}
+
+impl<'a> AnsiRenderer<'a> {
+    pub fn new(theme: &'a Theme, mode: ColorMode) -> Self {
+        Self { theme, mode }
+    }
+
+    /// Paint a text with the style of the given highlight name, or its parent like "markup" for
+    /// "markup.bold". The text is returned as is if the theme doesn't style it.
+    pub fn paint(&self, text: &str, highlight_name: &str) -> String {
+        let text = strip_control_chars(text);
+        match self.theme.style_of(highlight_name) {
+            Some(style) if !text.is_empty() => {
+                format!("{}{text}{RESET}", self.style_sequence(style))
+            }
+            _ => text.to_string(),
+        }
+    }
+
+    /// Highlight the code with the given highlighter, on the background of the theme.
+    /// Every line is padded to the longest line, to look like a block as in HTML.
+    pub fn highlight(
+        &self,
+        highlighter: &TreeSitterHighlighter,
+        code: &str,
+        cancellation_flag: Option<&AtomicUsize>,
+    ) -> Result<String, HighlightError> {
+        let mut lines: Vec<Vec<(String, Option<String>)>> = vec![Vec::new()];
+        highlighter.try_highlight_tokens(code, cancellation_flag, |token, name| {
+            for (index, part) in token.split('\n').enumerate() {
+                if index > 0 {
+                    lines.push(Vec::new());
+                }
+                if !part.is_empty() {
+                    if let Some(line) = lines.last_mut() {
+                        line.push((part.to_string(), name.map(str::to_string)));
+                    }
+                }
+            }
+        })?;
+        Ok(self.render_block(lines))
+    }
+
+    /// Render the code as a block without highlighting, with the colours of the theme
+    pub fn plain_block(&self, code: &str) -> String {
+        self.render_block(
+            code.split('\n')
+                .map(|line| match line.is_empty() {
+                    true => Vec::new(),
+                    false => vec![(line.to_string(), None)],
+                })
+                .collect(),
+        )
+    }
+
+    fn render_block(&self, mut lines: Vec<Vec<(String, Option<String>)>>) -> String {
+        // The final newline of the code is not a line
+        if lines.last().is_some_and(|line| line.is_empty()) && lines.len() > 1 {
+            lines.pop();
+        }
+        let lines: Vec<Vec<(String, Option<String>)>> = lines
+            .into_iter()
+            .map(|line| {
+                line.into_iter()
+                    .map(|(text, name)| (strip_control_chars(&text).replace('\t', "    "), name))
+                    .collect()
+            })
+            .collect();
+        let width = |line: &Vec<(String, Option<String>)>| -> usize {
+            line.iter().map(|(text, _)| text.width()).sum()
+        };
+        let max_width = lines.iter().map(width).max().unwrap_or_default();
+
+        let base = format!(
+            "{}{}",
+            self.color_sequence(48, &self.theme.background),
+            self.color_sequence(38, &self.theme.foreground)
+        );
+        let mut output = String::new();
+        for line in &lines {
+            output.push_str(&base);
+            for (text, name) in line {
+                match name.as_deref().and_then(|name| self.theme.style_of(name)) {
+                    Some(style) => {
+                        let _ = write!(output, "{}{text}{RESET}{base}", self.style_sequence(style));
+                    }
+                    None => output.push_str(text),
+                }
+            }
+            let _ = writeln!(
+                output,
+                "{}{RESET}",
+                " ".repeat(max_width.saturating_sub(width(line)))
+            );
+        }
+        output
+    }
+
+    /// The escape sequence to apply the colours and modifiers of a style
+    fn style_sequence(&self, style: &Style) -> String {
+        let mut sequence = String::new();
+        if style.is_bold {
+            sequence.push_str("\x1b[1m");
+        }
+        if style.is_dim {
+            sequence.push_str("\x1b[2m");
+        }
+        if style.is_italic {
+            sequence.push_str("\x1b[3m");
+        }
+        if let Some(underline) = &style.underline {
+            // The styled underlines are an extension supported by most recent terminals
+            let _ = write!(
+                sequence,
+                "\x1b[4:{}m",
+                match underline.style {
+                    UnderlineStyle::Line => 1,
+                    UnderlineStyle::DoubleLine => 2,
+                    UnderlineStyle::Curl => 3,
+                    UnderlineStyle::Dotted => 4,
+                    UnderlineStyle::Dashed => 5,
+                }
+            );
+            if let Some(color) = &underline.color {
+                sequence.push_str(&self.color_sequence(58, color));
+            }
+        }
+        if style.is_crossed_out {
+            sequence.push_str("\x1b[9m");
+        }
+        if let Some(fg) = &style.fg {
+            sequence.push_str(&self.color_sequence(38, fg));
+        }
+        if let Some(bg) = &style.bg {
+            sequence.push_str(&self.color_sequence(48, bg));
+        }
+        sequence
+    }
+
+    /// The escape sequence to set a colour: 38 for the text, 48 for the background and 58 for
+    /// the underline
+    fn color_sequence(&self, kind: u8, color: &str) -> String {
+        self.color_parameters(color)
+            .map(|color| format!("\x1b[{kind};{color}m"))
+            .unwrap_or_default()
+    }
+
+    /// The parameters of a SGR colour sequence after 38 or 48, like "2;255;0;0" or "5;196"
+    fn color_parameters(&self, color: &str) -> Option<String> {
+        let (r, g, b) = parse_hex_color(color)?;
+        Some(match self.mode {
+            ColorMode::TrueColor => format!("2;{r};{g};{b}"),
+            ColorMode::Ansi256 => format!("5;{}", to_ansi256(r, g, b)),
+        })
+    }
+}
+
+/// Remove the control characters, except new lines and tabs, so a document cannot inject
+/// escape sequences to change the terminal
+pub(crate) fn strip_control_chars(text: &str) -> Cow<'_, str> {
+    if text
+        .chars()
+        .any(|c| c.is_control() && c != '\n' && c != '\t')
+    {
+        Cow::Owned(
+            text.chars()
+                .filter(|c| !c.is_control() || *c == '\n' || *c == '\t')
+                .collect(),
+        )
+    } else {
+        Cow::Borrowed(text)
+    }
+}
+
+/// Find the nearest colour of the xterm 256 colours palette, in the 6x6x6 colour cube
+/// or in the grayscale ramp
+fn to_ansi256(r: u8, g: u8, b: u8) -> u8 {
+    const CUBE_LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+    let nearest_level = |c: u8| -> usize {
+        (0..CUBE_LEVELS.len())
+            .min_by_key(|i| (CUBE_LEVELS[*i] as i32 - c as i32).abs())
+            .unwrap_or_default()
+    };
+    let distance = |(r2, g2, b2): (u8, u8, u8)| -> i32 {
+        let d = |a: u8, b: u8| (a as i32 - b as i32).pow(2);
+        d(r, r2) + d(g, g2) + d(b, b2)
+    };
+
+    let (ri, gi, bi) = (nearest_level(r), nearest_level(g), nearest_level(b));
+    let cube_color = (CUBE_LEVELS[ri], CUBE_LEVELS[gi], CUBE_LEVELS[bi]);
+    let cube_index = 16 + 36 * ri + 6 * gi + bi;
+
+    // The grayscale ramp goes from 8 to 238 by steps of 10
+    let average = (r as u32 + g as u32 + b as u32) / 3;
+    let gray_step = (average.saturating_sub(3) / 10).min(23) as u8;
+    let gray_level = 8 + gray_step * 10;
+    let gray_index = 232 + gray_step as usize;
+
+    if distance((gray_level, gray_level, gray_level)) < distance(cube_color) {
+        gray_index as u8
+    } else {
+        cube_index as u8
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::{strip_control_chars, to_ansi256, AnsiRenderer, ColorMode};
+    use crate::theming::theme::{Theme, DEFAULT_THEME};
+
+    #[test]
+    fn test_paint_uses_theme_colours_in_both_modes() {
+        let theme = Theme::from_helix(DEFAULT_THEME, &["markup.bold", "function"]).unwrap();
+        let truecolor = AnsiRenderer::new(&theme, ColorMode::TrueColor);
+        // "markup.bold" = { fg = "red", modifiers = ["bold"] } with red = "#d20f39"
+        assert_eq!(
+            truecolor.paint("hey", "markup.bold"),
+            "\x1b[1m\x1b[38;2;210;15;57mhey\x1b[0m"
+        );
+        // Dotted names fallback on their parent, unknown names are not styled
+        assert_eq!(
+            truecolor.paint("f", "function.method"),
+            truecolor.paint("f", "function")
+        );
+        assert_eq!(truecolor.paint("x\x1b[2J", "unknown"), "x[2J");
+
+        let ansi256 = AnsiRenderer::new(&theme, ColorMode::Ansi256);
+        assert_eq!(
+            ansi256.paint("hey", "markup.bold"),
+            "\x1b[1m\x1b[38;5;161mhey\x1b[0m"
+        );
+        assert_eq!((to_ansi256(0, 0, 0), to_ansi256(128, 128, 128)), (16, 244));
+        assert_eq!(strip_control_chars("a\tb\n\x07"), "a\tb\n");
+    }
+}
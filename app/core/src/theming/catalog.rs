@@ -0,0 +1,318 @@
+// The list of themes that can be used: the bundled ones and the Helix themes of the user in
+// the themes folder (~/.config/dme/themes on Linux). A user theme with the same name as a
+// bundled theme replaces it, like in Helix. VS Code (.json) and TextMate (.tmTheme) themes
+// in this folder are converted to Helix themes when loaded.
+
+use std::{
+    collections::BTreeSet,
+    fs::{read_dir, read_to_string},
+    path::{Path, PathBuf},
+};
+
+use etcetera::{AppStrategy, AppStrategyArgs};
+use once_cell::sync::Lazy;
+use serde::Serialize;
+
+use crate::theming::{
+    helix::ALL_HIGHLIGHT_NAMES_SUPPORTED_BY_HELIX,
+    textmate::ImportedTheme,
+    theme::{Theme, ThemeAppearance, DEFAULT_THEME},
+};
+
+/// The themes included in the binary, by name
+pub const BUNDLED_THEMES: &[(&str, &str)] = &[
+    ("catppuccin_latte", DEFAULT_THEME),
+    (
+        "catppuccin_frappe",
+        include_str!("default/catppuccin_frappe.toml"),
+    ),
+    (
+        "catppuccin_macchiato",
+        include_str!("default/catppuccin_macchiato.toml"),
+    ),
+    (
+        "catppuccin_mocha",
+        include_str!("default/catppuccin_mocha.toml"),
+    ),
+    (
+        "solarized_light",
+        include_str!("default/solarized_light.toml"),
+    ),
+    (
+        "solarized_dark",
+        include_str!("default/solarized_dark.toml"),
+    ),
+];
+
+/// The extensions of the files in the themes folder: Helix, VS Code and TextMate themes
+const USER_THEME_EXTENSIONS: &[&str] = &["toml", "json", "tmTheme"];
+
+/// The name of `DEFAULT_THEME`
+pub const DEFAULT_THEME_NAME: &str = "catppuccin_latte";
+
+/// The dark theme paired with `DEFAULT_THEME` when following the colour scheme of the system
+pub const DEFAULT_DARK_THEME_NAME: &str = "catppuccin_mocha";
+
+static THEMES_FOLDER_VIA_ENV: Lazy<Option<PathBuf>> =
+    Lazy::new(|| std::env::var("DME_THEMES_FOLDER").ok().map(PathBuf::from));
+
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ThemeSource {
+    Bundled,
+    User,
+}
+
+/// A theme that can be loaded by name
+#[derive(Debug, Serialize, Clone, PartialEq, Eq)]
+pub struct ThemeInfo {
+    pub name: String,
+    /// Detected via the background colour
+    pub appearance: ThemeAppearance,
+    pub source: ThemeSource,
+}
+
+pub struct ThemeCatalog {
+    themes_folder: PathBuf,
+}
+
+impl ThemeCatalog {
+    /// Create a catalog with the default themes folder, or the DME_THEMES_FOLDER environment
+    /// variable if defined
+    pub fn new() -> Result<Self, String> {
+        let themes_folder = match &*THEMES_FOLDER_VIA_ENV {
+            Some(folder) => folder.clone(),
+            None => config_folder()?.join("themes"),
+        };
+        Ok(Self::new_with_themes_folder(themes_folder))
+    }
+
+    /// Create a catalog with another themes folder, it doesn't have to exist
+    pub fn new_with_themes_folder(themes_folder: PathBuf) -> Self {
+        ThemeCatalog { themes_folder }
+    }
+
+    pub fn get_themes_folder(&self) -> &Path {
+        &self.themes_folder
+    }
+
+    /// List the bundled and user themes sorted by name. The user themes that cannot be
+    /// loaded are not listed.
+    pub fn list(&self) -> Vec<ThemeInfo> {
+        let mut themes: Vec<ThemeInfo> = BUNDLED_THEMES
+            .iter()
+            .filter(|(name, _)| self.user_theme_content(name).is_none())
+            .filter_map(|(name, _)| self.info(name, ThemeSource::Bundled))
+            .collect();
+        themes.extend(
+            self.user_theme_names()
+                .iter()
+                .filter_map(|name| self.info(name, ThemeSource::User)),
+        );
+        themes.sort_by(|a, b| a.name.cmp(&b.name));
+        themes
+    }
+
+    /// Get the TOML content of a theme, the user themes first
+    pub fn content_of(&self, name: &str) -> Option<String> {
+        self.user_theme_content(name)
+            .or_else(|| bundled_theme_content(name))
+    }
+
+    /// Load a theme by name, it can inherit from any other theme of the catalog. A user theme
+    /// inheriting from its own name inherits from the bundled theme it replaces, like in Helix.
+    pub fn load<'a>(
+        &self,
+        name: &str,
+        supported_highlight_names: &'a [&'a str],
+    ) -> Result<Theme<'a>, String> {
+        let content = self
+            .content_of(name)
+            .ok_or(format!("Theme {name} not found"))?;
+        Theme::from_helix_with_parents(&content, supported_highlight_names, |parent, child| {
+            match child.unwrap_or(name) == parent {
+                true => bundled_theme_content(parent),
+                false => self.content_of(parent),
+            }
+        })
+        .map_err(|e| format!("Theme {name} couldn't be loaded: {e}"))
+    }
+
+    fn info(&self, name: &str, source: ThemeSource) -> Option<ThemeInfo> {
+        let theme = self
+            .load(name, ALL_HIGHLIGHT_NAMES_SUPPORTED_BY_HELIX)
+            .ok()?;
+        Some(ThemeInfo {
+            name: name.to_string(),
+            appearance: theme.appearance(),
+            source,
+        })
+    }
+
+    fn user_theme_names(&self) -> Vec<String> {
+        let Ok(entries) = read_dir(&self.themes_folder) else {
+            return Vec::new();
+        };
+        entries
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|path| {
+                path.extension()
+                    .is_some_and(|ext| USER_THEME_EXTENSIONS.iter().any(|known| ext == *known))
+            })
+            .filter_map(|path| Some(path.file_stem()?.to_str()?.to_string()))
+            .filter(|name| is_valid_theme_name(name))
+            .collect::<BTreeSet<String>>()
+            .into_iter()
+            .collect()
+    }
+
+    fn user_theme_content(&self, name: &str) -> Option<String> {
+        // The name comes from the frontend or from an inherits, it must not escape the folder
+        if !is_valid_theme_name(name) {
+            return None;
+        }
+        USER_THEME_EXTENSIONS.iter().find_map(|extension| {
+            let content =
+                read_to_string(self.themes_folder.join(format!("{name}.{extension}"))).ok()?;
+            let imported = match *extension {
+                "json" => ImportedTheme::from_vscode_json(&content),
+                "tmTheme" => ImportedTheme::from_tmtheme(&content),
+                _ => return Some(content),
+            };
+            imported.ok().map(|imported| imported.to_helix_toml())
+        })
+    }
+}
+
+fn bundled_theme_content(name: &str) -> Option<String> {
+    BUNDLED_THEMES
+        .iter()
+        .find(|(bundled, _)| *bundled == name)
+        .map(|(_, content)| content.to_string())
+}
+
+/// The config folder of dme, like ~/.config/dme on Linux, for the themes and the document style
+pub fn config_folder() -> Result<PathBuf, String> {
+    Ok(etcetera::choose_app_strategy(AppStrategyArgs {
+        app_name: "dme".to_string(),
+        ..Default::default()
+    })
+    .map_err(|e| format!("Couldn't find the config folder: {e}"))?
+    .config_dir())
+}
+
+fn is_valid_theme_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::write;
+
+    use pretty_assertions::assert_eq;
+
+    use super::{ThemeCatalog, ThemeSource};
+    use crate::preview::tree_sitter_grammars::get_unique_local_tree_sitter_grammars_folder;
+    use crate::theming::theme::ThemeAppearance;
+
+    #[test]
+    fn test_user_themes_are_listed_with_bundled_ones() {
+        let folder = get_unique_local_tree_sitter_grammars_folder();
+        // A dark theme inheriting from a bundled one, and a broken theme
+        write(
+            folder.join("night.toml"),
+            "inherits = \"catppuccin_mocha\"\n\"comment\" = \"#ff0000\"",
+        )
+        .unwrap();
+        write(folder.join("broken.toml"), "\"comment\" = ").unwrap();
+        write(
+            folder.join("vscode.json"),
+            "{\"type\": \"dark\", \"tokenColors\": []}",
+        )
+        .unwrap();
+        let catalog = ThemeCatalog::new_with_themes_folder(folder);
+
+        let themes: Vec<(String, ThemeAppearance, ThemeSource)> = catalog
+            .list()
+            .into_iter()
+            .map(|info| (info.name, info.appearance, info.source))
+            .collect();
+        let theme = |name: &str, appearance, source| (name.to_string(), appearance, source);
+        assert_eq!(
+            themes,
+            vec![
+                theme(
+                    "catppuccin_frappe",
+                    ThemeAppearance::Dark,
+                    ThemeSource::Bundled
+                ),
+                theme(
+                    "catppuccin_latte",
+                    ThemeAppearance::Light,
+                    ThemeSource::Bundled
+                ),
+                theme(
+                    "catppuccin_macchiato",
+                    ThemeAppearance::Dark,
+                    ThemeSource::Bundled
+                ),
+                theme(
+                    "catppuccin_mocha",
+                    ThemeAppearance::Dark,
+                    ThemeSource::Bundled
+                ),
+                theme("night", ThemeAppearance::Dark, ThemeSource::User),
+                theme(
+                    "solarized_dark",
+                    ThemeAppearance::Dark,
+                    ThemeSource::Bundled
+                ),
+                theme(
+                    "solarized_light",
+                    ThemeAppearance::Light,
+                    ThemeSource::Bundled
+                ),
+                theme("vscode", ThemeAppearance::Dark, ThemeSource::User),
+            ]
+        );
+
+        let night = catalog.load("night", &["comment"]).unwrap();
+        assert_eq!(night.background, "#1e1e2e");
+        assert!(catalog.load("../night", &[]).is_err());
+        assert!(catalog.load("broken", &["comment"]).is_err());
+    }
+
+    #[test]
+    fn test_user_theme_can_inherit_from_the_bundled_theme_it_replaces() {
+        let folder = get_unique_local_tree_sitter_grammars_folder();
+        write(
+            folder.join("catppuccin_latte.toml"),
+            "inherits = \"catppuccin_latte\"\n\"comment\" = \"#ff0000\"",
+        )
+        .unwrap();
+        let catalog = ThemeCatalog::new_with_themes_folder(folder);
+
+        let latte = catalog
+            .load("catppuccin_latte", &["comment", "string"])
+            .unwrap();
+        assert_eq!(
+            latte.style_of("comment").unwrap().fg.as_deref(),
+            Some("#ff0000")
+        );
+        assert_eq!(
+            latte.style_of("string").unwrap().fg.as_deref(),
+            Some("#40a02b")
+        );
+        // The dark flavours inherit from the user theme, which inherits from the bundled one
+        let mocha = catalog.load("catppuccin_mocha", &["comment"]).unwrap();
+        assert_eq!(
+            mocha.style_of("comment").unwrap().fg.as_deref(),
+            Some("#ff0000")
+        );
+        assert_eq!(mocha.background, "#1e1e2e");
+    }
+}
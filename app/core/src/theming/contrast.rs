@@ -0,0 +1,267 @@
+// Accessibility audit of the themes: the WCAG contrast ratio of each style against its
+// background, to notice unreadable colours (like comments) before using a theme.
+// https://www.w3.org/TR/WCAG21/#contrast-minimum
+
+use std::fmt;
+
+use serde::Serialize;
+
+use crate::theming::theme::{parse_hex_color, relative_luminance, Style, Theme};
+
+/// The WCAG levels for normal text, the code is never large text
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ContrastLevel {
+    Fail,
+    #[serde(rename = "AA")]
+    Aa,
+    #[serde(rename = "AAA")]
+    Aaa,
+}
+
+impl ContrastLevel {
+    fn of(ratio: f64) -> Self {
+        match ratio {
+            ratio if ratio >= 7.0 => ContrastLevel::Aaa,
+            ratio if ratio >= 4.5 => ContrastLevel::Aa,
+            _ => ContrastLevel::Fail,
+        }
+    }
+
+    /// The minimum ratio to reach this level
+    pub fn min_ratio(&self) -> f64 {
+        match self {
+            ContrastLevel::Fail => 1.0,
+            ContrastLevel::Aa => 4.5,
+            ContrastLevel::Aaa => 7.0,
+        }
+    }
+}
+
+impl fmt::Display for ContrastLevel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ContrastLevel::Fail => write!(f, "fail"),
+            ContrastLevel::Aa => write!(f, "AA"),
+            ContrastLevel::Aaa => write!(f, "AAA"),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct ContrastCheck {
+    /// The highlight name, or "ui.text" for the default text colour
+    pub name: String,
+    /// The colour as displayed, dim colours are mixed with their background
+    pub color: String,
+    pub background: String,
+    /// From 1 to 21
+    pub ratio: f64,
+    pub level: ContrastLevel,
+    /// The closest colour reaching the level asked to `audit()`, if the level is not reached.
+    /// This is the colour to write in the theme, before dimming.
+    pub suggestion: Option<String>,
+}
+
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct ThemeAudit {
+    pub checks: Vec<ContrastCheck>,
+}
+
+impl ThemeAudit {
+    /// Check the default text colour and the text colour of all styles of the theme, the styles
+    /// without a text colour are checked with the default one. With a level, the colours below
+    /// this level get a suggestion.
+    pub fn audit(theme: &Theme, suggestions_for: Option<ContrastLevel>) -> Self {
+        let mut styles: Vec<(&str, &Style)> = theme
+            .style_map
+            .iter()
+            .map(|(index, style)| (theme.supported_highlight_names[*index], style))
+            .collect();
+        styles.sort_by_key(|(name, _)| *name);
+
+        let text = Style {
+            fg: Some(theme.foreground.clone()),
+            ..Default::default()
+        };
+        let checks = std::iter::once(("ui.text", &text))
+            .chain(styles)
+            .filter_map(|(name, style)| {
+                let background = style.bg.as_deref().unwrap_or(&theme.background);
+                check(name, style, &theme.foreground, background, suggestions_for)
+            })
+            .collect();
+        ThemeAudit { checks }
+    }
+
+    /// The checks below the given level
+    pub fn failures(&self, level: ContrastLevel) -> Vec<&ContrastCheck> {
+        self.checks
+            .iter()
+            .filter(|check| check.level < level)
+            .collect()
+    }
+
+    /// Explain the checks below the given level, one line per check
+    pub fn summary(&self, level: ContrastLevel) -> Vec<String> {
+        self.failures(level)
+            .iter()
+            .map(|check| {
+                let mut line = format!(
+                    "{} {} on {} has a contrast of {:.2}:1, below {} ({}:1)",
+                    check.name,
+                    check.color,
+                    check.background,
+                    check.ratio,
+                    level,
+                    level.min_ratio()
+                );
+                if let Some(suggestion) = &check.suggestion {
+                    line.push_str(&format!(", try {suggestion}"));
+                }
+                line
+            })
+            .collect()
+    }
+}
+
+/// The contrast ratio between two colours, from 1 to 21
+pub fn contrast_ratio(color: &str, other: &str) -> Option<f64> {
+    let (a, b) = (relative_luminance(color)?, relative_luminance(other)?);
+    let (lighter, darker) = if a > b { (a, b) } else { (b, a) };
+    Some((lighter + 0.05) / (darker + 0.05))
+}
+
+fn check(
+    name: &str,
+    style: &Style,
+    foreground: &str,
+    background: &str,
+    suggestions_for: Option<ContrastLevel>,
+) -> Option<ContrastCheck> {
+    let fg = parse_hex_color(style.fg.as_deref().unwrap_or(foreground))?;
+    let bg = parse_hex_color(background)?;
+    // The dim text is rendered with an opacity of 0.6 on the background
+    let displayed = |color: Rgb| match style.is_dim {
+        true => mix(color, bg, 0.4),
+        false => color,
+    };
+    let ratio_of = |color: Rgb| contrast_ratio(&hex(displayed(color)), background);
+
+    let ratio = ratio_of(fg)?;
+    let level = ContrastLevel::of(ratio);
+    let suggestion = suggestions_for
+        .filter(|target| level < *target)
+        .and_then(|target| closest_passing_color(fg, target.min_ratio(), ratio_of))
+        .map(hex);
+    Some(ContrastCheck {
+        name: name.to_string(),
+        color: hex(displayed(fg)),
+        background: background.to_string(),
+        ratio,
+        level,
+        suggestion,
+    })
+}
+
+type Rgb = (u8, u8, u8);
+
+/// Mix the colour with black or white, as little as possible to reach the ratio, and keep the
+/// closest one of both
+fn closest_passing_color(
+    color: Rgb,
+    min_ratio: f64,
+    ratio_of: impl Fn(Rgb) -> Option<f64>,
+) -> Option<Rgb> {
+    [(0, 0, 0), (255, 255, 255)]
+        .into_iter()
+        .filter_map(|target| {
+            let passes =
+                |amount: f64| ratio_of(mix(color, target, amount)).unwrap_or(0.0) >= min_ratio;
+            if !passes(1.0) {
+                return None;
+            }
+            // The ratio grows with the amount of black or white, so a binary search is enough
+            let (mut low, mut high) = (0.0, 1.0);
+            for _ in 0..16 {
+                let middle = (low + high) / 2.0;
+                if passes(middle) {
+                    high = middle;
+                } else {
+                    low = middle;
+                }
+            }
+            Some((high, mix(color, target, high)))
+        })
+        .min_by(|(a, _), (b, _)| a.total_cmp(b))
+        .map(|(_, color)| color)
+}
+
+/// Mix `color` with `other`, with 0 to 1 of `other`
+fn mix(color: Rgb, other: Rgb, amount: f64) -> Rgb {
+    let channel = |a: u8, b: u8| (a as f64 + (b as f64 - a as f64) * amount).round() as u8;
+    (
+        channel(color.0, other.0),
+        channel(color.1, other.1),
+        channel(color.2, other.2),
+    )
+}
+
+fn hex((r, g, b): Rgb) -> String {
+    format!("#{r:02x}{g:02x}{b:02x}")
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::{contrast_ratio, ContrastLevel, ThemeAudit};
+    use crate::theming::theme::Theme;
+
+    #[test]
+    fn test_theme_audit_flags_unreadable_colours_with_suggestions() {
+        assert_eq!(contrast_ratio("#000", "#fff"), Some(21.0));
+
+        let theme = Theme::from_helix(
+            r##""ui.background" = { bg = "#ffffff" }
+"ui.text" = "#000000"
+"comment" = { fg = "#aaaaaa" }
+"keyword" = { fg = "#595959", modifiers = ["dim"] }
+"string" = { fg = "#ffffff", bg = "#767676" }
+"markup.quote" = { bg = "#000000" }"##,
+            &["comment", "keyword", "string", "markup.quote"],
+        )
+        .unwrap();
+        let audit = ThemeAudit::audit(&theme, Some(ContrastLevel::Aa));
+        let levels: Vec<(&str, ContrastLevel)> = audit
+            .checks
+            .iter()
+            .map(|check| (check.name.as_str(), check.level))
+            .collect();
+        assert_eq!(
+            levels,
+            vec![
+                ("ui.text", ContrastLevel::Aaa),
+                ("comment", ContrastLevel::Fail),
+                // #595959 passes AAA but not once dimmed
+                ("keyword", ContrastLevel::Fail),
+                // Without its own colour, the default black text is on a black background
+                ("markup.quote", ContrastLevel::Fail),
+                ("string", ContrastLevel::Aa),
+            ]
+        );
+
+        let comment = &audit.checks[1];
+        let suggestion = comment.suggestion.as_deref().unwrap();
+        let ratio = contrast_ratio(suggestion, "#ffffff").unwrap();
+        assert!((4.5..4.7).contains(&ratio), "{suggestion} has {ratio}");
+        assert_eq!(audit.checks[2].color, "#9b9b9b");
+        assert_eq!(audit.checks[3].color, "#000000");
+        assert!(audit.checks[4].suggestion.is_none());
+
+        assert_eq!(audit.failures(ContrastLevel::Aaa).len(), 4);
+        assert_eq!(
+            audit.summary(ContrastLevel::Aa)[0],
+            format!("comment #aaaaaa on #ffffff has a contrast of 2.32:1, below AA (4.5:1), try {suggestion}")
+        );
+    }
+}
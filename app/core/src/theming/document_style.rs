@@ -0,0 +1,718 @@
+// The style of the whole document, not just the code: fonts, headings, links, tables,
+// blockquotes, figures, math and page margins, defined in a TOML file like
+//
+// [text]
+// font_family = "Inter, sans-serif"
+// [headings.h1]
+// color = "#1e66f5"
+//
+// The values end in a <style> that is not sanitized, so they are all validated before
+// generating the CSS, and the CSS is scoped to the document to not break the rest of the page.
+
+use std::{fmt, fs::read_to_string, path::Path};
+
+use serde::{Deserialize, Serialize};
+
+/// The class of the element wrapping the document, all rules are scoped inside it
+pub const DOCUMENT_SCOPE: &str = "dme-document";
+
+#[derive(Debug, Deserialize, Default, Clone, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct DocumentStyle {
+    pub page: PageStyle,
+    pub text: TextStyle,
+    pub headings: HeadingsStyle,
+    pub links: LinksStyle,
+    pub code: CodeStyle,
+    pub tables: TablesStyle,
+    pub blockquotes: BlockquotesStyle,
+    pub figures: FiguresStyle,
+    pub math: MathStyle,
+}
+
+#[derive(Debug, Deserialize, Default, Clone, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct PageStyle {
+    /// Around the document, and the margins of printed pages
+    pub margin: Option<String>,
+    pub max_width: Option<String>,
+    pub background: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Default, Clone, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct TextStyle {
+    pub font_family: Option<String>,
+    pub font_size: Option<String>,
+    /// A number like "1.5" or a length
+    pub line_height: Option<String>,
+    pub color: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Default, Clone, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct HeadingsStyle {
+    /// Applied on all headings, the levels below override them
+    pub font_family: Option<String>,
+    pub color: Option<String>,
+    pub font_weight: Option<String>,
+    pub h1: HeadingStyle,
+    pub h2: HeadingStyle,
+    pub h3: HeadingStyle,
+    pub h4: HeadingStyle,
+    pub h5: HeadingStyle,
+    pub h6: HeadingStyle,
+}
+
+#[derive(Debug, Deserialize, Default, Clone, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct HeadingStyle {
+    pub font_size: Option<String>,
+    pub color: Option<String>,
+    pub font_weight: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Default, Clone, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct LinksStyle {
+    pub color: Option<String>,
+    pub underline: Option<bool>,
+}
+
+#[derive(Debug, Deserialize, Default, Clone, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct CodeStyle {
+    pub font_family: Option<String>,
+    pub font_size: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Default, Clone, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct TablesStyle {
+    pub border_color: Option<String>,
+    pub header_background: Option<String>,
+    /// The background of one row out of two
+    pub striped_background: Option<String>,
+    pub cell_padding: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Default, Clone, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct BlockquotesStyle {
+    pub border_color: Option<String>,
+    pub background: Option<String>,
+    pub color: Option<String>,
+    pub italic: Option<bool>,
+}
+
+#[derive(Debug, Deserialize, Default, Clone, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct FiguresStyle {
+    pub align: Option<Align>,
+    pub caption_color: Option<String>,
+    pub caption_font_size: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Default, Clone, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct MathStyle {
+    pub color: Option<String>,
+    /// The math expressions are scaled with the font size
+    pub font_size: Option<String>,
+    pub block_align: Option<Align>,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Align {
+    Left,
+    Center,
+    Right,
+}
+
+impl Align {
+    fn css(&self) -> &'static str {
+        match self {
+            Align::Left => "left",
+            Align::Center => "center",
+            Align::Right => "right",
+        }
+    }
+}
+
+/// An invalid value, or an invalid TOML file, the key is None when the error is not on a key
+#[derive(Debug, Serialize, Clone, PartialEq, Eq)]
+pub struct StyleError {
+    /// The dotted key of the value like "headings.h1.color"
+    pub key: Option<String>,
+    pub message: String,
+}
+
+impl fmt::Display for StyleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.key {
+            Some(key) => write!(f, "`{key}`: {}", self.message),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+/// What a value must look like to be accepted
+#[derive(Clone, Copy)]
+enum ValueKind {
+    /// "#rgb", "#rrggbb" with an optional alpha, or a name like "teal"
+    Color,
+    /// "12px", "1.5em", "0"...
+    Length,
+    /// A length or a number without unit
+    LineHeight,
+    /// "normal", "bold" or a weight from 100 to 900
+    FontWeight,
+    /// Font names separated by commas, quoted or not
+    FontFamily,
+    /// Generated by us, not written by the user
+    Keyword,
+}
+
+const LENGTH_UNITS: &[&str] = &[
+    "px", "em", "rem", "%", "pt", "pc", "cm", "mm", "in", "ch", "ex", "vw", "vh",
+];
+
+impl ValueKind {
+    /// Check the value is valid, it also makes sure no CSS can be injected via the value
+    fn check(&self, value: &str) -> Result<(), String> {
+        let is_number = |text: &str| {
+            !text.is_empty()
+                && text.chars().all(|c| c.is_ascii_digit() || c == '.')
+                && text.parse::<f64>().is_ok()
+        };
+        let is_length = |text: &str| {
+            text == "0"
+                || LENGTH_UNITS
+                    .iter()
+                    .any(|unit| text.strip_suffix(unit).is_some_and(&is_number))
+        };
+        let valid = match self {
+            ValueKind::Color => match value.strip_prefix('#') {
+                Some(hex) => {
+                    matches!(hex.len(), 3 | 4 | 6 | 8) && hex.chars().all(|c| c.is_ascii_hexdigit())
+                }
+                None => !value.is_empty() && value.chars().all(|c| c.is_ascii_alphabetic()),
+            },
+            ValueKind::Length => is_length(value),
+            ValueKind::LineHeight => is_number(value) || is_length(value),
+            ValueKind::FontWeight => {
+                matches!(value, "normal" | "bold" | "bolder" | "lighter")
+                    || value
+                        .parse::<u16>()
+                        .is_ok_and(|weight| (100..=900).contains(&weight) && weight % 100 == 0)
+            }
+            // An unpaired quote would open a CSS string swallowing the next declarations
+            ValueKind::FontFamily => value.split(',').all(|font| {
+                let font = font.trim();
+                let name = ['\'', '"']
+                    .iter()
+                    .find_map(|quote| font.strip_prefix(*quote)?.strip_suffix(*quote))
+                    .unwrap_or(font);
+                !name.trim().is_empty()
+                    && name
+                        .chars()
+                        .all(|c| c.is_alphanumeric() || matches!(c, ' ' | '-' | '_'))
+            }),
+            ValueKind::Keyword => true,
+        };
+        if valid {
+            return Ok(());
+        }
+        Err(match self {
+            ValueKind::Color => format!("`{value}` is not a colour like \"#1e66f5\" or \"teal\""),
+            ValueKind::Length => format!(
+                "`{value}` is not a length like \"12px\", the units are {}",
+                LENGTH_UNITS.join(", ")
+            ),
+            ValueKind::LineHeight => {
+                format!("`{value}` is not a number like \"1.5\" or a length like \"24px\"")
+            }
+            ValueKind::FontWeight => {
+                format!("`{value}` is not \"normal\", \"bold\" or a weight from 100 to 900")
+            }
+            ValueKind::FontFamily => format!(
+                "`{value}` is not a list of fonts like \"'Fira Sans', sans-serif\", only letters, digits, spaces, - and _ are allowed in the names, optionally between paired quotes"
+            ),
+            ValueKind::Keyword => String::default(),
+        })
+    }
+}
+
+/// Build the CSS rules and collect the invalid values
+struct CssBuilder {
+    css: String,
+    errors: Vec<StyleError>,
+}
+
+impl CssBuilder {
+    /// Add a rule for the elements inside the document, with the declarations that are given
+    fn rule(&mut self, selector: &str, declarations: &[(&str, &str, Option<&str>, ValueKind)]) {
+        let mut body = String::new();
+        for (key, property, value, kind) in declarations {
+            let Some(value) = value else {
+                continue;
+            };
+            match kind.check(value) {
+                Ok(()) => body.push_str(&format!("{property}:{value};")),
+                Err(message) => self.errors.push(StyleError {
+                    key: Some(key.to_string()),
+                    message,
+                }),
+            }
+        }
+        if !body.is_empty() {
+            let selector = match selector {
+                "" => format!(".{DOCUMENT_SCOPE}"),
+                selector => format!(".{DOCUMENT_SCOPE} {selector}"),
+            };
+            self.css.push_str(&format!("{selector}{{{body}}}\n"));
+        }
+    }
+    /// Check a value used inside a bigger value like "1px solid <color>", the invalid values
+    /// are reported and give None
+    fn composed(
+        &mut self,
+        key: &str,
+        value: Option<&str>,
+        kind: ValueKind,
+        compose: impl Fn(&str) -> String,
+    ) -> Option<String> {
+        let value = value?;
+        match kind.check(value) {
+            Ok(()) => Some(compose(value)),
+            Err(message) => {
+                self.errors.push(StyleError {
+                    key: Some(key.to_string()),
+                    message,
+                });
+                None
+            }
+        }
+    }
+}
+
+/// The dotted key at this position of the TOML file, like "links.colour", from the last table
+/// header before it and the key of its line. None outside of a key/value line.
+fn key_at(content: &str, position: usize) -> Option<String> {
+    let position = position.min(content.len());
+    let line_start = content[..position].rfind('\n').map_or(0, |i| i + 1);
+    let line = content[line_start..].lines().next()?;
+    let (key, _) = line.split_once('=')?;
+    let unquote = |key: &str| key.trim().trim_matches(['"', '\'']).to_string();
+    let key = unquote(key);
+    let table = content[..line_start]
+        .lines()
+        .rev()
+        .map(str::trim)
+        .find(|line| line.starts_with('['))
+        .map(|header| {
+            unquote(
+                header
+                    .trim_start_matches('[')
+                    .split(']')
+                    .next()
+                    .unwrap_or(""),
+            )
+        });
+    match table {
+        Some(table) => Some(format!("{table}.{key}")),
+        None => Some(key),
+    }
+}
+
+impl DocumentStyle {
+    /// Parse a document style, the unknown keys and invalid values are reported
+    pub fn from_toml(content: &str) -> Result<Self, Vec<StyleError>> {
+        let style: DocumentStyle = toml::from_str(content).map_err(|e| {
+            let key = e.span().and_then(|span| key_at(content, span.start));
+            let message = match key {
+                Some(_) => e.message().to_string(),
+                None => e.to_string(),
+            };
+            vec![StyleError { key, message }]
+        })?;
+        style.to_css()?;
+        Ok(style)
+    }
+
+    pub fn from_toml_file(path: &Path) -> Result<Self, Vec<StyleError>> {
+        let content = read_to_string(path).map_err(|e| {
+            vec![StyleError {
+                key: None,
+                message: format!("Couldn't read the document style at {path:?}: {e}"),
+            }]
+        })?;
+        Self::from_toml(&content)
+    }
+
+    /// Generate the CSS scoped inside the element with the `DOCUMENT_SCOPE` class, or the list
+    /// of invalid values
+    pub fn to_css(&self) -> Result<String, Vec<StyleError>> {
+        use ValueKind::*;
+        let mut builder = CssBuilder {
+            css: String::new(),
+            errors: Vec::new(),
+        };
+
+        let page = &self.page;
+        let text = &self.text;
+        builder.rule(
+            "",
+            &[
+                ("page.margin", "padding", page.margin.as_deref(), Length),
+                (
+                    "page.max_width",
+                    "max-width",
+                    page.max_width.as_deref(),
+                    Length,
+                ),
+                (
+                    "page.background",
+                    "background-color",
+                    page.background.as_deref(),
+                    Color,
+                ),
+                (
+                    "text.font_family",
+                    "font-family",
+                    text.font_family.as_deref(),
+                    FontFamily,
+                ),
+                (
+                    "text.font_size",
+                    "font-size",
+                    text.font_size.as_deref(),
+                    Length,
+                ),
+                (
+                    "text.line_height",
+                    "line-height",
+                    text.line_height.as_deref(),
+                    LineHeight,
+                ),
+                ("text.color", "color", text.color.as_deref(), Color),
+            ],
+        );
+
+        let headings = &self.headings;
+        builder.rule(
+            ":is(h1,h2,h3,h4,h5,h6)",
+            &[
+                (
+                    "headings.font_family",
+                    "font-family",
+                    headings.font_family.as_deref(),
+                    FontFamily,
+                ),
+                ("headings.color", "color", headings.color.as_deref(), Color),
+                (
+                    "headings.font_weight",
+                    "font-weight",
+                    headings.font_weight.as_deref(),
+                    FontWeight,
+                ),
+            ],
+        );
+        let levels = [
+            ("h1", &headings.h1),
+            ("h2", &headings.h2),
+            ("h3", &headings.h3),
+            ("h4", &headings.h4),
+            ("h5", &headings.h5),
+            ("h6", &headings.h6),
+        ];
+        for (level, heading) in levels {
+            let key = |name: &str| format!("headings.{level}.{name}");
+            builder.rule(
+                level,
+                &[
+                    (
+                        &key("font_size"),
+                        "font-size",
+                        heading.font_size.as_deref(),
+                        Length,
+                    ),
+                    (&key("color"), "color", heading.color.as_deref(), Color),
+                    (
+                        &key("font_weight"),
+                        "font-weight",
+                        heading.font_weight.as_deref(),
+                        FontWeight,
+                    ),
+                ],
+            );
+        }
+
+        let underline = self
+            .links
+            .underline
+            .map(|underline| if underline { "underline" } else { "none" });
+        builder.rule(
+            "a",
+            &[
+                ("links.color", "color", self.links.color.as_deref(), Color),
+                ("links.underline", "text-decoration", underline, Keyword),
+            ],
+        );
+
+        builder.rule(
+            "code",
+            &[
+                (
+                    "code.font_family",
+                    "font-family",
+                    self.code.font_family.as_deref(),
+                    FontFamily,
+                ),
+                (
+                    "code.font_size",
+                    "font-size",
+                    self.code.font_size.as_deref(),
+                    Length,
+                ),
+            ],
+        );
+
+        let tables = &self.tables;
+        let border = builder.composed(
+            "tables.border_color",
+            tables.border_color.as_deref(),
+            Color,
+            |color| format!("1px solid {color}"),
+        );
+        builder.rule(
+            "table",
+            &[(
+                "tables.border_color",
+                "border-collapse",
+                border.as_ref().map(|_| "collapse"),
+                Keyword,
+            )],
+        );
+        builder.rule(
+            ":is(th,td)",
+            &[
+                ("tables.border_color", "border", border.as_deref(), Keyword),
+                (
+                    "tables.cell_padding",
+                    "padding",
+                    tables.cell_padding.as_deref(),
+                    Length,
+                ),
+            ],
+        );
+        builder.rule(
+            "th",
+            &[(
+                "tables.header_background",
+                "background-color",
+                tables.header_background.as_deref(),
+                Color,
+            )],
+        );
+        builder.rule(
+            "tr:nth-child(even) td",
+            &[(
+                "tables.striped_background",
+                "background-color",
+                tables.striped_background.as_deref(),
+                Color,
+            )],
+        );
+
+        let quotes = &self.blockquotes;
+        let quote_border = builder.composed(
+            "blockquotes.border_color",
+            quotes.border_color.as_deref(),
+            Color,
+            |color| format!("4px solid {color}"),
+        );
+        let italic = quotes
+            .italic
+            .map(|italic| if italic { "italic" } else { "normal" });
+        builder.rule(
+            "blockquote",
+            &[
+                (
+                    "blockquotes.border_color",
+                    "border-left",
+                    quote_border.as_deref(),
+                    Keyword,
+                ),
+                (
+                    "blockquotes.background",
+                    "background-color",
+                    quotes.background.as_deref(),
+                    Color,
+                ),
+                ("blockquotes.color", "color", quotes.color.as_deref(), Color),
+                ("blockquotes.italic", "font-style", italic, Keyword),
+            ],
+        );
+
+        let figures = &self.figures;
+        builder.rule(
+            "figure",
+            &[(
+                "figures.align",
+                "text-align",
+                figures.align.map(|align| align.css()),
+                Keyword,
+            )],
+        );
+        builder.rule(
+            "figcaption",
+            &[
+                (
+                    "figures.caption_color",
+                    "color",
+                    figures.caption_color.as_deref(),
+                    Color,
+                ),
+                (
+                    "figures.caption_font_size",
+                    "font-size",
+                    figures.caption_font_size.as_deref(),
+                    Length,
+                ),
+            ],
+        );
+
+        let math = &self.math;
+        builder.rule(
+            ":is(.math-inline,.math-block)",
+            &[
+                ("math.color", "color", math.color.as_deref(), Color),
+                (
+                    "math.font_size",
+                    "font-size",
+                    math.font_size.as_deref(),
+                    Length,
+                ),
+            ],
+        );
+        if math.color.is_some() {
+            // The SVG are drawn in black by default
+            builder.rule(
+                ".typst-frame",
+                &[("math.color", "fill", Some("currentColor"), Keyword)],
+            );
+        }
+        builder.rule(
+            ".math-block",
+            &[(
+                "math.block_align",
+                "text-align",
+                math.block_align.map(|align| align.css()),
+                Keyword,
+            )],
+        );
+
+        // The margins of printed pages, for the exporters
+        if let Some(margin) = page.margin.as_deref().filter(|m| Length.check(m).is_ok()) {
+            builder
+                .css
+                .push_str(&format!("@page{{margin:{margin};}}\n"));
+        }
+
+        match builder.errors.is_empty() {
+            true => Ok(builder.css),
+            false => Err(builder.errors),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::{DocumentStyle, StyleError};
+
+    #[test]
+    fn test_document_style_compiles_to_scoped_css() {
+        let style = DocumentStyle::from_toml(
+            r##"
+[page]
+margin = "2cm"
+[text]
+font_family = "'Fira Sans', sans-serif"
+line_height = "1.6"
+[headings]
+color = "#1e66f5"
+[headings.h1]
+font_size = "2.5em"
+[links]
+underline = false
+[tables]
+border_color = "gray"
+[math]
+block_align = "center"
+"##,
+        )
+        .unwrap();
+        assert_eq!(
+            style.to_css().unwrap(),
+            ".dme-document{padding:2cm;font-family:'Fira Sans', sans-serif;line-height:1.6;}
+.dme-document :is(h1,h2,h3,h4,h5,h6){color:#1e66f5;}
+.dme-document h1{font-size:2.5em;}
+.dme-document a{text-decoration:none;}
+.dme-document table{border-collapse:collapse;}
+.dme-document :is(th,td){border:1px solid gray;}
+.dme-document .math-block{text-align:center;}
+@page{margin:2cm;}
+"
+        );
+    }
+
+    #[test]
+    fn test_invalid_values_are_reported_with_their_key() {
+        let errors = DocumentStyle::from_toml(
+            "[headings.h2]\ncolor = \"red;}body{display:none\"\nfont_size = \"12 px\"\n[blockquotes]\nborder_color = \"#12\"",
+        )
+        .unwrap_err();
+        let keys: Vec<Option<String>> = errors.iter().map(|e| e.key.clone()).collect();
+        assert_eq!(
+            keys,
+            vec![
+                Some("headings.h2.font_size".to_string()),
+                Some("headings.h2.color".to_string()),
+                Some("blockquotes.border_color".to_string()),
+            ]
+        );
+        assert_eq!(
+            errors[1].to_string(),
+            "`headings.h2.color`: `red;}body{display:none` is not a colour like \"#1e66f5\" or \"teal\""
+        );
+
+        // The quotes must be paired around a whole font name
+        for font_family in [
+            "'Fira Sans",
+            "\"Fira Sans'",
+            "'Fira' Sans'",
+            "Inter,, serif",
+        ] {
+            let toml = format!("[text]\nfont_family = {font_family:?}");
+            let errors = DocumentStyle::from_toml(&toml).unwrap_err();
+            assert_eq!(errors[0].key.as_deref(), Some("text.font_family"));
+        }
+        assert!(
+            DocumentStyle::from_toml("[text]\nfont_family = \"\\\"Fira Sans\\\", Inter\"").is_ok()
+        );
+
+        let unknown = DocumentStyle::from_toml("[page]\n[links]\ncolour = \"red\"").unwrap_err();
+        assert!(
+            matches!(&unknown[..], [StyleError { key: Some(key), message }] if key == "links.colour" && message.starts_with("unknown field `colour`"))
+        );
+        let invalid_type =
+            DocumentStyle::from_toml("[headings.h1] # Title\ncolor = 1").unwrap_err();
+        assert_eq!(invalid_type[0].key.as_deref(), Some("headings.h1.color"));
+        let syntax_error = DocumentStyle::from_toml("[text\nfont_size = 1").unwrap_err();
+        assert_eq!(syntax_error[0].key, None);
+    }
+}
@@ -11,7 +11,16 @@ pub enum Error {
     /// TOML data is not structured like a valid Helix theme.
     #[error("toml does not contain valid helix theme")]
     InvalidTheme,
-    /// A color is referenced but is not defined.
-    #[error("toml color {0} not found")]
-    InvalidColorReference(String),
+    /// A theme inherits from a theme that cannot be found
+    #[error("theme {0} to inherit from not found")]
+    UnknownParentTheme(String),
+    /// A theme inherits from itself, directly or not
+    #[error("theme {0} inherits from itself")]
+    InheritanceCycle(String),
+    /// A VS Code theme could not be parsed as JSON
+    #[error("json parse error: {0}")]
+    Json(#[from] serde_json::Error),
+    /// A VS Code or TextMate theme is not structured as expected
+    #[error("invalid imported theme: {0}")]
+    InvalidImportedTheme(String),
 }
@@ -2,8 +2,11 @@
 // and it has been is adapted to our situation
 // https://github.com/matze/tree-painter
 //
-use crate::theming::theme;
-use std::fmt::Write;
+use crate::theming::{
+    alerts::{ALERTS_CSS, ALERT_COLORS},
+    theme::{self, parse_hex_color, Style, Theme, UnderlineStyle},
+};
+use std::{collections::BTreeSet, fmt::Write};
 
 /// HTML syntax highlighting renderer.
 pub struct Renderer<'a> {
@@ -14,6 +17,72 @@ pub struct Renderer<'a> {
 /// Just to avoid CSS names conflicts with other
 const CSS_SCOPE: &str = "code";
 
+/// How the CSS of a light and dark themes pair switches between them, see `color_scheme_css()`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorSchemeSwitch {
+    /// Follow the system via `@media (prefers-color-scheme: dark)`
+    MediaQuery,
+    /// Follow the `data-theme="light|dark"` attribute of the `<html>` element
+    DataTheme,
+    /// Follow the system, unless `data-theme` is set
+    Both,
+}
+
+/// Generate the CSS of a light and dark themes pair. The light theme is the default one.
+/// The page background and text colour follow the theme, so do the math SVGs that are
+/// drawn with the text colour. With `highlight_names`, only the rules of these names are
+/// generated like in `Renderer::css_for_highlight_names()`. The alerts of `alert_types` get
+/// their style too.
+pub fn color_scheme_css(
+    light: &Theme,
+    dark: &Theme,
+    switch: ColorSchemeSwitch,
+    highlight_names: Option<&BTreeSet<String>>,
+    alert_types: &BTreeSet<String>,
+) -> String {
+    let mut css = page_css(light, "", "light");
+    css.push_str(&Renderer::new(light).css_with_prefix("", highlight_names));
+    css.push_str(&Renderer::new(light).css_for_alerts(alert_types));
+
+    let mut dark_in = |prefix: &str, media_query: bool| {
+        let dark_css = format!(
+            "{}{}{}",
+            page_css(dark, prefix, "dark"),
+            Renderer::new(dark).css_with_prefix(prefix, highlight_names),
+            Renderer::new(dark).alert_colors_css(prefix, alert_types)
+        );
+        match media_query {
+            true => {
+                let _ = writeln!(css, "@media (prefers-color-scheme: dark) {{\n{dark_css}}}");
+            }
+            false => css.push_str(&dark_css),
+        }
+    };
+    match switch {
+        ColorSchemeSwitch::MediaQuery => dark_in("", true),
+        ColorSchemeSwitch::DataTheme => dark_in("[data-theme=dark] ", false),
+        ColorSchemeSwitch::Both => {
+            dark_in(":root:not([data-theme=light]) ", true);
+            dark_in("[data-theme=dark] ", false);
+        }
+    }
+
+    css.push_str(".typst-frame{fill:currentColor;}\n");
+    css
+}
+
+/// The background and text colour of the page, the prefix is used as selector or :root
+fn page_css(theme: &Theme, prefix: &str, scheme: &str) -> String {
+    let selector = match prefix.trim() {
+        "" => ":root",
+        selector => selector,
+    };
+    format!(
+        "{selector} {{background-color:{};color:{};color-scheme:{scheme};}}\n",
+        theme.background, theme.foreground
+    )
+}
+
 impl<'a> Renderer<'a> {
     /// Create a new renderer based on `theme`.
     pub fn new(theme: &'a theme::Theme) -> Self {
@@ -23,46 +92,202 @@ impl<'a> Renderer<'a> {
     /// Generate CSS block to be included in the `<style></style>` block or in an external CSS file.
     /// The generated classes are based on all available highlighting names defined in the `theme`
     pub fn css(&self) -> String {
-        let mut css = format!(
-            "pre {{background-color:{};}}\n{} {{color:{};}}\n",
-            self.theme.background.color, CSS_SCOPE, self.theme.foreground.color
-        );
+        self.css_with_prefix("", None)
+    }
+
+    /// Same as `css()` with only the rules of the given highlight names, the ones found in the
+    /// highlighted code. A name unknown by the theme like "function.method.call" gets the style
+    /// of its closest parent, "function.method" or else "function", like in Helix.
+    pub fn css_for_highlight_names(&self, highlight_names: &BTreeSet<String>) -> String {
+        self.css_with_prefix("", Some(highlight_names))
+    }
+
+    /// The style of the alerts of the given types, like "note", with the colours of the theme.
+    /// Nothing is generated without alerts.
+    pub fn css_for_alerts(&self, alert_types: &BTreeSet<String>) -> String {
+        match alert_types.is_empty() {
+            true => String::new(),
+            false => format!("{ALERTS_CSS}{}", self.alert_colors_css("", alert_types)),
+        }
+    }
 
-        // Just sort the vec in tests to allow regression tests on the output
-        // This is changing the order of iteration otherwise
-        let mut styles: Vec<(_, _)> = self.theme.style_map.iter().collect();
-        styles.sort();
-        for (index, style) in styles {
-            let _ = write!(
+    /// The border and title colour of each alert type, from the colour of a highlight name, or
+    /// the text colour if the theme doesn't style it
+    fn alert_colors_css(&self, prefix: &str, alert_types: &BTreeSet<String>) -> String {
+        let mut css = String::new();
+        for (alert_type, name) in ALERT_COLORS {
+            if !alert_types.contains(alert_type) {
+                continue;
+            }
+            let color = self
+                .theme
+                .style_of(name)
+                .and_then(|style| style.fg.as_deref())
+                .unwrap_or(&self.theme.foreground);
+            let _ = writeln!(
                 css,
-                "{} .{}{{color:{};",
-                CSS_SCOPE, self.theme.supported_highlight_names[*index], style.color
+                "{prefix}.markdown-alert-{alert_type}{{border-color:{color};}}\n{prefix}.markdown-alert-{alert_type} .markdown-alert-title{{color:{color};}}"
             );
+        }
+        css
+    }
 
-            if style.is_bold {
-                css.push_str("font-weight:bold;");
-            }
+    /// Same as `css()` with all selectors prefixed, like "[data-theme=dark] ", and only the
+    /// rules of the highlight names if given
+    fn css_with_prefix(&self, prefix: &str, highlight_names: Option<&BTreeSet<String>>) -> String {
+        let scope = format!("{prefix}{CSS_SCOPE}");
+        let mut css = format!(
+            "{prefix}pre {{background-color:{};}}\n{} {{color:{};}}\n",
+            self.theme.background, scope, self.theme.foreground
+        );
 
-            if style.is_italic {
-                css.push_str("font-style:italic;");
+        let rules: Vec<(&str, &Style)> = match highlight_names {
+            Some(highlight_names) => highlight_names
+                .iter()
+                .filter_map(|name| Some((name.as_str(), self.theme.style_of(name)?)))
+                .collect(),
+            None => {
+                // Just sort the vec in tests to allow regression tests on the output
+                // This is changing the order of iteration otherwise
+                let mut styles: Vec<(_, _)> = self.theme.style_map.iter().collect();
+                styles.sort();
+                styles
+                    .into_iter()
+                    .map(|(index, style)| (self.theme.supported_highlight_names[*index], style))
+                    .collect()
+            }
+        };
+        for (name, style) in rules {
+            if let Some(selector) = Self::class_selector(name) {
+                let _ = writeln!(css, "{scope} {selector}{{{}}}", Self::declarations(style));
             }
-
-            css.push_str("}\n");
         }
 
+        css.push_str(&self.diff_css(&scope));
+
         // TODO: remove that if that's actually useless
         // css.push_str(".tsc-line { word-wrap: normal; white-space: pre; }\n");
         css
     }
+
+    /// The selector of the classes of a highlight name, "function.method" gives
+    /// ".function.method". The names come from the queries of the grammars, so the ones with
+    /// unexpected characters are ignored, and the parts starting with a digit are escaped.
+    fn class_selector(name: &str) -> Option<String> {
+        let mut selector = String::new();
+        for part in name.split('.') {
+            let is_valid = !part.is_empty()
+                && part
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+            if !is_valid {
+                return None;
+            }
+            selector.push('.');
+            match part.chars().next() {
+                // "markup.heading.1" gives ".markup.heading.\31 "
+                Some(digit) if digit.is_ascii_digit() => {
+                    let _ = write!(selector, "\\3{digit} {}", &part[1..]);
+                }
+                _ => selector.push_str(part),
+            }
+        }
+        Some(selector)
+    }
+
+    /// The CSS declarations of a style, like "color:#d20f39;font-weight:bold;"
+    fn declarations(style: &Style) -> String {
+        let mut css = String::new();
+        if let Some(fg) = &style.fg {
+            let _ = write!(css, "color:{fg};");
+        }
+        if let Some(bg) = &style.bg {
+            let _ = write!(css, "background-color:{bg};");
+        }
+        if style.is_bold {
+            css.push_str("font-weight:bold;");
+        }
+        if style.is_italic {
+            css.push_str("font-style:italic;");
+        }
+        if style.is_dim {
+            css.push_str("opacity:0.6;");
+        }
+
+        let mut lines = Vec::new();
+        if style.underline.is_some() {
+            lines.push("underline");
+        }
+        if style.is_crossed_out {
+            lines.push("line-through");
+        }
+        if !lines.is_empty() {
+            let _ = write!(css, "text-decoration-line:{};", lines.join(" "));
+        }
+        if let Some(underline) = &style.underline {
+            let decoration_style = match underline.style {
+                UnderlineStyle::Line => None,
+                UnderlineStyle::Curl => Some("wavy"),
+                UnderlineStyle::Dashed => Some("dashed"),
+                UnderlineStyle::Dotted => Some("dotted"),
+                UnderlineStyle::DoubleLine => Some("double"),
+            };
+            if let Some(decoration_style) = decoration_style {
+                let _ = write!(css, "text-decoration-style:{decoration_style};");
+            }
+            if let Some(color) = &underline.color {
+                let _ = write!(css, "text-decoration-color:{color};");
+            }
+        }
+        css
+    }
+
+    /// The lines of ```diff blocks, each one filling the width of the block, and their
+    /// backgrounds, a light version of the "diff.plus" and "diff.minus" colours of the theme.
+    /// The backgrounds are only generated if the theme styles both.
+    fn diff_css(&self, scope: &str) -> String {
+        let mut css =
+            format!("{scope} .diff-line{{display:inline-block;min-width:100%;min-height:1lh;}}\n");
+        let color_of = |name| {
+            self.theme
+                .style_of(name)
+                .and_then(|style| style.fg.as_deref())
+        };
+        let (Some(plus), Some(minus)) = (color_of("diff.plus"), color_of("diff.minus")) else {
+            return css;
+        };
+        // The same colour with 15% of opacity
+        let light = |color: &str| match parse_hex_color(color) {
+            Some((r, g, b)) => format!("rgba({r},{g},{b},.15)"),
+            None => format!("color-mix(in srgb,{color} 15%,transparent)"),
+        };
+        let _ = writeln!(
+            css,
+            "{scope} .diff-added{{background-color:{};}}",
+            light(plus)
+        );
+        let _ = writeln!(
+            css,
+            "{scope} .diff-removed{{background-color:{};}}",
+            light(minus)
+        );
+        let _ = writeln!(
+            css,
+            "{scope} .diff-added .diff-marker{{color:{};}}\n{scope} .diff-removed .diff-marker{{color:{};}}",
+            plus, minus
+        );
+        css
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use std::{env::current_dir, fs::read_to_string};
+    use std::{collections::BTreeSet, env::current_dir, fs::read_to_string};
 
-    use crate::theming::theme::Theme;
+    use crate::theming::theme::{Theme, DEFAULT_THEME};
 
-    use super::Renderer;
+    use super::{color_scheme_css, ColorSchemeSwitch, Renderer};
+    use crate::theming::catalog::ThemeCatalog;
 
     #[test]
     fn test_can_render_css_for_catppuccin_latte_theme() {
@@ -81,10 +306,93 @@ mod tests {
             lines.sort();
             lines.join("\n")
         };
-        assert_eq!(sorter(&renderer.css()), sorter("code .function{color:#1e66f5;}\ncode .markup.bold{color:#d20f39;font-weight:bold;}\ncode .variable{color:#4c4f69;}\ncode {color:#4c4f69;}\npre {background-color:#eff1f5;}"));
+        assert_eq!(sorter(&renderer.css()), sorter("code .function{color:#1e66f5;}\ncode .markup.bold{color:#d20f39;font-weight:bold;}\ncode .diff-line{display:inline-block;min-width:100%;min-height:1lh;}\ncode .variable{color:#4c4f69;}\ncode {color:#4c4f69;}\npre {background-color:#eff1f5;}"));
+
+        let theme = Theme::from_helix(&content, &["diff.plus", "diff.minus"]).unwrap();
+        let css = Renderer::new(&theme).css();
+        assert!(css.contains("code .diff-added{background-color:rgba(64,160,43,.15);}"));
+        assert!(css.contains("code .diff-removed{background-color:rgba(210,15,57,.15);}"));
+        let theme = Theme::from_helix(
+            "\"diff.plus\" = \"#0f0\"\n\"diff.minus\" = \"red\"",
+            &["diff.plus", "diff.minus"],
+        )
+        .unwrap();
+        let css = Renderer::new(&theme).css();
+        assert!(css.contains("code .diff-added{background-color:rgba(0,255,0,.15);}"));
+        assert!(
+            css.contains("code .diff-removed{background-color:"),
+            "{css}"
+        );
+
+        let theme = Theme::from_helix(
+            "\"comment\" = { fg = \"gray\", bg = \"#000\", modifiers = [\"italic\", \"dim\", \"crossed_out\"], underline = { style = \"curl\" } }",
+            &["comment"],
+        )
+        .unwrap();
+        assert!(Renderer::new(&theme).css().contains("code .comment{color:#7f7f7f;background-color:#000;font-style:italic;opacity:0.6;text-decoration-line:underline line-through;text-decoration-style:wavy;}"));
 
         // TODO: we could try to minimize later the size of the generated CSS
         // I see that color in variable is from "text" var in TOML file so it's a duplicated from
         // the default value given in the first CSS default rule.
     }
+
+    #[test]
+    fn test_light_and_dark_themes_pair_switches_via_media_query_and_data_theme() {
+        let catalog = ThemeCatalog::new_with_themes_folder("not-a-folder".into());
+        let light = catalog.load("catppuccin_latte", &["function"]).unwrap();
+        let dark = catalog.load("catppuccin_mocha", &["function"]).unwrap();
+
+        let css = color_scheme_css(
+            &light,
+            &dark,
+            ColorSchemeSwitch::Both,
+            None,
+            &BTreeSet::new(),
+        );
+        assert!(css.starts_with(
+            ":root {background-color:#eff1f5;color:#4c4f69;color-scheme:light;}\npre {background-color:#eff1f5;}\n"
+        ));
+        assert!(css.contains("@media (prefers-color-scheme: dark) {\n:root:not([data-theme=light]) {background-color:#1e1e2e;color:#cdd6f4;color-scheme:dark;}\n:root:not([data-theme=light]) pre {background-color:#1e1e2e;}"));
+        assert!(css.contains("\n[data-theme=dark] code .function{color:#89b4fa;}\n"));
+        assert!(css.contains(".typst-frame{fill:currentColor;}"));
+
+        let css = color_scheme_css(
+            &light,
+            &dark,
+            ColorSchemeSwitch::MediaQuery,
+            None,
+            &BTreeSet::from(["note".to_string()]),
+        );
+        assert!(
+            css.contains("@media (prefers-color-scheme: dark) {\n:root {background-color:#1e1e2e;")
+        );
+        assert!(!css.contains("data-theme"));
+        // The alerts get the colour of "function" of each theme
+        assert!(css.contains("\n.markdown-alert-note{border-color:#1e66f5;}\n"));
+        assert!(css.contains("\n.markdown-alert-note .markdown-alert-title{color:#89b4fa;}\n"));
+        assert!(!css.contains("markdown-alert-tip{"));
+    }
+
+    #[test]
+    fn test_only_used_highlight_names_get_css_with_dotted_fallback() {
+        let theme = Theme::from_helix(
+            DEFAULT_THEME,
+            &["function", "function.macro", "keyword", "markup.heading.1"],
+        )
+        .unwrap();
+        let names = [
+            "function.method.call",
+            "keyword.function",
+            "markup.heading.1",
+            "spell",
+            "bad{name",
+        ]
+        .map(String::from)
+        .into();
+        let css = Renderer::new(&theme).css_for_highlight_names(&names);
+        assert_eq!(
+            css,
+            "pre {background-color:#eff1f5;}\ncode {color:#4c4f69;}\ncode .function.method.call{color:#1e66f5;}\ncode .keyword.function{color:#8839ef;}\ncode .markup.heading.\\31 {color:#d20f39;}\ncode .diff-line{display:inline-block;min-width:100%;min-height:1lh;}\n"
+        );
+    }
 }
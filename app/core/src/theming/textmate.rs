@@ -0,0 +1,684 @@
+// Import of VS Code colour themes (JSON with `colors` and `tokenColors`) and TextMate `.tmTheme`
+// files. Both are converted to a Helix theme, so they are loaded like any other theme.
+//
+// TextMate scopes like "entity.name.function" are mapped onto the highlight names of
+// `ALL_HIGHLIGHT_NAMES_SUPPORTED_BY_HELIX`, via a list of equivalent scopes for each name. A scope
+// of the theme styles a name when it is the same or a parent of an equivalent scope, the most
+// specific one wins, like in TextMate. A highlight name that is not mapped gets the style of
+// its parent via the CSS classes ("function.method" is styled by ".function").
+
+use std::collections::BTreeSet;
+
+use serde::Serialize;
+use serde_json::Value as JsonValue;
+use toml::{value::Table, Value};
+
+use crate::theming::{error::Error, theme::Theme};
+
+/// The TextMate scopes equivalent to each highlight name, the first ones are preferred
+const SCOPES_OF_HIGHLIGHT_NAMES: &[(&str, &[&str])] = &[
+    ("attribute", &["entity.other.attribute-name"]),
+    (
+        "type",
+        &["entity.name.type", "support.type", "entity.name.class"],
+    ),
+    (
+        "type.builtin",
+        &[
+            "support.type",
+            "storage.type.primitive",
+            "storage.type.builtin",
+        ],
+    ),
+    ("type.parameter", &["entity.name.type.parameter"]),
+    ("type.enum", &["entity.name.type.enum"]),
+    (
+        "type.enum.variant",
+        &["variable.other.enummember", "constant.other.enum"],
+    ),
+    ("constructor", &["entity.name.function.constructor"]),
+    ("constant", &["constant", "variable.other.constant"]),
+    (
+        "constant.builtin",
+        &["constant.language", "support.constant"],
+    ),
+    ("constant.builtin.boolean", &["constant.language.boolean"]),
+    ("constant.character", &["constant.character"]),
+    ("constant.character.escape", &["constant.character.escape"]),
+    ("constant.numeric", &["constant.numeric"]),
+    ("constant.numeric.integer", &["constant.numeric.integer"]),
+    ("constant.numeric.float", &["constant.numeric.float"]),
+    ("string", &["string"]),
+    ("string.regexp", &["string.regexp"]),
+    ("string.special", &["string.other", "constant.other.symbol"]),
+    ("string.special.path", &["string.other.path"]),
+    (
+        "string.special.url",
+        &["markup.underline.link", "string.other.link"],
+    ),
+    ("string.special.symbol", &["constant.other.symbol"]),
+    ("comment", &["comment"]),
+    ("comment.line", &["comment.line"]),
+    ("comment.block", &["comment.block"]),
+    (
+        "comment.block.documentation",
+        &["comment.block.documentation", "comment.documentation"],
+    ),
+    ("variable", &["variable"]),
+    ("variable.builtin", &["variable.language"]),
+    ("variable.parameter", &["variable.parameter"]),
+    ("variable.other", &["variable.other"]),
+    (
+        "variable.other.member",
+        &[
+            "variable.other.member",
+            "variable.other.property",
+            "variable.other.object.property",
+        ],
+    ),
+    ("label", &["entity.name.label"]),
+    ("punctuation", &["punctuation"]),
+    (
+        "punctuation.delimiter",
+        &["punctuation.separator", "punctuation.terminator"],
+    ),
+    (
+        "punctuation.bracket",
+        &["punctuation.bracket", "punctuation.section", "meta.brace"],
+    ),
+    (
+        "punctuation.special",
+        &["punctuation.definition.template-expression"],
+    ),
+    ("keyword", &["keyword", "storage"]),
+    ("keyword.control", &["keyword.control"]),
+    (
+        "keyword.control.conditional",
+        &["keyword.control.conditional", "keyword.control.flow"],
+    ),
+    (
+        "keyword.control.repeat",
+        &["keyword.control.loop", "keyword.control.flow"],
+    ),
+    (
+        "keyword.control.import",
+        &["keyword.control.import", "keyword.other.import"],
+    ),
+    (
+        "keyword.control.return",
+        &["keyword.control.return", "keyword.control.flow"],
+    ),
+    (
+        "keyword.control.exception",
+        &["keyword.control.exception", "keyword.control.trycatch"],
+    ),
+    ("keyword.operator", &["keyword.operator"]),
+    (
+        "keyword.directive",
+        &["keyword.control.directive", "meta.preprocessor"],
+    ),
+    (
+        "keyword.function",
+        &["storage.type.function", "keyword.function"],
+    ),
+    ("keyword.storage", &["storage"]),
+    ("keyword.storage.type", &["storage.type"]),
+    ("keyword.storage.modifier", &["storage.modifier"]),
+    ("operator", &["keyword.operator"]),
+    ("function", &["entity.name.function", "support.function"]),
+    ("function.builtin", &["support.function"]),
+    (
+        "function.method",
+        &["entity.name.function.member", "entity.name.function.method"],
+    ),
+    (
+        "function.macro",
+        &[
+            "entity.name.function.macro",
+            "entity.name.function.preprocessor",
+        ],
+    ),
+    ("tag", &["entity.name.tag"]),
+    (
+        "namespace",
+        &[
+            "entity.name.namespace",
+            "entity.name.module",
+            "entity.name.type.module",
+        ],
+    ),
+    ("markup.heading", &["markup.heading", "entity.name.section"]),
+    ("markup.heading.marker", &["punctuation.definition.heading"]),
+    (
+        "markup.list",
+        &["markup.list", "punctuation.definition.list"],
+    ),
+    ("markup.list.numbered", &["markup.list.numbered"]),
+    ("markup.list.unnumbered", &["markup.list.unnumbered"]),
+    ("markup.bold", &["markup.bold"]),
+    ("markup.italic", &["markup.italic"]),
+    ("markup.strikethrough", &["markup.strikethrough"]),
+    ("markup.link", &["markup.link", "meta.link"]),
+    ("markup.link.url", &["markup.underline.link"]),
+    ("markup.link.text", &["string.other.link"]),
+    ("markup.quote", &["markup.quote"]),
+    (
+        "markup.raw",
+        &["markup.raw", "markup.inline.raw", "markup.fenced_code"],
+    ),
+    ("markup.raw.inline", &["markup.inline.raw"]),
+    ("diff.plus", &["markup.inserted"]),
+    ("diff.minus", &["markup.deleted"]),
+    ("diff.delta", &["markup.changed"]),
+];
+
+/// What couldn't be converted, to tell the user why some tokens are not coloured as expected
+#[derive(Debug, Default, Serialize, Clone, PartialEq, Eq)]
+pub struct ImportReport {
+    /// The scopes of the theme that don't style any highlight name, sorted
+    pub unmapped_scopes: Vec<String>,
+    /// The parts of the theme that are ignored, like invalid colours
+    pub warnings: Vec<String>,
+}
+
+/// A VS Code or TextMate theme converted to a Helix theme
+#[derive(Debug, Clone)]
+pub struct ImportedTheme {
+    helix: Table,
+    pub report: ImportReport,
+}
+
+/// A "tokenColors" entry of VS Code, or a "settings" entry of TextMate
+#[derive(Debug, Default)]
+struct TokenRule {
+    /// The selectors as written, like "source.js string" or "comment, string"
+    selector: String,
+    fg: Option<String>,
+    bg: Option<String>,
+    /// Like "bold italic", an empty one removes the font style of the parent scopes
+    font_style: Option<String>,
+}
+
+/// The common parts of both formats
+#[derive(Debug, Default)]
+struct ParsedTheme {
+    background: Option<String>,
+    foreground: Option<String>,
+    is_dark: bool,
+    rules: Vec<TokenRule>,
+    warnings: Vec<String>,
+}
+
+impl ImportedTheme {
+    /// Convert a VS Code theme, comments and trailing commas are accepted like in VS Code
+    pub fn from_vscode_json(data: &str) -> Result<Self, Error> {
+        let json: JsonValue = serde_json::from_str(&strip_jsonc(data))?;
+        let mut parsed = ParsedTheme {
+            is_dark: !json
+                .get("type")
+                .and_then(JsonValue::as_str)
+                .is_some_and(|kind| kind.contains("light")),
+            ..Default::default()
+        };
+        let colors = json.get("colors");
+        let editor_color = |key: &str| {
+            colors
+                .and_then(|colors| colors.get(key))
+                .and_then(JsonValue::as_str)
+                .map(str::to_string)
+        };
+        parsed.background = editor_color("editor.background");
+        parsed.foreground = editor_color("editor.foreground");
+        if let Some(include) = json.get("include").and_then(JsonValue::as_str) {
+            parsed
+                .warnings
+                .push(format!("The included theme {include} is ignored"));
+        }
+
+        match json.get("tokenColors") {
+            Some(JsonValue::Array(entries)) => {
+                for entry in entries {
+                    let settings = entry.get("settings");
+                    let setting = |key: &str| {
+                        settings
+                            .and_then(|settings| settings.get(key))
+                            .and_then(JsonValue::as_str)
+                            .map(str::to_string)
+                    };
+                    let selector = match entry.get("scope") {
+                        Some(JsonValue::String(scope)) => scope.clone(),
+                        Some(JsonValue::Array(scopes)) => scopes
+                            .iter()
+                            .filter_map(JsonValue::as_str)
+                            .collect::<Vec<&str>>()
+                            .join(", "),
+                        _ => String::default(),
+                    };
+                    parsed.push_rule(TokenRule {
+                        selector,
+                        fg: setting("foreground"),
+                        bg: setting("background"),
+                        font_style: setting("fontStyle"),
+                    });
+                }
+            }
+            Some(JsonValue::String(path)) => parsed
+                .warnings
+                .push(format!("The token colours in {path} are ignored")),
+            _ => parsed
+                .warnings
+                .push("There are no tokenColors in this theme".to_string()),
+        }
+        if json.get("semanticTokenColors").is_some() {
+            parsed
+                .warnings
+                .push("The semanticTokenColors are ignored".to_string());
+        }
+        Ok(parsed.into_helix())
+    }
+
+    /// Convert a TextMate theme, a plist XML file with a list of settings
+    pub fn from_tmtheme(data: &str) -> Result<Self, Error> {
+        let options = roxmltree::ParsingOptions {
+            allow_dtd: true,
+            ..Default::default()
+        };
+        let document = roxmltree::Document::parse_with_options(data, options)
+            .map_err(|e| Error::InvalidImportedTheme(e.to_string()))?;
+        let plist = document
+            .root_element()
+            .children()
+            .find(|node| node.is_element())
+            .map(parse_plist)
+            .ok_or(Error::InvalidImportedTheme("Empty plist".to_string()))?;
+        let Some(Plist::Array(settings)) = plist.get("settings") else {
+            return Err(Error::InvalidImportedTheme(
+                "No settings array in this theme".to_string(),
+            ));
+        };
+
+        let mut parsed = ParsedTheme::default();
+        for entry in settings {
+            let setting = |key: &str| {
+                entry
+                    .get("settings")
+                    .and_then(|settings| settings.get(key))
+                    .and_then(Plist::as_str)
+                    .map(str::to_string)
+            };
+            match entry.get("scope").and_then(Plist::as_str) {
+                Some(selector) => parsed.push_rule(TokenRule {
+                    selector: selector.to_string(),
+                    fg: setting("foreground"),
+                    bg: setting("background"),
+                    font_style: setting("fontStyle"),
+                }),
+                // The global settings, with the editor colours
+                None => {
+                    parsed.background = parsed.background.or(setting("background"));
+                    parsed.foreground = parsed.foreground.or(setting("foreground"));
+                }
+            }
+        }
+        parsed.is_dark = parsed
+            .background
+            .as_deref()
+            .and_then(normalize_color)
+            .and_then(|color| crate::theming::theme::relative_luminance(&color))
+            .is_none_or(|luminance| luminance < 0.18);
+        Ok(parsed.into_helix())
+    }
+
+    /// The Helix TOML theme, to save it in the themes folder
+    pub fn to_helix_toml(&self) -> String {
+        toml::to_string(&self.helix).unwrap_or_default()
+    }
+
+    pub fn to_theme<'a>(
+        &self,
+        supported_highlight_names: &'a [&'a str],
+    ) -> Result<Theme<'a>, Error> {
+        Theme::from_helix(&self.to_helix_toml(), supported_highlight_names)
+    }
+}
+
+impl ParsedTheme {
+    fn push_rule(&mut self, rule: TokenRule) {
+        // The global settings of VS Code themes have no scope
+        if rule.selector.trim().is_empty() {
+            self.background = self.background.take().or(rule.bg);
+            self.foreground = self.foreground.take().or(rule.fg);
+        } else {
+            self.rules.push(rule);
+        }
+    }
+
+    fn into_helix(mut self) -> ImportedTheme {
+        let color = |color: &Option<String>, warnings: &mut Vec<String>| {
+            let color = color.as_deref()?;
+            let normalized = normalize_color(color);
+            if normalized.is_none() {
+                warnings.push(format!("The colour {color} is not supported"));
+            }
+            normalized
+        };
+        let mut warnings = std::mem::take(&mut self.warnings);
+
+        // The defaults of VS Code
+        let (default_background, default_foreground) = match self.is_dark {
+            true => ("#1e1e1e", "#d4d4d4"),
+            false => ("#ffffff", "#000000"),
+        };
+        let background =
+            color(&self.background, &mut warnings).unwrap_or(default_background.to_string());
+        let foreground =
+            color(&self.foreground, &mut warnings).unwrap_or(default_foreground.to_string());
+
+        let mut helix = Table::new();
+        let mut ui_background = Table::new();
+        ui_background.insert("bg".to_string(), Value::String(background));
+        helix.insert("ui.background".to_string(), Value::Table(ui_background));
+        helix.insert("ui.text".to_string(), Value::String(foreground));
+
+        // The innermost scope of each selector: "source.js string.quoted" gives "string.quoted",
+        // the exclusions like "string - string.regexp" are ignored
+        let scopes: Vec<Vec<&str>> = self
+            .rules
+            .iter()
+            .map(|rule| {
+                rule.selector
+                    .split(',')
+                    .filter_map(|selector| {
+                        selector.split(" -").next()?.split_whitespace().next_back()
+                    })
+                    .collect()
+            })
+            .collect();
+        let mut used_scopes = BTreeSet::new();
+
+        for (name, equivalents) in SCOPES_OF_HIGHLIGHT_NAMES {
+            // The most specific scope matching an equivalent, the first equivalent and the
+            // last rule win in case of equality
+            let best_rule = |has_property: &dyn Fn(&TokenRule) -> bool| {
+                let mut best: Option<(usize, usize, usize, &str)> = None;
+                for (position, equivalent) in equivalents.iter().enumerate() {
+                    for (index, rule) in self.rules.iter().enumerate() {
+                        if !has_property(rule) {
+                            continue;
+                        }
+                        for scope in &scopes[index] {
+                            let matches = equivalent == scope
+                                || equivalent
+                                    .strip_prefix(scope)
+                                    .is_some_and(|rest| rest.starts_with('.'));
+                            let specificity = scope.split('.').count();
+                            let is_better = best.is_none_or(|(s, p, _, _)| {
+                                specificity > s || (specificity == s && position <= p)
+                            });
+                            if matches && is_better {
+                                best = Some((specificity, position, index, scope));
+                            }
+                        }
+                    }
+                }
+                best.map(|(_, _, index, scope)| (index, scope))
+            };
+
+            let mut style = Table::new();
+            let fg = best_rule(&|rule| rule.fg.is_some());
+            let bg = best_rule(&|rule| rule.bg.is_some());
+            let font_style = best_rule(&|rule| rule.font_style.is_some());
+            for (key, found) in [("fg", fg), ("bg", bg)] {
+                let Some((index, scope)) = found else {
+                    continue;
+                };
+                used_scopes.insert(scope);
+                let rule = &self.rules[index];
+                let rule_color = if key == "fg" { &rule.fg } else { &rule.bg };
+                if let Some(rule_color) = color(rule_color, &mut warnings) {
+                    style.insert(key.to_string(), Value::String(rule_color));
+                }
+            }
+            if let Some((index, scope)) = font_style {
+                used_scopes.insert(scope);
+                let modifiers: Vec<Value> = self.rules[index]
+                    .font_style
+                    .as_deref()
+                    .unwrap_or_default()
+                    .split_whitespace()
+                    .filter_map(|font_style| match font_style {
+                        "bold" => Some("bold"),
+                        "italic" => Some("italic"),
+                        "underline" => Some("underlined"),
+                        "strikethrough" => Some("crossed_out"),
+                        _ => None,
+                    })
+                    .map(|modifier| Value::String(modifier.to_string()))
+                    .collect();
+                if !modifiers.is_empty() {
+                    style.insert("modifiers".to_string(), Value::Array(modifiers));
+                }
+            }
+            if !style.is_empty() {
+                helix.insert(name.to_string(), Value::Table(style));
+            }
+        }
+
+        let unmapped_scopes: BTreeSet<String> = scopes
+            .iter()
+            .flatten()
+            .filter(|scope| !used_scopes.contains(*scope))
+            .map(|scope| scope.to_string())
+            .collect();
+        warnings.dedup();
+        ImportedTheme {
+            helix,
+            report: ImportReport {
+                unmapped_scopes: unmapped_scopes.into_iter().collect(),
+                warnings,
+            },
+        }
+    }
+}
+
+/// Get a "#rgb" or "#rrggbb" colour, the alpha channel of "#rgba" and "#rrggbbaa" is removed,
+/// and fully transparent colours are ignored
+fn normalize_color(color: &str) -> Option<String> {
+    let hex = color.strip_prefix('#')?;
+    if !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    let (rgb, alpha) = match hex.len() {
+        3 | 6 => (hex, None),
+        4 => (&hex[..3], Some(&hex[3..])),
+        8 => (&hex[..6], Some(&hex[6..])),
+        _ => return None,
+    };
+    if alpha.is_some_and(|alpha| alpha.chars().all(|c| c == '0')) {
+        return None;
+    }
+    Some(format!("#{}", rgb.to_lowercase()))
+}
+
+/// Remove the comments and trailing commas of JSON with comments, used by VS Code themes
+fn strip_jsonc(data: &str) -> String {
+    let mut json = String::with_capacity(data.len());
+    let mut chars = data.chars().peekable();
+    let mut in_string = false;
+    while let Some(c) = chars.next() {
+        if in_string {
+            json.push(c);
+            match c {
+                '\\' => json.extend(chars.next()),
+                '"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+        match (c, chars.peek()) {
+            ('"', _) => {
+                in_string = true;
+                json.push(c);
+            }
+            ('/', Some('/')) => {
+                for c in chars.by_ref() {
+                    if c == '\n' {
+                        json.push(c);
+                        break;
+                    }
+                }
+            }
+            ('/', Some('*')) => {
+                chars.next();
+                let mut previous = ' ';
+                for c in chars.by_ref() {
+                    if previous == '*' && c == '/' {
+                        break;
+                    }
+                    previous = c;
+                }
+            }
+            ('}' | ']', _) => {
+                // A comma followed by spaces only is a trailing one
+                let trimmed = json.trim_end().len();
+                if json[..trimmed].ends_with(',') {
+                    json.truncate(trimmed - 1);
+                }
+                json.push(c);
+            }
+            _ => json.push(c),
+        }
+    }
+    json
+}
+
+/// The parts of a plist that are used in TextMate themes
+#[derive(Debug)]
+enum Plist {
+    String(String),
+    Array(Vec<Plist>),
+    Dict(Vec<(String, Plist)>),
+    /// Numbers, booleans, dates and data
+    Other,
+}
+
+impl Plist {
+    fn get(&self, key: &str) -> Option<&Plist> {
+        match self {
+            Plist::Dict(entries) => entries
+                .iter()
+                .find(|(entry, _)| entry == key)
+                .map(|(_, value)| value),
+            _ => None,
+        }
+    }
+
+    fn as_str(&self) -> Option<&str> {
+        match self {
+            Plist::String(text) => Some(text),
+            _ => None,
+        }
+    }
+}
+
+fn parse_plist(node: roxmltree::Node) -> Plist {
+    let elements = || node.children().filter(|child| child.is_element());
+    match node.tag_name().name() {
+        "string" => Plist::String(node.text().unwrap_or_default().trim().to_string()),
+        "array" => Plist::Array(elements().map(parse_plist).collect()),
+        "dict" => {
+            let mut entries = Vec::new();
+            let mut key = None;
+            for child in elements() {
+                match child.tag_name().name() {
+                    "key" => key = Some(child.text().unwrap_or_default().to_string()),
+                    _ => {
+                        if let Some(key) = key.take() {
+                            entries.push((key, parse_plist(child)));
+                        }
+                    }
+                }
+            }
+            Plist::Dict(entries)
+        }
+        _ => Plist::Other,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::ImportedTheme;
+    use crate::theming::theme::ThemeAppearance;
+
+    #[test]
+    fn test_vscode_theme_scopes_are_mapped_to_highlight_names() {
+        let json = r##"{
+            // Comments and trailing commas are accepted
+            "type": "dark",
+            "colors": { "editor.background": "#282a36", "editor.foreground": "#f8f8f2", },
+            "tokenColors": [
+                { "scope": ["comment", "punctuation.definition.comment"], "settings": { "foreground": "#6272a4", "fontStyle": "italic" } },
+                { "scope": "keyword, storage.type", "settings": { "foreground": "#ff79c6" } },
+                { "scope": "keyword.control.flow", "settings": { "fontStyle": "bold" } },
+                { "scope": "source.js entity.name.function", "settings": { "foreground": "#50fa7b80" } },
+                { "scope": "meta.embedded", "settings": { "foreground": "#00000000" } },
+                /* not in the theme */
+            ],
+        }"##;
+        let imported = ImportedTheme::from_vscode_json(json).unwrap();
+        assert_eq!(
+            imported.report.unmapped_scopes,
+            vec!["meta.embedded", "punctuation.definition.comment"]
+        );
+        assert_eq!(imported.report.warnings, Vec::<String>::new());
+
+        let names = ["comment", "keyword.control.return", "function", "type"];
+        let theme = imported.to_theme(&names).unwrap();
+        assert_eq!(theme.background, "#282a36");
+        assert_eq!(theme.appearance(), ThemeAppearance::Dark);
+        let comment = theme.style_of("comment").unwrap();
+        assert_eq!(comment.fg.as_deref(), Some("#6272a4"));
+        assert!(comment.is_italic);
+        // The colour of "keyword" and the font style of "keyword.control.flow" are merged
+        let control = theme.style_of("keyword.control.return").unwrap();
+        assert_eq!(control.fg.as_deref(), Some("#ff79c6"));
+        assert!(control.is_bold);
+        assert_eq!(
+            theme.style_of("function").unwrap().fg.as_deref(),
+            Some("#50fa7b")
+        );
+        assert_eq!(theme.style_of("type"), None);
+    }
+
+    #[test]
+    fn test_tmtheme_plist_is_converted() {
+        let plist = r##"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+    <key>name</key><string>Light</string>
+    <key>settings</key>
+    <array>
+        <dict><key>settings</key><dict><key>background</key><string>#FAFAFA</string><key>foreground</key><string>#383A42</string></dict></dict>
+        <dict><key>scope</key><string>string, markup.inserted</string><key>settings</key><dict><key>foreground</key><string>#50A14F</string></dict></dict>
+        <dict><key>scope</key><string>invalid.illegal</string><key>settings</key><dict><key>foreground</key><string>red</string></dict></dict>
+    </array>
+</dict>
+</plist>"##;
+        let imported = ImportedTheme::from_tmtheme(plist).unwrap();
+        assert_eq!(imported.report.unmapped_scopes, vec!["invalid.illegal"]);
+        let theme = imported.to_theme(&["string", "diff.plus"]).unwrap();
+        assert_eq!(theme.appearance(), ThemeAppearance::Light);
+        assert_eq!(theme.foreground, "#383a42");
+        assert_eq!(
+            theme.style_of("diff.plus").unwrap().fg.as_deref(),
+            Some("#50a14f")
+        );
+        assert!(imported
+            .to_helix_toml()
+            .contains("[\"ui.background\"]\nbg = \"#fafafa\""));
+        assert!(ImportedTheme::from_tmtheme("<plist><dict></dict></plist>").is_err());
+    }
+}
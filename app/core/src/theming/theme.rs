@@ -2,34 +2,79 @@
 // and it has been is adapted to our situation
 // https://github.com/matze/tree-painter
 use crate::theming::error::Error;
+use serde::Serialize;
 use std::collections::HashMap;
-use std::convert::From;
 use toml::value::Table;
 use toml::Value;
 
-#[derive(PartialEq, PartialOrd, Ord, Eq)]
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ThemeAppearance {
+    Light,
+    Dark,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, PartialOrd, Ord, Eq)]
 pub(crate) struct Style {
-    pub color: String,
+    /// The text colour as "#rrggbb" or "#rgb", None to keep the colour of the parent
+    pub fg: Option<String>,
+    pub bg: Option<String>,
+    pub underline: Option<Underline>,
     pub is_bold: bool,
     pub is_italic: bool,
+    pub is_dim: bool,
+    pub is_crossed_out: bool,
 }
 
-impl From<&String> for Style {
-    fn from(color: &String) -> Self {
-        Style {
-            color: color.clone(),
-            is_bold: false,
-            is_italic: false,
-        }
-    }
+#[derive(Debug, Clone, PartialEq, PartialOrd, Ord, Eq)]
+pub(crate) struct Underline {
+    pub color: Option<String>,
+    pub style: UnderlineStyle,
+}
+
+/// The `underline.style` values of Helix
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd, Ord, Eq)]
+pub(crate) enum UnderlineStyle {
+    Line,
+    Curl,
+    Dashed,
+    Dotted,
+    DoubleLine,
 }
 
+/// The named ANSI colours accepted by Helix, with the colours of the xterm palette
+const ANSI_COLORS: &[(&str, &str)] = &[
+    ("black", "#000000"),
+    ("red", "#cd0000"),
+    ("green", "#00cd00"),
+    ("yellow", "#cdcd00"),
+    ("blue", "#0000ee"),
+    ("magenta", "#cd00cd"),
+    ("cyan", "#00cdcd"),
+    ("gray", "#7f7f7f"),
+    ("light-red", "#ff0000"),
+    ("light-green", "#00ff00"),
+    ("light-yellow", "#ffff00"),
+    ("light-blue", "#5c5cff"),
+    ("light-magenta", "#ff00ff"),
+    ("light-cyan", "#00ffff"),
+    ("light-gray", "#e5e5e5"),
+    ("white", "#ffffff"),
+];
+
+/// Themes inheriting from themes inheriting from... are stopped at this depth
+const MAX_INHERITANCE_DEPTH: usize = 16;
+
 /// A theme defining colors and modifiers to be used for syntax highlighting.
 pub struct Theme<'a> {
     pub(crate) style_map: HashMap<usize, Style>,
-    pub(crate) foreground: Style,
-    pub(crate) background: Style,
+    /// The default text colour
+    pub(crate) foreground: String,
+    /// The background colour of code blocks
+    pub(crate) background: String,
     pub(crate) supported_highlight_names: &'a [&'a str],
+    /// The colours that couldn't be resolved and were ignored
+    pub(crate) warnings: Vec<String>,
 }
 
 pub const DEFAULT_THEME: &str = include_str!("default/catppuccin_latte.toml");
@@ -45,97 +90,284 @@ impl<'a> Theme<'a> {
     ///
     /// Make sure to include all possible highlighting names inside this theme
     /// for all the languages you are highlighting ! We are giving an external list to limit the
-    /// amount of keys to generate CSS for
-    /// Note: we might change that in the future to generate the whole CSS definitions for any
-    /// highlighting name used in the theme itself
+    /// amount of keys to load. The names used by the grammars but missing in this list get the
+    /// style of their closest parent, see `style_of()`.
+    ///
+    /// A theme with `inherits = "..."` fails with `Error::UnknownParentTheme`, see
+    /// `from_helix_with_parents()` or `ThemeCatalog::load()` to load its parents.
     pub fn from_helix(data: &str, supported_highlight_names: &'a [&'a str]) -> Result<Self, Error> {
-        let root = match data.parse::<toml::Value>()? {
-            Value::Table(table) => table,
-            _ => return Err(Error::InvalidTheme),
-        };
+        Self::from_helix_with_parents(data, supported_highlight_names, |_, _| None)
+    }
 
-        let palette = root.get("palette").ok_or(Error::InvalidTheme)?;
+    /// Same as `from_helix()`, with a function giving the content of a theme to support
+    /// `inherits = "name"`. It gets the name of the parent and the name of the inheriting
+    /// theme, None for `data`, as a theme can inherit from another theme with its own name.
+    /// The inheriting theme overrides the styles and the palette colours of its parent, like
+    /// in Helix.
+    pub fn from_helix_with_parents(
+        data: &str,
+        supported_highlight_names: &'a [&'a str],
+        parent_of: impl Fn(&str, Option<&str>) -> Option<String>,
+    ) -> Result<Self, Error> {
+        let root = Self::inherited_table(data, &parent_of, &mut Vec::new())?;
 
-        // Helper to find the text color of a given name
-        let fg_color = |name: &str| -> Result<Option<Style>, Error> {
-            if let Some(value) = root.get(name) {
-                match value {
-                    Value::String(reference) => {
-                        if let Some(Value::String(color)) = palette.get(reference) {
-                            return Ok(Some(Style::from(color)));
-                        }
-                    }
-                    Value::Table(table) => {
-                        let mut style = Self::referenced_color(table, palette, "fg")?;
-
-                        if let Some(Value::Array(modifiers)) = table.get("modifiers") {
-                            for modifier in modifiers {
-                                if let Value::String(modifier) = modifier {
-                                    if modifier == "italic" {
-                                        style.is_italic = true;
-                                    } else if modifier == "bold" {
-                                        style.is_bold = true;
-                                    }
-                                }
-                            }
-                        }
-
-                        return Ok(Some(style));
-                    }
-                    _ => {}
+        // The palette is optional as colours can be given directly
+        let palette = match root.get("palette") {
+            Some(Value::Table(palette)) => palette.clone(),
+            Some(_) => return Err(Error::InvalidTheme),
+            None => Table::default(),
+        };
+
+        // Helper to find the style of a given name
+        let mut warnings = Vec::new();
+        let mut style_of = |name: &str| -> Option<Style> {
+            match root.get(name) {
+                Some(Value::String(color)) => Some(Style {
+                    fg: Self::resolve_color(&palette, color, &mut warnings),
+                    ..Default::default()
+                }),
+                Some(Value::Table(table)) => {
+                    Some(Self::parse_style(&palette, table, &mut warnings))
                 }
+                _ => None,
             }
-
-            Ok(None)
         };
 
         let mut style_map = HashMap::default();
 
         // Only include style if they are part of the supported_highlight_names vec
         for (index, name) in supported_highlight_names.iter().enumerate() {
-            if let Some(style) = fg_color(name)? {
+            if let Some(style) = style_of(name) {
                 style_map.insert(index, style);
             }
         }
 
-        // Get the color behing "ui.background" highlighting name or use white
-        let background = match root.get("ui.background") {
-            Some(Value::Table(table)) => Self::referenced_color(table, palette, "bg")?,
-            _ => Style::from(&"#000".to_string()),
-        };
+        // Get the color behind "ui.background" highlighting name or use black
+        let ui_background = style_of("ui.background").unwrap_or_default();
+        let background = ui_background
+            .bg
+            .clone()
+            .unwrap_or_else(|| "#000".to_string());
 
-        // Get the color behing "ui.text" highlighting name or use black
-        let foreground = fg_color("ui.text")?.unwrap_or_else(|| Style::from(&"#fff".to_string()));
+        // Get the color behind "ui.text" highlighting name, or the text colour on the
+        // background, or use white
+        let foreground = style_of("ui.text")
+            .and_then(|style| style.fg)
+            .or(ui_background.fg)
+            .unwrap_or_else(|| "#fff".to_string());
 
         Ok(Self {
             style_map,
             foreground,
             background,
             supported_highlight_names,
+            warnings,
         })
     }
 
-    /// Simple helper to get the color behind a reference
+    /// Get the colours that couldn't be resolved, they are ignored like in Helix
+    pub fn get_warnings(&self) -> &[String] {
+        &self.warnings
+    }
+
+    /// Light or dark, depending on the luminance of the background
+    pub fn appearance(&self) -> ThemeAppearance {
+        match relative_luminance(&self.background) {
+            Some(luminance) if luminance < 0.18 => ThemeAppearance::Dark,
+            _ => ThemeAppearance::Light,
+        }
+    }
+
+    /// Check if a highlight name like "function.method" is styled by this theme, directly or via
+    /// a parent like "function", as the dots become separated CSS classes
+    pub fn is_highlight_name_styled(&self, name: &str) -> bool {
+        self.style_of(name).is_some()
+    }
+
+    /// Get the style applied on a highlight name like "function.method", the most specific one
+    /// between its own style and the style of its parents like "function"
+    pub(crate) fn style_of(&self, name: &str) -> Option<&Style> {
+        self.style_map
+            .iter()
+            .map(|(index, style)| (self.supported_highlight_names[*index], style))
+            .filter(|(styled, _)| {
+                name == *styled
+                    || name
+                        .strip_prefix(styled)
+                        .is_some_and(|rest| rest.starts_with('.'))
+            })
+            .max_by_key(|(styled, _)| styled.len())
+            .map(|(_, style)| style)
+    }
+
+    /// Parse the theme and merge it into its parents, the palettes are merged too
+    fn inherited_table(
+        data: &str,
+        parent_of: &impl Fn(&str, Option<&str>) -> Option<String>,
+        visited: &mut Vec<String>,
+    ) -> Result<Table, Error> {
+        let mut table = match data.parse::<toml::Value>()? {
+            Value::Table(table) => table,
+            _ => return Err(Error::InvalidTheme),
+        };
+        let Some(inherits) = table.remove("inherits") else {
+            return Ok(table);
+        };
+        let Value::String(parent_name) = inherits else {
+            return Err(Error::InvalidTheme);
+        };
+        let child_name = visited.last().map(String::as_str);
+        // Inheriting from its own name gives another theme, like a user theme overriding a
+        // bundled one, the depth still stops a real loop
+        let is_cycle = child_name != Some(&parent_name) && visited.contains(&parent_name);
+        if is_cycle || visited.len() >= MAX_INHERITANCE_DEPTH {
+            return Err(Error::InheritanceCycle(parent_name));
+        }
+        let parent_data = parent_of(&parent_name, child_name)
+            .ok_or(Error::UnknownParentTheme(parent_name.clone()))?;
+        visited.push(parent_name);
+        let mut merged = Self::inherited_table(&parent_data, parent_of, visited)?;
+
+        for (key, value) in table {
+            match (merged.get_mut(&key), value) {
+                (Some(Value::Table(parent_palette)), Value::Table(palette)) if key == "palette" => {
+                    parent_palette.extend(palette)
+                }
+                (_, value) => {
+                    merged.insert(key, value);
+                }
+            }
+        }
+        Ok(merged)
+    }
+
+    /// Parse a style like `{ fg = "red", bg = "#000000", modifiers = ["bold"],
+    /// underline = { color = "blue", style = "curl" } }`
+    fn parse_style(palette: &Table, table: &Table, warnings: &mut Vec<String>) -> Style {
+        let mut color = |key: &str| -> Option<String> {
+            match table.get(key) {
+                Some(Value::String(color)) => Self::resolve_color(palette, color, warnings),
+                _ => None,
+            }
+        };
+        let mut style = Style {
+            fg: color("fg"),
+            bg: color("bg"),
+            ..Default::default()
+        };
+
+        if let Some(Value::Array(modifiers)) = table.get("modifiers") {
+            for modifier in modifiers.iter().filter_map(Value::as_str) {
+                match modifier {
+                    "bold" => style.is_bold = true,
+                    "italic" => style.is_italic = true,
+                    "dim" => style.is_dim = true,
+                    "crossed_out" => style.is_crossed_out = true,
+                    // The old way to underline, before the underline table
+                    "underlined" => {
+                        style.underline = Some(Underline {
+                            color: None,
+                            style: UnderlineStyle::Line,
+                        })
+                    }
+                    // Blinking, hidden and reversed text makes no sense in a preview
+                    _ => {}
+                }
+            }
+        }
+
+        if let Some(Value::Table(underline)) = table.get("underline") {
+            let underline_style = match underline.get("style").and_then(Value::as_str) {
+                Some("curl") => UnderlineStyle::Curl,
+                Some("dashed") => UnderlineStyle::Dashed,
+                Some("dotted") => UnderlineStyle::Dotted,
+                Some("double_line") => UnderlineStyle::DoubleLine,
+                _ => UnderlineStyle::Line,
+            };
+            let underline_color = match underline.get("color") {
+                Some(Value::String(color)) => Self::resolve_color(palette, color, warnings),
+                _ => None,
+            };
+            style.underline = Some(Underline {
+                color: underline_color,
+                style: underline_style,
+            });
+        }
+
+        style
+    }
+
+    /// Get the "#rrggbb" colour behind a palette reference, a named ANSI colour or a direct
+    /// colour. The palette has priority like in Helix.
     /// Exemple when reading this line in TOML file
     // "constant" = "peach"
     // we want to get the color behind "peach" in the palette of color
     // -> peach = "#ef9f76"
-    fn referenced_color(table: &Table, palette: &Value, name: &str) -> Result<Style, Error> {
-        if let Some(Value::String(reference)) = table.get(name) {
-            if let Some(Value::String(color)) = palette.get(reference) {
-                return Ok(Style::from(color));
-            }
+    // An unknown colour is ignored with a warning, like in Helix
+    fn resolve_color(palette: &Table, color: &str, warnings: &mut Vec<String>) -> Option<String> {
+        let color = match palette.get(color) {
+            Some(Value::String(referenced)) => referenced.as_str(),
+            _ => color,
+        };
+        if color == "default" {
+            return None;
+        }
+        if let Some((_, hex)) = ANSI_COLORS.iter().find(|(name, _)| *name == color) {
+            return Some(hex.to_string());
+        }
+        let is_hex = color.strip_prefix('#').is_some_and(|hex| {
+            matches!(hex.len(), 3 | 6) && hex.chars().all(|c| c.is_ascii_hexdigit())
+        });
+        if is_hex {
+            return Some(color.to_string());
+        }
+
+        let warning = format!("The colour {color} is not in the palette, it is ignored");
+        if !warnings.contains(&warning) {
+            warnings.push(warning);
         }
+        None
+    }
+}
 
-        Err(Error::InvalidColorReference(name.to_string()))
+/// Parse "#rrggbb" and "#rgb" colours
+pub(crate) fn parse_hex_color(color: &str) -> Option<(u8, u8, u8)> {
+    let hex = color.strip_prefix('#').filter(|hex| hex.is_ascii())?;
+    let channel = |s: &str| u8::from_str_radix(s, 16).ok();
+    match hex.len() {
+        6 => Some((
+            channel(&hex[0..2])?,
+            channel(&hex[2..4])?,
+            channel(&hex[4..6])?,
+        )),
+        3 => {
+            let short = |i: usize| channel(&hex[i..i + 1]).map(|c| c * 17);
+            Some((short(0)?, short(1)?, short(2)?))
+        }
+        _ => None,
     }
 }
 
+/// The relative luminance of a colour as defined by WCAG, from 0 for black to 1 for white
+pub(crate) fn relative_luminance(color: &str) -> Option<f64> {
+    let (r, g, b) = parse_hex_color(color)?;
+    let linear = |c: u8| {
+        let c = c as f64 / 255.0;
+        if c <= 0.03928 {
+            c / 12.92
+        } else {
+            ((c + 0.055) / 1.055).powf(2.4)
+        }
+    };
+    Some(0.2126 * linear(r) + 0.7152 * linear(g) + 0.0722 * linear(b))
+}
+
 #[cfg(test)]
 mod tests {
     use std::{env::current_dir, fs::read_to_string};
 
-    use super::{Theme, DEFAULT_THEME};
+    use super::{Theme, UnderlineStyle, DEFAULT_THEME};
+    use crate::theming::error::Error;
 
     #[test]
     fn test_can_load_catppuccin_latte_toml_theme() {
@@ -148,11 +380,78 @@ mod tests {
         let theme = Theme::from_helix(&content, &["variable", "function"]).unwrap();
 
         // See line with: text = "#4c4f69"
-        assert_eq!(theme.foreground.color, "#4c4f69");
+        assert_eq!(theme.foreground, "#4c4f69");
     }
 
     #[test]
     fn test_default_theme_can_be_loaded() {
         Theme::from_helix(DEFAULT_THEME, &[]).unwrap();
     }
+
+    #[test]
+    fn test_highlight_names_styled_via_their_parent() {
+        let theme = Theme::from_helix(DEFAULT_THEME, &["function", "not.in.theme"]).unwrap();
+        assert!(theme.is_highlight_name_styled("function"));
+        assert!(theme.is_highlight_name_styled("function.method"));
+        assert!(!theme.is_highlight_name_styled("functional"));
+        assert!(!theme.is_highlight_name_styled("not.in.theme"));
+    }
+
+    #[test]
+    fn test_inherited_theme_with_direct_and_ansi_colours() {
+        let child = r##"
+inherits = "catppuccin_latte"
+"comment" = { fg = "#abcdef", bg = "light-red", modifiers = ["dim", "crossed_out"] }
+"markup.bold" = { modifiers = ["bold"], underline = { color = "red", style = "curl" } }
+[palette]
+blue = "#000001"
+"##;
+        let names = ["comment", "markup.bold", "function", "string"];
+        let bundled = |_: &str, _: Option<&str>| Some(DEFAULT_THEME.to_string());
+        let theme = Theme::from_helix_with_parents(child, &names, bundled).unwrap();
+        let comment = theme.style_of("comment").unwrap();
+        assert_eq!(comment.fg.as_deref(), Some("#abcdef"));
+        assert_eq!(comment.bg.as_deref(), Some("#ff0000"));
+        assert!(comment.is_dim && comment.is_crossed_out);
+        let bold = theme.style_of("markup.bold").unwrap();
+        assert_eq!(bold.fg, None);
+        // "red" comes from the palette of the parent, not from the ANSI colours
+        let underline = bold.underline.clone().unwrap();
+        assert_eq!(underline.color.as_deref(), Some("#d20f39"));
+        assert_eq!(underline.style, UnderlineStyle::Curl);
+        // The palette of the child overrides the one of the parent
+        assert_eq!(
+            theme.style_of("function").unwrap().fg.as_deref(),
+            Some("#000001")
+        );
+        assert_eq!(
+            theme.style_of("string").unwrap().fg.as_deref(),
+            Some("#40a02b")
+        );
+        assert_eq!(theme.background, "#eff1f5");
+        assert!(theme.get_warnings().is_empty());
+
+        // An unknown colour is ignored with a warning instead of failing the whole theme
+        let unknown = "\"comment\" = \"peach\"\n\"string\" = { fg = \"#40a02b\", bg = \"peach\" }";
+        let theme = Theme::from_helix(unknown, &names).unwrap();
+        assert_eq!(theme.style_of("comment").unwrap().fg, None);
+        assert_eq!(
+            theme.style_of("string").unwrap().fg.as_deref(),
+            Some("#40a02b")
+        );
+        assert_eq!(
+            theme.get_warnings(),
+            ["The colour peach is not in the palette, it is ignored"]
+        );
+
+        assert!(matches!(
+            Theme::from_helix("inherits = \"unknown\"", &names),
+            Err(Error::UnknownParentTheme(_))
+        ));
+        let looping = |_: &str, _: Option<&str>| Some("inherits = \"loop\"".to_string());
+        assert!(matches!(
+            Theme::from_helix_with_parents("inherits = \"loop\"", &names, looping),
+            Err(Error::InheritanceCycle(_))
+        ));
+    }
 }
@@ -1,3 +1,4 @@
+pub mod archive;
 pub mod git;
 mod security;
 pub mod setup;
@@ -0,0 +1,110 @@
+// Minimal helpers to create and extract the archive formats commonly used to distribute source code
+
+use std::{fs::File, path::Path};
+
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+
+/// The archive formats we know how to extract
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveKind {
+    /// A gzipped tarball, with a .tar.gz or .tgz extension
+    TarGz,
+    /// A zip archive, with a .zip extension
+    Zip,
+}
+
+impl ArchiveKind {
+    /// Detect the archive kind via the file name extension
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let name = path.file_name()?.to_str()?.to_lowercase();
+        if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+            Some(ArchiveKind::TarGz)
+        } else if name.ends_with(".zip") {
+            Some(ArchiveKind::Zip)
+        } else {
+            None
+        }
+    }
+}
+
+/// Extract the given archive inside the destination folder, which is created if needed.
+/// Entries trying to escape the destination (absolute paths or `..`) are refused by both
+/// the tar and zip implementations.
+pub fn extract_archive(archive: &Path, destination: &Path) -> Result<(), String> {
+    let kind = ArchiveKind::from_path(archive).ok_or_else(|| {
+        format!(
+            "Unsupported archive format for {archive:?}, only .tar.gz, .tgz and .zip are supported"
+        )
+    })?;
+    let file =
+        File::open(archive).map_err(|e| format!("Couldn't open archive {archive:?}: {e}"))?;
+    std::fs::create_dir_all(destination)
+        .map_err(|e| format!("Couldn't create folder {destination:?}: {e}"))?;
+
+    match kind {
+        ArchiveKind::TarGz => tar::Archive::new(GzDecoder::new(file))
+            .unpack(destination)
+            .map_err(|e| format!("Couldn't extract archive {archive:?}: {e}")),
+        ArchiveKind::Zip => zip::ZipArchive::new(file)
+            .and_then(|mut zip| zip.extract(destination))
+            .map_err(|e| format!("Couldn't extract archive {archive:?}: {e}")),
+    }
+}
+
+/// Create a .tar.gz archive with the content of the given folder at the root of the archive
+pub fn create_tar_gz(folder: &Path, archive: &Path) -> Result<(), String> {
+    let file =
+        File::create(archive).map_err(|e| format!("Couldn't create archive {archive:?}: {e}"))?;
+    let mut builder = tar::Builder::new(GzEncoder::new(file, Compression::default()));
+    builder
+        .append_dir_all(".", folder)
+        .and_then(|_| builder.into_inner())
+        .and_then(|encoder| encoder.finish())
+        .map(|_| ())
+        .map_err(|e| format!("Couldn't write archive {archive:?}: {e}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs::read_to_string, path::PathBuf};
+
+    use pretty_assertions::assert_eq;
+
+    use super::{create_tar_gz, extract_archive, ArchiveKind};
+    use crate::preview::tree_sitter_grammars::get_unique_local_tree_sitter_grammars_folder;
+
+    #[test]
+    fn test_archive_kind_is_detected_from_extension() {
+        assert_eq!(
+            ArchiveKind::from_path(&PathBuf::from("tree-sitter-css-0.23.0.tar.gz")),
+            Some(ArchiveKind::TarGz)
+        );
+        assert_eq!(
+            ArchiveKind::from_path(&PathBuf::from("/tmp/grammar.TGZ")),
+            Some(ArchiveKind::TarGz)
+        );
+        assert_eq!(
+            ArchiveKind::from_path(&PathBuf::from("main.zip")),
+            Some(ArchiveKind::Zip)
+        );
+        assert_eq!(ArchiveKind::from_path(&PathBuf::from("main.tar")), None);
+    }
+
+    #[test]
+    fn test_can_create_and_extract_tar_gz_archive() {
+        let folder = get_unique_local_tree_sitter_grammars_folder();
+        let content = "module.exports = {}";
+        std::fs::create_dir_all(folder.join("source/tree-sitter-test")).unwrap();
+        std::fs::write(folder.join("source/tree-sitter-test/grammar.js"), content).unwrap();
+
+        let archive_path = folder.join("test.tar.gz");
+        create_tar_gz(&folder.join("source"), &archive_path).unwrap();
+
+        let destination = folder.join("extracted");
+        extract_archive(&archive_path, &destination).unwrap();
+        assert_eq!(
+            read_to_string(destination.join("tree-sitter-test/grammar.js")).unwrap(),
+            content
+        );
+    }
+}
@@ -1,8 +1,15 @@
 use std::{
     env::current_dir,
+    fs::remove_dir_all,
+    io::Read,
     path::PathBuf,
-    process::{Command, Output},
-    sync::LazyLock,
+    process::{Command, Output, Stdio},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, LazyLock,
+    },
+    thread,
+    time::Duration,
 };
 
 use regex::Regex;
@@ -15,11 +22,24 @@ pub struct GitRepos {
 
 static GIT_CLONE_HTTPS_LINK_REGEX: LazyLock<Regex> = LazyLock::new(|| {
     // Note: the +? after repos name is important to be non greedy and not take a possible .git
-    // afterwards so the group 4 can be used as a way to extract the name
-    Regex::new(r#"^https://(([a-z0-9-]+\.)+[a-z0-9-]+)/([a-z0-9-]+)/([a-z0-9-_.]+?)(\.git)?$"#)
+    // afterwards so the "name" group can be used as a way to extract the name.
+    // The path can contain nested groups (like on GitLab) and the host can have a port.
+    Regex::new(r#"^https://(([a-z0-9-]+\.)+[a-z0-9-]+)(:[0-9]+)?/([A-Za-z0-9-_.]+/)+(?P<name>[A-Za-z0-9-_.]+?)(\.git)?$"#)
         .expect("GIT_CLONE_HTTPS_LINK_REGEX failed to compile")
 });
 
+static GIT_CLONE_SSH_LINK_REGEX: LazyLock<Regex> = LazyLock::new(|| {
+    // Support both the scp-like syntax "git@host:org/repo.git"
+    // and the URL syntax "ssh://git@host:2222/org/repo.git"
+    Regex::new(r#"^(ssh://[A-Za-z0-9-_.]+@(([a-z0-9-]+\.)*[a-z0-9-]+)(:[0-9]+)?/|[A-Za-z0-9-_.]+@(([a-z0-9-]+\.)*[a-z0-9-]+):)([A-Za-z0-9-_.]+/)+(?P<name>[A-Za-z0-9-_.]+?)(\.git)?$"#)
+        .expect("GIT_CLONE_SSH_LINK_REGEX failed to compile")
+});
+
+static GIT_CLONE_FILE_LINK_REGEX: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r#"^file://(/[^/]+)*/(?P<name>[^/]+?)(\.git)?/?$"#)
+        .expect("GIT_CLONE_FILE_LINK_REGEX failed to compile")
+});
+
 impl GitRepos {
     /// Just extract the path of the repository
     pub fn path(&self) -> &PathBuf {
@@ -47,7 +67,8 @@ impl GitRepos {
         })
     }
 
-    /// Get a git repository after cloning it, make sure the link is valid before hand
+    /// Get a git repository after cloning it, make sure the link is valid before hand.
+    /// The link can be an HTTPS, SSH or file:// URL or a path to a local repository
     /// We can define the --depth via only_latest_commits and the --single-branch argument for
     /// faster clones when we don't need the full history or all the branches
     pub fn from_clone(
@@ -56,8 +77,32 @@ impl GitRepos {
         only_latest_commits: Option<usize>,
         single_branch: bool,
     ) -> Result<Self, String> {
-        let mut args: Vec<String> = vec!["clone".to_owned(), git_clone_url.to_owned()];
-        let count_str = "";
+        Self::from_clone_with_progress(
+            git_clone_url,
+            base_directory,
+            only_latest_commits,
+            single_branch,
+            &|_| {},
+            &AtomicBool::new(false),
+        )
+    }
+
+    /// Same as from_clone() but on_progress receives each progress line written by git, and the
+    /// clone is stopped as soon as cancelled is true. The partial clone is removed in this case.
+    pub fn from_clone_with_progress(
+        git_clone_url: &str,
+        base_directory: &PathBuf,
+        only_latest_commits: Option<usize>,
+        single_branch: bool,
+        on_progress: &dyn Fn(&str),
+        cancelled: &AtomicBool,
+    ) -> Result<Self, String> {
+        let grammar_folder_name = Self::validate_and_extract_repos_name(git_clone_url)?;
+        let mut args: Vec<String> = vec![
+            "clone".to_owned(),
+            "--progress".to_owned(),
+            git_clone_url.to_owned(),
+        ];
         if let Some(count) = only_latest_commits {
             args.push("--depth".to_string());
             args.push(count.to_string())
@@ -68,13 +113,17 @@ impl GitRepos {
         // Note: we are forced to create a Vec<&str> afterwards because count.to_string() need to
         // be owned first to exist long enough
         let args_ref = args.iter().map(|e| e.as_ref()).collect();
-        let output = Self::run_git_cmd(&args_ref, base_directory)?;
-        let grammar_folder_name =
-            Self::validate_and_extract_repos_name_from_https_url(git_clone_url)?;
+        let destination = base_directory.join(&grammar_folder_name);
+        let existed_before = destination.exists();
+        let output =
+            Self::run_git_cmd_with_progress(&args_ref, base_directory, on_progress, cancelled)
+                .inspect_err(|_| {
+                    if !existed_before {
+                        let _ = remove_dir_all(&destination);
+                    }
+                })?;
         if output.status.success() {
-            Ok(GitRepos {
-                path: base_directory.join(grammar_folder_name),
-            })
+            Ok(GitRepos { path: destination })
         } else {
             Err(format!(
                 "Failed to git clone {}\n{}",
@@ -88,9 +137,27 @@ impl GitRepos {
     /// Try to pull a repository, only if is remote and return
     /// true if some commits were pulled, false if it was already up-to-date
     pub fn pull(&self) -> Result<bool, String> {
+        self.pull_with_progress(&|_| {}, &AtomicBool::new(false))
+    }
+
+    /// Same as pull() with progress lines and cancellation like from_clone_with_progress()
+    /// The repository is moved back to its previous revision if cancelled
+    pub fn pull_with_progress(
+        &self,
+        on_progress: &dyn Fn(&str),
+        cancelled: &AtomicBool,
+    ) -> Result<bool, String> {
         if self.is_remote().is_ok_and(|v| v) {
             let hash_before = self.get_last_commit_hash()?;
-            Self::run_git_cmd(&vec!["pull"], &self.path)?;
+            if let Err(e) = Self::run_git_cmd_with_progress(
+                &vec!["pull", "--progress"],
+                &self.path,
+                on_progress,
+                cancelled,
+            ) {
+                let _ = self.reset_hard(&hash_before);
+                return Err(e);
+            }
             let hash_after = self.get_last_commit_hash()?;
             Ok(hash_before != hash_after)
         } else {
@@ -98,8 +165,22 @@ impl GitRepos {
         }
     }
 
+    /// Move back the repository to the given revision by running: git reset --hard <revision>
+    pub fn reset_hard(&self, revision: &str) -> Result<(), String> {
+        let output = Self::run_git_cmd(&vec!["reset", "--hard", "--quiet", revision], &self.path)?;
+        if output.status.success() {
+            Ok(())
+        } else {
+            Err(format!(
+                "Failed to reset {:?} to {revision}\n{}",
+                self.path,
+                String::from_utf8(output.stderr).unwrap_or_default()
+            ))
+        }
+    }
+
     /// Get last commit hash by running: git rev-parse HEAD
-    fn get_last_commit_hash(&self) -> Result<String, String> {
+    pub fn get_last_commit_hash(&self) -> Result<String, String> {
         let output = Self::run_git_cmd(&vec!["rev-parse", "HEAD"], &self.path)?;
         Ok(String::from_utf8(output.stdout)
             .map_err(|e| e.to_string())?
@@ -107,6 +188,21 @@ impl GitRepos {
             .to_string())
     }
 
+    /// Get the URL of the origin remote
+    pub fn remote_url(&self) -> Result<String, String> {
+        let output = Self::run_git_cmd(&vec!["config", "--get", "remote.origin.url"], &self.path)?;
+        if !output.status.success() {
+            return Err(format!(
+                "The repository {:?} has no origin remote",
+                self.path
+            ));
+        }
+        Ok(String::from_utf8(output.stdout)
+            .map_err(|e| e.to_string())?
+            .trim()
+            .to_string())
+    }
+
     /// Check if the repository is a remote repository by checking if
     /// a remote.origin.url config entry exists
     pub fn is_remote(&self) -> Result<bool, String> {
@@ -117,13 +213,54 @@ impl GitRepos {
     /// Given a git clone link like "https://codeberg.org/samuelroland/productivity",
     /// make sure the link is valid and extract the name "productivity"
     pub fn validate_and_extract_repos_name_from_https_url(url: &str) -> Result<String, String> {
-        Ok(GIT_CLONE_HTTPS_LINK_REGEX
+        Self::extract_name_with_regex(&GIT_CLONE_HTTPS_LINK_REGEX, url)
+            .ok_or_else(|| "Given URL not a valid HTTPS git clone URL".to_string())
+    }
+
+    /// Given a git clone link like "git@gitlab.company.com:group/sub/productivity.git",
+    /// make sure the link is valid and extract the name "productivity"
+    pub fn validate_and_extract_repos_name_from_ssh_url(url: &str) -> Result<String, String> {
+        Self::extract_name_with_regex(&GIT_CLONE_SSH_LINK_REGEX, url)
+            .ok_or_else(|| "Given URL not a valid SSH git clone URL".to_string())
+    }
+
+    /// Given a git clone link like "file:///srv/mirrors/productivity.git",
+    /// make sure the link is valid and extract the name "productivity"
+    pub fn validate_and_extract_repos_name_from_file_url(url: &str) -> Result<String, String> {
+        Self::extract_name_with_regex(&GIT_CLONE_FILE_LINK_REGEX, url)
+            .ok_or_else(|| "Given URL not a valid file:// git clone URL".to_string())
+    }
+
+    /// Extract the name of the repository that `git clone` will create for any supported kind
+    /// of clone link: HTTPS, SSH, file:// URLs and paths to an existing local repository
+    pub fn validate_and_extract_repos_name(git_clone_url: &str) -> Result<String, String> {
+        if git_clone_url.starts_with("https://") {
+            Self::validate_and_extract_repos_name_from_https_url(git_clone_url)
+        } else if git_clone_url.starts_with("file://") {
+            Self::validate_and_extract_repos_name_from_file_url(git_clone_url)
+        } else if let Ok(name) = Self::validate_and_extract_repos_name_from_ssh_url(git_clone_url) {
+            Ok(name)
+        } else {
+            let path = PathBuf::from(git_clone_url);
+            if !path.join(".git").exists() && !path.join("HEAD").exists() {
+                return Err(format!(
+                    "Given link {git_clone_url} is not a valid git clone URL nor a local git repository"
+                ));
+            }
+            let name = path
+                .file_name()
+                .and_then(|n| n.to_str())
+                .ok_or_else(|| format!("Couldn't extract a repository name from {path:?}"))?;
+            Ok(name.strip_suffix(".git").unwrap_or(name).to_string())
+        }
+    }
+
+    /// Run the given regex on the url and take the "name" group
+    fn extract_name_with_regex(regex: &Regex, url: &str) -> Option<String> {
+        regex
             .captures(url)
-            .ok_or_else(|| "Given URL not a valid HTTPS git clone URL".to_string())?
-            .get(4)
-            .expect("Error, no group 4 in regex for GIT_CLONE_HTTPS_LINK_REGEX")
-            .as_str()
-            .to_string())
+            .and_then(|c| c.name("name"))
+            .map(|name| name.as_str().to_string())
     }
 
     /// Run a git commands with given args and exec_directory in which the command will be ran
@@ -138,6 +275,81 @@ impl GitRepos {
         cmd.map_err(|e| format!("Failed to run git {}: {e}", args.join(" ")))
     }
 
+    /// Run a git command like run_git_cmd() but call on_progress for each progress line written
+    /// by git on stderr, and kill the command if cancelled becomes true
+    pub(crate) fn run_git_cmd_with_progress(
+        args: &Vec<&str>,
+        exec_directory: &PathBuf,
+        on_progress: &dyn Fn(&str),
+        cancelled: &AtomicBool,
+    ) -> Result<Output, String> {
+        let mut child = Command::new("git")
+            .args(args)
+            .current_dir(exec_directory)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| format!("Failed to run git {}: {e}", args.join(" ")))?;
+
+        // Progress lines are updated in place with \r, so we split on both \r and \n
+        let mut stdout = child.stdout.take().expect("stdout is piped");
+        let mut stderr = child.stderr.take().expect("stderr is piped");
+        let (tx, rx) = mpsc::channel::<String>();
+        let stdout_reader = thread::spawn(move || {
+            let mut content = Vec::new();
+            let _ = stdout.read_to_end(&mut content);
+            content
+        });
+        let stderr_reader = thread::spawn(move || {
+            let mut content = Vec::new();
+            let mut buffer = [0u8; 1024];
+            let mut line = Vec::new();
+            while let Ok(count) = stderr.read(&mut buffer) {
+                if count == 0 {
+                    break;
+                }
+                for byte in &buffer[..count] {
+                    if *byte == b'\r' || *byte == b'\n' {
+                        if !line.is_empty() {
+                            let _ = tx.send(String::from_utf8_lossy(&line).to_string());
+                            line.clear();
+                        }
+                    } else {
+                        line.push(*byte);
+                    }
+                }
+                content.extend_from_slice(&buffer[..count]);
+            }
+            content
+        });
+
+        let status = loop {
+            for line in rx.try_iter() {
+                on_progress(&line);
+            }
+            if cancelled.load(Ordering::Relaxed) {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(format!("git {} has been cancelled", args.join(" ")));
+            }
+            match child.try_wait() {
+                Ok(Some(status)) => break status,
+                Ok(None) => thread::sleep(Duration::from_millis(20)),
+                Err(e) => return Err(format!("Failed to wait on git {}: {e}", args.join(" "))),
+            }
+        };
+        let stdout = stdout_reader.join().unwrap_or_default();
+        let stderr = stderr_reader.join().unwrap_or_default();
+        for line in rx.try_iter() {
+            on_progress(&line);
+        }
+        Ok(Output {
+            status,
+            stdout,
+            stderr,
+        })
+    }
+
     // Return true if Git is installed
     pub fn is_git_installed() -> bool {
         if let Ok(output) = Self::run_git_cmd(
@@ -285,6 +497,69 @@ mod tests {
         .is_err());
     }
 
+    #[test]
+    fn test_extract_repos_name_from_nested_ssh_and_file_urls() {
+        assert_eq!(
+            GitRepos::validate_and_extract_repos_name(
+                "https://git.company.internal:8443/tools/editors/grammars/tree-sitter-rust.git"
+            )
+            .unwrap(),
+            "tree-sitter-rust".to_string()
+        );
+        assert_eq!(
+            GitRepos::validate_and_extract_repos_name("git@github.com:samuelroland/cloneme.git")
+                .unwrap(),
+            "cloneme".to_string()
+        );
+        assert_eq!(
+            GitRepos::validate_and_extract_repos_name(
+                "ssh://git@gitlab.company.internal:2222/group/sub/tree-sitter-css.git"
+            )
+            .unwrap(),
+            "tree-sitter-css".to_string()
+        );
+        assert_eq!(
+            GitRepos::validate_and_extract_repos_name("file:///srv/mirrors/tree-sitter-c.git")
+                .unwrap(),
+            "tree-sitter-c".to_string()
+        );
+        assert!(GitRepos::validate_and_extract_repos_name("file://").is_err());
+        assert!(GitRepos::validate_and_extract_repos_name("git@github.com").is_err());
+        assert!(GitRepos::validate_and_extract_repos_name("/this/folder/does/not/exist").is_err());
+    }
+
+    #[test]
+    fn test_from_clone_with_local_repository_works_offline() {
+        let tests_folder = &get_unique_tests_subfolder();
+        let origin = tests_folder.join("origin-repos");
+        create_dir_all(&origin).unwrap();
+        GitRepos::run_git_cmd(&vec!["init", "--quiet"], &origin).unwrap();
+        std::fs::write(origin.join("grammar.js"), "module.exports = {}").unwrap();
+        GitRepos::run_git_cmd(&vec!["add", "."], &origin).unwrap();
+        GitRepos::run_git_cmd(
+            &vec![
+                "-c",
+                "user.name=dme",
+                "-c",
+                "user.email=dme@localhost",
+                "commit",
+                "--quiet",
+                "-m",
+                "init",
+            ],
+            &origin,
+        )
+        .unwrap();
+
+        let clones_folder = tests_folder.join("clones");
+        create_dir_all(&clones_folder).unwrap();
+        let repos =
+            GitRepos::from_clone(origin.to_str().unwrap(), &clones_folder, None, false).unwrap();
+        assert_eq!(clones_folder.join("origin-repos"), *repos.path());
+        assert!(repos.path().join("grammar.js").exists());
+        assert!(repos.is_remote().unwrap());
+    }
+
     #[test]
     fn test_is_git_installed() {
         // this would fail on a machine without Git, this is good as we need it for further testing
@@ -2,3 +2,4 @@ pub mod grammars;
 pub mod home;
 pub mod preview;
 pub mod search;
+pub mod themes;
@@ -1,9 +1,15 @@
-use std::collections::HashSet;
+use std::{collections::HashSet, sync::mpsc, thread};
 
 use dme_core::preview::{
-    proposed_grammars::PROPOSED_GRAMMAR_SOURCES, tree_sitter_grammars::TreeSitterGrammarsManager,
+    grammar_progress::{GrammarProgress, GrammarProgressSink},
+    grammar_update::GrammarUpdate,
+    proposed_grammars::PROPOSED_GRAMMAR_SOURCES,
+    tree_sitter_grammars::TreeSitterGrammarsManager,
 };
 use serde::Serialize;
+use tauri::{AppHandle, Emitter, Manager};
+
+use crate::AppData;
 
 #[derive(Serialize)]
 pub enum InstalledStatus {
@@ -43,16 +49,77 @@ pub async fn get_grammars_list() -> Result<Vec<GrammarState>, String> {
     Ok(result)
 }
 
+/// Sent as "grammar-progress" events during an installation
+#[derive(Serialize, Clone)]
+pub struct GrammarProgressEvent {
+    id: String,
+    progress: GrammarProgress,
+}
+
 #[tauri::command]
-pub async fn install_grammar(id: &str) -> Result<(), String> {
-    let mut manager = TreeSitterGrammarsManager::new()?;
+pub async fn install_grammar(app: AppHandle, id: String) -> Result<(), String> {
     let link = PROPOSED_GRAMMAR_SOURCES
-        .get(id)
+        .get(id.as_str())
         .ok_or(format!("No proposed grammar with id {id}"))?;
-    manager.install(link)?;
+    let state = app.state::<AppData>();
+    let (tx, rx) = mpsc::channel::<GrammarProgress>();
+    let progress = GrammarProgressSink::new(tx);
+    // Keep a clone of the sink so cancel_grammar_install() can cancel it
+    state
+        .grammar_installations
+        .lock()
+        .unwrap()
+        .insert(id.clone(), progress.clone());
+
+    // Forward the progress to the frontend until all senders are dropped
+    let app_for_events = app.clone();
+    let id_for_events = id.clone();
+    let forwarder = thread::spawn(move || {
+        for progress in rx {
+            let _ = app_for_events.emit(
+                "grammar-progress",
+                GrammarProgressEvent {
+                    id: id_for_events.clone(),
+                    progress,
+                },
+            );
+        }
+    });
+
+    let result = TreeSitterGrammarsManager::new()
+        .and_then(|mut manager| manager.install_with_progress(link, &progress));
+    state.grammar_installations.lock().unwrap().remove(&id);
+    drop(progress);
+    let _ = forwarder.join();
+    result.map(|_| ())
+}
+
+#[tauri::command]
+pub async fn cancel_grammar_install(app: AppHandle, id: String) -> Result<(), String> {
+    let state = app.state::<AppData>();
+    let installations = state.grammar_installations.lock().unwrap();
+    installations
+        .get(&id)
+        .ok_or(format!("No installation of {id} is running"))?
+        .cancel();
     Ok(())
 }
 
+#[tauri::command]
+pub async fn update_grammars() -> Result<Vec<GrammarUpdate>, String> {
+    let mut manager = TreeSitterGrammarsManager::new()?;
+    Ok(manager.update_all())
+}
+
+#[tauri::command]
+/// Approve the refused revision of a grammar, the one that changes its generated files, and
+/// update the grammar to it
+pub async fn approve_grammar_update(lang: String, revision: String) -> Result<(), String> {
+    let mut manager = TreeSitterGrammarsManager::new()?;
+    manager.approve_update(&lang, &revision)?;
+    manager.update(&lang).map(|_| ())
+}
+
 #[tauri::command]
 pub async fn remove_grammar(id: &str) -> Result<(), String> {
     let mut manager = TreeSitterGrammarsManager::new()?;
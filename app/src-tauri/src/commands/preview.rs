@@ -1,12 +1,30 @@
 use std::path::PathBuf;
 
-use dme_core::markdown_file_to_highlighted_html;
+use serde::Serialize;
+
+use dme_core::markdown_file_to_highlighted_html_with_options;
+use dme_core::preview::options::PreviewOptions;
+use dme_core::theming::catalog::{config_folder, DEFAULT_THEME_NAME};
+use dme_core::theming::document_style::{DocumentStyle, StyleError};
 use dme_core::preview::preview::ImageUrlRewriteMode;
 
+/// The rendered Markdown file, with the reasons why some parts couldn't be rendered normally
+#[derive(Serialize)]
+pub struct OpenedMarkdown {
+    html: String,
+    /// Human readable lines, like "3 code blocks in `lua` not highlighted: grammar not installed"
+    diagnostics: Vec<String>,
+}
+
 #[tauri::command]
 /// Open given Markdown file or the default one provided as argument
-/// or none otherwise
-pub async fn open_markdown_file(mut path: String) -> Result<Option<String>, String> {
+/// or none otherwise, with the given theme or the default one, the Markdown
+/// extensions in ~/.config/dme/preview.toml and the document style in
+/// ~/.config/dme/style.toml if they exist
+pub async fn open_markdown_file(
+    mut path: String,
+    theme: Option<String>,
+) -> Result<Option<OpenedMarkdown>, String> {
     if path.is_empty() {
         path = {
             let args: Vec<String> = std::env::args().collect();
@@ -28,11 +46,45 @@ pub async fn open_markdown_file(mut path: String) -> Result<Option<String>, Stri
             .unwrap_or_else(|| &pwd)
             .to_string_lossy()
             .to_string();
-        Ok(Some(
-            markdown_file_to_highlighted_html(&pathbuf)?
-                .set_image_rewrite(ImageUrlRewriteMode::TauriFullPath(parent_path))
-                .to_safe_html_string(),
-        ))
+        let theme = theme.as_deref().unwrap_or(DEFAULT_THEME_NAME);
+        // Invalid preview options don't block the preview, the default ones are used instead
+        let options_path = config_folder()?.join("preview.toml");
+        let (options, options_error) = match options_path.exists() {
+            true => match PreviewOptions::from_toml_file(&options_path) {
+                Ok(options) => (options, None),
+                Err(e) => (PreviewOptions::default(), Some(e)),
+            },
+            false => (PreviewOptions::default(), None),
+        };
+        let mut html = markdown_file_to_highlighted_html_with_options(&pathbuf, theme, &options)?
+            .set_image_rewrite(ImageUrlRewriteMode::TauriFullPath(parent_path));
+        if let Some(error) = options_error {
+            html.push_config_warning(format!("{error}, the default options are used"));
+        }
+        // An invalid document style doesn't block the preview, it is ignored with a warning
+        let mut style_errors: Vec<StyleError> = Vec::new();
+        let style_path = config_folder()?.join("style.toml");
+        if style_path.exists() {
+            // A parsed style is already validated, setting it cannot fail
+            match DocumentStyle::from_toml_file(&style_path) {
+                Ok(style) => {
+                    html = html
+                        .set_document_style(&style)
+                        .map_err(|_| format!("Invalid document style {style_path:?}"))?
+                }
+                Err(errors) => style_errors = errors,
+            }
+        }
+        let mut diagnostics = html.get_diagnostics_summary();
+        diagnostics.extend(html.get_warnings().iter().map(ToString::to_string));
+        diagnostics.extend_from_slice(html.get_config_warnings());
+        diagnostics.extend(style_errors.iter().map(|error| {
+            format!("The document style {style_path:?} is ignored, {error}")
+        }));
+        Ok(Some(OpenedMarkdown {
+            html: html.to_safe_html_string(),
+            diagnostics,
+        }))
     } else {
         Err(format!("File {path} doesn't exist !").to_string())
     }
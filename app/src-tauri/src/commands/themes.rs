@@ -0,0 +1,18 @@
+use dme_core::theming::catalog::{ThemeCatalog, ThemeInfo, DEFAULT_THEME_NAME};
+use dme_core::theming::contrast::{ContrastLevel, ThemeAudit};
+use dme_core::theming::helix::ALL_HIGHLIGHT_NAMES_SUPPORTED_BY_HELIX;
+
+#[tauri::command]
+/// List the bundled themes and the themes of the user, to pick one for the preview
+pub async fn get_themes_list() -> Result<Vec<ThemeInfo>, String> {
+    Ok(ThemeCatalog::new()?.list())
+}
+
+#[tauri::command]
+/// Check the contrast of the colours of a theme or the default one, with suggestions for the
+/// ones below AA
+pub async fn audit_theme(name: Option<String>) -> Result<ThemeAudit, String> {
+    let name = name.as_deref().unwrap_or(DEFAULT_THEME_NAME);
+    let theme = ThemeCatalog::new()?.load(name, ALL_HIGHLIGHT_NAMES_SUPPORTED_BY_HELIX)?;
+    Ok(ThemeAudit::audit(&theme, Some(ContrastLevel::Aa)))
+}
@@ -2,12 +2,20 @@ mod commands;
 
 use crate::commands::grammars::get_grammars_list;
 use crate::commands::search::run_search;
-use std::sync::{mpsc::Receiver, Mutex};
+use dme_core::preview::grammar_progress::GrammarProgressSink;
+use std::{
+    collections::HashMap,
+    sync::{mpsc::Receiver, Mutex},
+};
 
 use commands::{
-    grammars::{grammars_folder, install_grammar, remove_grammar},
+    grammars::{
+        approve_grammar_update, cancel_grammar_install, grammars_folder, install_grammar,
+        remove_grammar, update_grammars,
+    },
     home::get_app_info,
     preview::open_markdown_file,
+    themes::{audit_theme, get_themes_list},
 };
 use dme_core::search::{disk::DiskResearcher, search::ResearchResult};
 use tauri::Manager;
@@ -15,6 +23,8 @@ use tauri::Manager;
 struct AppData {
     disk_researcher: Mutex<DiskResearcher>,
     search_stream_receiver: Mutex<Option<Receiver<ResearchResult>>>,
+    /// The progress sinks of running grammar installations by grammar id, to cancel them
+    grammar_installations: Mutex<HashMap<String, GrammarProgressSink>>,
 }
 
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
@@ -27,8 +37,13 @@ pub fn run() {
             open_markdown_file,
             get_grammars_list,
             install_grammar,
+            cancel_grammar_install,
             remove_grammar,
-            grammars_folder
+            update_grammars,
+            approve_grammar_update,
+            grammars_folder,
+            get_themes_list,
+            audit_theme
         ])
         .setup(|app| {
             let home_dir = etcetera::home_dir()
@@ -40,6 +55,7 @@ pub fn run() {
             app.manage(AppData {
                 disk_researcher: Mutex::new(disk_researcher),
                 search_stream_receiver: Mutex::new(None),
+                grammar_installations: Mutex::new(HashMap::new()),
             });
             Ok(())
         })
@@ -9,3 +9,59 @@ export type AppInfo = {
 };
 
 export type MenuEntry = { action: string; icon: string; keymap: string };
+
+// See grammar_progress.rs in core library
+export type GrammarProgress =
+  | { phase: "resolving"; source: string }
+  | { phase: "cloning"; message: string; percent: number | null }
+  | { phase: "pulling"; message: string; percent: number | null }
+  | { phase: "compiling" }
+  | { phase: "loading-queries" }
+  | { phase: "done" }
+  | { phase: "failed"; error: string }
+  | { phase: "cancelled" };
+
+export type GrammarProgressEvent = {
+  id: string;
+  progress: GrammarProgress;
+};
+
+// See grammar_update.rs in core library
+export type GrammarUpdate = {
+  lang: string;
+  previous_revision: string | null;
+  revision: string | null;
+  outcome:
+    | { outcome: "up-to-date" }
+    | { outcome: "updated" }
+    | { outcome: "refused"; reason: string }
+    | { outcome: "rolled-back"; reason: string }
+    | { outcome: "failed"; reason: string };
+};
+
+// See catalog.rs in core library
+export type ThemeInfo = {
+  name: string;
+  appearance: "light" | "dark";
+  source: "bundled" | "user";
+};
+
+// See contrast.rs in core library
+export type ContrastCheck = {
+  name: string;
+  color: string;
+  background: string;
+  ratio: number;
+  level: "Fail" | "AA" | "AAA";
+  suggestion: string | null;
+};
+
+export type ThemeAudit = {
+  checks: ContrastCheck[];
+};
+
+// See commands/preview.rs in the Tauri app
+export type OpenedMarkdown = {
+  html: string;
+  diagnostics: string[];
+};